@@ -0,0 +1,217 @@
+//! Actor style handles to a WebSocket.
+//!
+//! [`WebSocketActor::spawn`] moves a [`WebSocket`] into its own task and returns a [`WsHandle`]
+//! that talks to it over a command channel, along with a receiver for its events. Handles are
+//! cheap to clone, `Send`, and `Sync`, so they fit architectures where connections are addressed
+//! through handles rather than owned.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::response::Response;
+//! use axum_tungstenite::{
+//!     actor::{ActorEvent, WebSocketActor},
+//!     WebSocketUpgrade,
+//! };
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|socket| async move {
+//!         let (handle, mut events) = WebSocketActor::spawn(socket);
+//!
+//!         while let Ok(event) = events.recv().await {
+//!             match event {
+//!                 ActorEvent::Message(msg) => {
+//!                     let _ = handle.send(msg).await;
+//!                 }
+//!                 ActorEvent::Closed => break,
+//!                 _ => {}
+//!             }
+//!         }
+//!     })
+//! }
+//! ```
+
+use crate::{Error, Message, WebSocket};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+const COMMAND_CAPACITY: usize = 32;
+const EVENT_CAPACITY: usize = 64;
+
+/// Spawns [`WebSocket`]s into actor tasks.
+///
+/// See the [module docs](self) for more details.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct WebSocketActor;
+
+impl WebSocketActor {
+    /// Spawn a task that owns `socket` and return a handle to it, along with a receiver of all
+    /// its events.
+    ///
+    /// The task ends, closing the socket, when the socket closes, when [`WsHandle::close`] is
+    /// called, or when all handles have been dropped. Receivers then get [`ActorEvent::Closed`]
+    /// followed by [`RecvError::Closed`](broadcast::error::RecvError::Closed).
+    pub fn spawn(socket: WebSocket) -> (WsHandle, broadcast::Receiver<ActorEvent>) {
+        let (commands_tx, commands_rx) = mpsc::channel(COMMAND_CAPACITY);
+        // subscribe before spawning, so no event is missed
+        let (events_tx, events_rx) = broadcast::channel(EVENT_CAPACITY);
+        let events = Arc::new(Mutex::new(Some(events_tx.clone())));
+
+        tokio::spawn(run(socket, commands_rx, events_tx, events.clone()));
+
+        let handle = WsHandle {
+            commands: commands_tx,
+            events,
+        };
+        (handle, events_rx)
+    }
+}
+
+/// A handle to a [`WebSocket`] running in an actor task.
+#[derive(Debug, Clone)]
+pub struct WsHandle {
+    commands: mpsc::Sender<ActorCommand>,
+    /// Taken by the actor when it stops, so receivers see the channel close.
+    events: Arc<Mutex<Option<broadcast::Sender<ActorEvent>>>>,
+}
+
+impl WsHandle {
+    /// Send a message and wait for it to be written.
+    ///
+    /// Fails with [`Error::AlreadyClosed`] if the actor has stopped.
+    pub async fn send(&self, msg: Message) -> Result<(), Error> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.command(ActorCommand::Send(msg, reply_tx)).await?;
+        reply_rx.await.map_err(|_| Error::AlreadyClosed)?
+    }
+
+    /// Gracefully close the socket and stop the actor.
+    ///
+    /// Fails with [`Error::AlreadyClosed`] if the actor has already stopped.
+    pub async fn close(&self) -> Result<(), Error> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.command(ActorCommand::Close(reply_tx)).await?;
+        reply_rx.await.map_err(|_| Error::AlreadyClosed)?
+    }
+
+    /// Returns `true` while the actor is running.
+    pub fn is_alive(&self) -> bool {
+        !self.commands.is_closed()
+    }
+
+    /// Subscribe to the events of the socket.
+    ///
+    /// Only events that happen after subscribing are received, use the receiver returned by
+    /// [`WebSocketActor::spawn`] to see all of them. Subscribers that fall more than 64 events
+    /// behind miss the oldest ones, see [`broadcast::Receiver::recv`]. If the actor has already
+    /// stopped, the receiver is closed.
+    pub fn subscribe(&self) -> broadcast::Receiver<ActorEvent> {
+        match &*self.events.lock().unwrap() {
+            Some(events) => events.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+
+    async fn command(&self, command: ActorCommand) -> Result<(), Error> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| Error::AlreadyClosed)
+    }
+}
+
+/// An event emitted by an actor.
+///
+/// Received through the receiver returned by [`WebSocketActor::spawn`] or
+/// [`WsHandle::subscribe`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ActorEvent {
+    /// A message was received.
+    Message(Message),
+    /// Receiving failed. The actor stops afterwards.
+    Error(Arc<Error>),
+    /// The actor stopped. This is always the last event.
+    Closed,
+}
+
+#[derive(Debug)]
+enum ActorCommand {
+    Send(Message, oneshot::Sender<Result<(), Error>>),
+    Close(oneshot::Sender<Result<(), Error>>),
+}
+
+async fn run(
+    mut socket: WebSocket,
+    mut commands: mpsc::Receiver<ActorCommand>,
+    events: broadcast::Sender<ActorEvent>,
+    shared_events: Arc<Mutex<Option<broadcast::Sender<ActorEvent>>>>,
+) {
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(ActorCommand::Send(msg, reply)) => {
+                    let _ = reply.send(socket.send(msg).await);
+                }
+                Some(ActorCommand::Close(reply)) => {
+                    let _ = reply.send(socket.close().await);
+                    break;
+                }
+                None => {
+                    let _ = socket.close().await;
+                    break;
+                }
+            },
+            msg = socket.recv() => match msg {
+                Some(Ok(msg)) => {
+                    let _ = events.send(ActorEvent::Message(msg));
+                }
+                Some(Err(err)) => {
+                    let _ = events.send(ActorEvent::Error(Arc::new(err)));
+                    break;
+                }
+                None => break,
+            },
+        }
+    }
+
+    let _ = events.send(ActorEvent::Closed);
+    shared_events.lock().unwrap().take();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_helpers, WebSocketUpgrade};
+    use axum::{routing::get, Router};
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn client_closing_right_away_ends_the_event_stream() {
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(move |socket| async move {
+                    let (handle, mut events) = WebSocketActor::spawn(socket);
+                    let mut closed = false;
+                    while let Ok(event) = events.recv().await {
+                        closed = matches!(event, ActorEvent::Closed);
+                    }
+                    assert!(handle.subscribe().recv().await.is_err());
+                    done_tx.send(closed).unwrap();
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 1024).await;
+        client.close(None).await.unwrap();
+
+        let closed = timeout(Duration::from_secs(1), done_rx.recv())
+            .await
+            .expect("event stream didn't end")
+            .unwrap();
+        assert!(closed, "`Closed` wasn't the last event");
+    }
+}
@@ -0,0 +1,270 @@
+//! Authenticating upgrades with a token in the query string.
+//!
+//! Browsers can't set an `Authorization` header when opening a WebSocket, so a common pattern is
+//! to pass a short lived token as a query parameter, as in `/ws?token=...`.
+//! [`AuthenticatedWebSocketUpgrade`] is an extractor that verifies such a token before the
+//! connection is upgraded and rejects the request with `401 Unauthorized` if it's missing or
+//! invalid.
+//!
+//! The parameter name and verifier are configured with a [`QueryTokenAuth`], added to the
+//! router as an [`Extension`](axum::Extension).
+//!
+//! # Example
+//!
+//! ```
+//! use axum::{routing::get, Extension, Router};
+//! use axum_core::response::Response;
+//! use axum_tungstenite::auth::{AuthenticatedWebSocketUpgrade, QueryTokenAuth};
+//!
+//! #[derive(Clone)]
+//! struct User {
+//!     name: String,
+//! }
+//!
+//! async fn verify(token: String) -> Option<User> {
+//!     // look up the token in a database, verify a signature, ...
+//!     # None
+//! }
+//!
+//! async fn handler(ws: AuthenticatedWebSocketUpgrade<User>) -> Response {
+//!     ws.on_upgrade(|socket, user| async move {
+//!         // `user` is the value returned by `verify`
+//!     })
+//! }
+//!
+//! let app = Router::new()
+//!     .route("/ws", get(handler))
+//!     .layer(Extension(QueryTokenAuth::new("token", verify)));
+//! # let _: Router = app;
+//! ```
+
+use crate::{
+    rejection::{
+        AuthenticatedWebSocketUpgradeRejection, InvalidToken, MissingQueryTokenAuth, MissingToken,
+    },
+    AuthorizedUpgrade, CallbackResult, WebSocket, WebSocketUpgrade,
+};
+use async_trait::async_trait;
+use axum_core::{extract::FromRequestParts, response::Response};
+use futures_util::future::{BoxFuture, FutureExt};
+use http::request::Parts;
+use std::{borrow::Cow, fmt, future::Future, sync::Arc};
+
+/// Configuration for [`AuthenticatedWebSocketUpgrade`].
+///
+/// See the [module docs](self) for an example.
+pub struct QueryTokenAuth<T> {
+    param: Cow<'static, str>,
+    verify: Arc<dyn Fn(String) -> BoxFuture<'static, Option<T>> + Send + Sync>,
+}
+
+impl<T> QueryTokenAuth<T> {
+    /// Read the token from the query parameter `param` and verify it with `verify`.
+    ///
+    /// `verify` returns `None` to reject the token.
+    pub fn new<F, Fut>(param: impl Into<Cow<'static, str>>, verify: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<T>> + Send + 'static,
+    {
+        Self {
+            param: param.into(),
+            verify: Arc::new(move |token| verify(token).boxed()),
+        }
+    }
+
+    fn token(&self, parts: &Parts) -> Option<String> {
+        let query = parts.uri.query()?;
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| *name == self.param)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+impl<T> Clone for QueryTokenAuth<T> {
+    fn clone(&self) -> Self {
+        Self {
+            param: self.param.clone(),
+            verify: self.verify.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for QueryTokenAuth<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryTokenAuth")
+            .field("param", &self.param)
+            .finish()
+    }
+}
+
+/// Extractor for WebSocket connections authenticated with a token in the query string.
+///
+/// Requires a [`QueryTokenAuth<T>`] request extension. The request is rejected with
+/// `401 Unauthorized` if the token is missing or the verifier rejects it. See the
+/// [module docs](self) for an example.
+#[derive(Debug)]
+pub struct AuthenticatedWebSocketUpgrade<T> {
+    upgrade: AuthorizedUpgrade<T>,
+}
+
+impl<T> AuthenticatedWebSocketUpgrade<T> {
+    /// The value produced by verifying the token.
+    pub fn value(&self) -> &T {
+        self.upgrade.value()
+    }
+
+    /// Finalize upgrading the connection and call the provided callback with the stream and the
+    /// value produced by verifying the token.
+    ///
+    /// See [`WebSocketUpgrade::on_upgrade`] for more details.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
+    where
+        F: FnOnce(WebSocket, T) -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: CallbackResult,
+        T: Send + 'static,
+    {
+        self.upgrade.on_upgrade(callback)
+    }
+
+    /// Get the upgrade and the value produced by verifying the token.
+    ///
+    /// Useful for configuring the upgrade before finalizing it.
+    pub fn into_parts(self) -> (WebSocketUpgrade, T) {
+        self.upgrade.into_parts()
+    }
+}
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for AuthenticatedWebSocketUpgrade<T>
+where
+    S: Sync,
+    T: Send + 'static,
+{
+    type Rejection = AuthenticatedWebSocketUpgradeRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let upgrade = WebSocketUpgrade::from_request_head(parts)?;
+        let auth = parts
+            .extensions
+            .get::<QueryTokenAuth<T>>()
+            .cloned()
+            .ok_or(MissingQueryTokenAuth)?;
+        let token = auth.token(parts).ok_or(MissingToken)?;
+        let value = (auth.verify)(token).await.ok_or(InvalidToken)?;
+
+        Ok(Self {
+            upgrade: AuthorizedUpgrade { upgrade, value },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rejection::{HandleRejectionLayer, OnRejectLayer, WebSocketUpgradeRejection},
+        test_helpers::{call, upgrade_headers},
+    };
+    use axum::{body::Body, response::IntoResponse, routing::get, Extension, Router};
+    use http::{Request, StatusCode};
+    use std::sync::Mutex;
+
+    fn app() -> Router {
+        async fn handler(ws: AuthenticatedWebSocketUpgrade<String>) -> Response {
+            ws.on_upgrade(|_, _| async {})
+        }
+
+        Router::new()
+            .route("/ws", get(handler))
+            .layer(Extension(QueryTokenAuth::new(
+                "token",
+                |token: String| async move { (token == "secret").then_some(token) },
+            )))
+    }
+
+    fn upgrade_request(uri: &str) -> Request<Body> {
+        let mut req = Request::get(uri).body(Body::empty()).unwrap();
+        *req.headers_mut() = upgrade_headers();
+        let on_upgrade = hyper::upgrade::on(Request::new(()));
+        req.extensions_mut().insert(on_upgrade);
+        req
+    }
+
+    #[tokio::test]
+    async fn handle_rejection_replaces_failed_handshakes() {
+        let app = app().layer(HandleRejectionLayer::new(
+            |rejection: WebSocketUpgradeRejection| {
+                assert!(matches!(
+                    rejection,
+                    WebSocketUpgradeRejection::InvalidConnectionHeader(_)
+                ));
+                StatusCode::IM_A_TEAPOT.into_response()
+            },
+        ));
+        let req = Request::get("/ws?token=secret")
+            .body(Body::empty())
+            .unwrap();
+
+        let res = call(app, req).await;
+
+        assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+    }
+
+    #[tokio::test]
+    async fn handle_rejection_replaces_failed_authentication() {
+        let app = app().layer(HandleRejectionLayer::new(
+            |rejection: AuthenticatedWebSocketUpgradeRejection| match rejection {
+                AuthenticatedWebSocketUpgradeRejection::MissingToken(_) => {
+                    StatusCode::IM_A_TEAPOT.into_response()
+                }
+                rejection => rejection.into_response(),
+            },
+        ));
+
+        let res = call(app.clone(), upgrade_request("/ws")).await;
+        assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+
+        let res = call(app, upgrade_request("/ws?token=wrong")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn handlers_for_plain_rejections_keep_authentication_responses() {
+        let app = app().layer(HandleRejectionLayer::new(|_: WebSocketUpgradeRejection| {
+            StatusCode::IM_A_TEAPOT.into_response()
+        }));
+
+        let res = call(app, upgrade_request("/ws?token=wrong")).await;
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn on_reject_sees_both_kinds_of_rejections() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let app = app()
+            .layer(OnRejectLayer::new({
+                let seen = seen.clone();
+                move |rejection: &AuthenticatedWebSocketUpgradeRejection, _: &Parts| {
+                    seen.lock().unwrap().push(rejection.status());
+                }
+            }))
+            .layer(HandleRejectionLayer::new(|_: WebSocketUpgradeRejection| {
+                StatusCode::IM_A_TEAPOT.into_response()
+            }));
+
+        let req = Request::get("/ws").body(Body::empty()).unwrap();
+        let res = call(app.clone(), req).await;
+        assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+
+        let res = call(app, upgrade_request("/ws")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [StatusCode::BAD_REQUEST, StatusCode::UNAUTHORIZED]
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Opening WebSocket connections to other servers.
+//!
+//! Connections opened with [`connect`] are regular [`WebSocket`]s, the same type handlers get
+//! from [`WebSocketUpgrade::on_upgrade`](crate::WebSocketUpgrade::on_upgrade). Services that
+//! both accept and originate connections, like gateways, can use the same message, error, and
+//! socket types for both.
+//!
+//! [`connect`] only supports `ws://` URLs. For `wss://`, establish the TLS connection with the
+//! TLS library of your choice and pass it to [`connect_with_io`].
+//!
+//! Requires the `client` feature.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use axum_tungstenite::{client, Message};
+//! use futures_util::{SinkExt, StreamExt};
+//! use http::{header, HeaderMap, HeaderValue};
+//!
+//! # async fn run() -> Result<(), axum_tungstenite::Error> {
+//! let mut headers = HeaderMap::new();
+//! headers.insert(
+//!     header::AUTHORIZATION,
+//!     HeaderValue::from_static("Bearer secret"),
+//! );
+//!
+//! let mut socket = client::connect("ws://upstream.internal/feed", headers).await?;
+//! socket.send(Message::Text("subscribe".into())).await?;
+//!
+//! while let Some(msg) = socket.next().await {
+//!     let msg = msg?;
+//!     // ...
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{handshake, ConnectionId, Telemetry, WebSocket};
+use http::{
+    header::{self, HeaderMap, HeaderValue},
+    Request, Response, StatusCode, Uri,
+};
+use hyper::Body;
+use std::io;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        error::{Error, ProtocolError, UrlError},
+        handshake::client::generate_key,
+        protocol::Role,
+    },
+    WebSocketStream,
+};
+
+/// Connect to the WebSocket server at `url` over TCP.
+///
+/// `headers` are sent with the upgrade request, for example to authenticate or to offer
+/// subprotocols with `Sec-WebSocket-Protocol`. The headers required by the handshake are added
+/// automatically.
+///
+/// Fails with [`UrlError::TlsFeatureNotEnabled`] for `wss://` URLs, use [`connect_with_io`]
+/// for those. If the server responds with anything other than `101 Switching Protocols` this
+/// fails with [`Error::Http`].
+///
+/// See the [module docs](self) for an example.
+#[allow(clippy::result_large_err)]
+pub async fn connect<U>(url: U, headers: HeaderMap) -> Result<WebSocket, Error>
+where
+    U: TryInto<Uri>,
+    U::Error: Into<http::Error>,
+{
+    let uri = parse_uri(url)?;
+    let port = match uri.scheme_str() {
+        Some("ws") => uri.port_u16().unwrap_or(80),
+        Some("wss") => return Err(Error::Url(UrlError::TlsFeatureNotEnabled)),
+        _ => return Err(Error::Url(UrlError::UnsupportedUrlScheme)),
+    };
+    let host = uri.host().ok_or(Error::Url(UrlError::NoHostName))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(Error::Url(UrlError::EmptyHostName));
+    }
+
+    let stream = TcpStream::connect((host, port)).await?;
+    let peer_addr = stream.peer_addr().ok();
+    let mut socket = connect_with_io(stream, uri, headers).await?;
+    socket.peer_addr = peer_addr;
+    Ok(socket)
+}
+
+/// Perform the WebSocket handshake for `url` over an already established connection.
+///
+/// `io` can be any transport, such as a TLS stream or a connection going through a proxy.
+/// The scheme and authority of `url` are only used for the `Host` header. Otherwise this
+/// behaves like [`connect`].
+#[allow(clippy::result_large_err)]
+pub async fn connect_with_io<I, U>(
+    io: I,
+    url: U,
+    mut headers: HeaderMap,
+) -> Result<WebSocket, Error>
+where
+    I: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    U: TryInto<Uri>,
+    U::Error: Into<http::Error>,
+{
+    let uri = parse_uri(url)?;
+    let key = generate_key();
+
+    if !headers.contains_key(header::HOST) {
+        let authority = uri.authority().ok_or(Error::Url(UrlError::NoHostName))?;
+        headers.insert(header::HOST, HeaderValue::from_str(authority.as_str())?);
+    }
+    headers.insert(header::CONNECTION, HeaderValue::from_static("Upgrade"));
+    headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+    headers.insert(
+        header::SEC_WEBSOCKET_VERSION,
+        HeaderValue::from_static("13"),
+    );
+    headers.insert(header::SEC_WEBSOCKET_KEY, HeaderValue::from_str(&key)?);
+
+    let offered = offered_protocols(&headers);
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let mut request = Request::get(path).body(Body::empty())?;
+    *request.headers_mut() = headers;
+
+    let (mut sender, connection) = hyper::client::conn::handshake(io)
+        .await
+        .map_err(io::Error::other)?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    let mut response = sender
+        .send_request(request)
+        .await
+        .map_err(io::Error::other)?;
+
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        let (parts, _) = response.into_parts();
+        return Err(Error::Http(Response::from_parts(parts, None)));
+    }
+    let protocol = check_response(response.headers(), &key, &offered)?;
+    let extensions = handshake::parse_extensions(response.headers());
+
+    let upgraded = hyper::upgrade::on(&mut response)
+        .await
+        .map_err(io::Error::other)?;
+    let inner = WebSocketStream::from_raw_socket(upgraded, Role::Client, None).await;
+
+    Ok(WebSocket::new(
+        inner,
+        protocol,
+        extensions,
+        Telemetry::new(ConnectionId::new(), None),
+    ))
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_uri<U>(url: U) -> Result<Uri, Error>
+where
+    U: TryInto<Uri>,
+    U::Error: Into<http::Error>,
+{
+    url.try_into().map_err(|err| Error::HttpFormat(err.into()))
+}
+
+/// Validate the `101` response and return the subprotocol selected by the server.
+#[allow(clippy::result_large_err)]
+fn check_response(
+    headers: &HeaderMap,
+    key: &str,
+    offered: &[String],
+) -> Result<Option<HeaderValue>, Error> {
+    if !handshake::header_contains(headers, header::UPGRADE, "websocket") {
+        return Err(Error::Protocol(
+            ProtocolError::MissingUpgradeWebSocketHeader,
+        ));
+    }
+    if !handshake::header_contains(headers, header::CONNECTION, "upgrade") {
+        return Err(Error::Protocol(
+            ProtocolError::MissingConnectionUpgradeHeader,
+        ));
+    }
+    if headers.get(header::SEC_WEBSOCKET_ACCEPT) != Some(&handshake::accept_key(key.as_bytes())) {
+        return Err(Error::Protocol(
+            ProtocolError::SecWebSocketAcceptKeyMismatch,
+        ));
+    }
+
+    let Some(protocol) = headers.get(header::SEC_WEBSOCKET_PROTOCOL) else {
+        return Ok(None);
+    };
+    let selected = protocol.to_str().ok().map(str::trim);
+    if selected.is_some_and(|selected| offered.iter().any(|offered| offered == selected)) {
+        Ok(Some(protocol.clone()))
+    } else {
+        Err(Error::Protocol(ProtocolError::InvalidHeader(
+            header::SEC_WEBSOCKET_PROTOCOL,
+        )))
+    }
+}
+
+fn offered_protocols(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|protocol| protocol.trim().to_owned())
+        .collect()
+}
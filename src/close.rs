@@ -0,0 +1,305 @@
+#[cfg(feature = "tracing")]
+use crate::{
+    message_log::{MessageLog, MessageLogger},
+    transcript::Direction,
+};
+use crate::{
+    transcript::Recorder, CloseCode, CloseFrame, ConnectionId, Message, PendingWrites, SocketStats,
+};
+use std::{fmt, sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+/// Information about a connection that has ended, passed to the callback given to
+/// [`WebSocketUpgrade::on_close`](crate::WebSocketUpgrade::on_close).
+#[derive(Debug, Clone)]
+pub struct CloseInfo {
+    connection_id: ConnectionId,
+    close_frame: Option<CloseFrame<'static>>,
+    error: Option<String>,
+    duration: Duration,
+    stats: SocketStats,
+}
+
+impl CloseInfo {
+    /// The ID of the connection.
+    pub fn connection_id(&self) -> ConnectionId {
+        self.connection_id
+    }
+
+    /// The first close frame sent or received on the connection, if any.
+    ///
+    /// `None` if the connection ended without a closing handshake or the close frame didn't
+    /// carry a code.
+    pub fn close_frame(&self) -> Option<&CloseFrame<'static>> {
+        self.close_frame.as_ref()
+    }
+
+    /// The code of the first close frame sent or received on the connection, if any.
+    pub fn close_code(&self) -> Option<CloseCode> {
+        self.close_frame.as_ref().map(|frame| frame.code)
+    }
+
+    /// The error that ended the connection, if any.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// How long the connection was open.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The final traffic counters of the connection.
+    pub fn stats(&self) -> SocketStats {
+        self.stats
+    }
+}
+
+/// The state of a [`WebSocket`](crate::WebSocket)'s connection.
+///
+/// Returned by [`WebSocket::state`](crate::WebSocket::state).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectionState {
+    /// Messages can be sent and received.
+    Open,
+    /// The server sent a `Close` frame and is waiting for the client to acknowledge it.
+    CloseSent,
+    /// The client sent a `Close` frame. The reply is sent automatically the next time the
+    /// socket is flushed.
+    CloseReceived,
+    /// The closing handshake completed, the connection failed, or the socket gave up on it
+    /// after a timeout or exceeded quota.
+    Closed,
+}
+
+#[derive(Clone)]
+pub(crate) struct OnClose(pub(crate) Arc<dyn Fn(CloseInfo) + Send + Sync>);
+
+impl fmt::Debug for OnClose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OnClose").finish()
+    }
+}
+
+/// Tracks what happens on a socket and calls the `on_close` callback when dropped.
+#[derive(Debug)]
+pub(crate) struct Telemetry {
+    pub(crate) connection_id: ConnectionId,
+    pub(crate) stats: SocketStats,
+    /// Messages written since the last successful flush.
+    pub(crate) unflushed: PendingWrites,
+    started: Instant,
+    close_frame: Option<CloseFrame<'static>>,
+    error: Option<String>,
+    on_close: Option<OnClose>,
+    pub(crate) recorder: Option<Recorder>,
+    #[cfg(feature = "tracing")]
+    logger: Option<MessageLogger>,
+    close_sent: bool,
+    close_received: bool,
+    /// Set once reading from the connection ended or failed.
+    ended: bool,
+    pub(crate) last_received_at: Option<Instant>,
+    pub(crate) last_sent_at: Option<Instant>,
+}
+
+impl Telemetry {
+    pub(crate) fn new(connection_id: ConnectionId, on_close: Option<OnClose>) -> Self {
+        Self {
+            connection_id,
+            stats: SocketStats::default(),
+            unflushed: PendingWrites::default(),
+            started: Instant::now(),
+            close_frame: None,
+            error: None,
+            on_close,
+            recorder: None,
+            #[cfg(feature = "tracing")]
+            logger: None,
+            close_sent: false,
+            close_received: false,
+            ended: false,
+            last_received_at: None,
+            last_sent_at: None,
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn with_message_log(mut self, config: Option<MessageLog>) -> Self {
+        self.logger = config.map(|config| MessageLogger::new(config, self.connection_id));
+        self
+    }
+
+    pub(crate) fn record_received(&mut self, msg: &Message) {
+        self.stats.record_received(msg);
+        self.close_received |= matches!(msg, Message::Close(_));
+        self.last_received_at = Some(Instant::now());
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_received(self.started.elapsed(), msg);
+        }
+        #[cfg(feature = "tracing")]
+        if let Some(logger) = &mut self.logger {
+            logger.log(Direction::Inbound, msg);
+        }
+    }
+
+    pub(crate) fn record_sent(&mut self, msg: &Message) {
+        self.stats.record_sent(msg);
+        self.unflushed.record_sent(msg);
+        self.close_sent |= matches!(msg, Message::Close(_));
+        self.last_sent_at = Some(Instant::now());
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_sent(self.started.elapsed(), msg);
+        }
+        #[cfg(feature = "tracing")]
+        if let Some(logger) = &mut self.logger {
+            logger.log(Direction::Outbound, msg);
+        }
+    }
+
+    pub(crate) fn record_ended(&mut self) {
+        self.ended = true;
+    }
+
+    pub(crate) fn state(&self) -> ConnectionState {
+        match (self.close_sent, self.close_received) {
+            _ if self.ended => ConnectionState::Closed,
+            (true, true) => ConnectionState::Closed,
+            (true, false) => ConnectionState::CloseSent,
+            (false, true) => ConnectionState::CloseReceived,
+            (false, false) => ConnectionState::Open,
+        }
+    }
+
+    pub(crate) fn record_flushed(&mut self) {
+        self.unflushed = PendingWrites::default();
+    }
+
+    pub(crate) fn record_close(&mut self, msg: &Message) {
+        if let Message::Close(Some(frame)) = msg {
+            self.close_frame
+                .get_or_insert_with(|| frame.clone().into_owned());
+        }
+    }
+
+    pub(crate) fn record_error(&mut self, error: &dyn fmt::Display) {
+        self.error.get_or_insert_with(|| error.to_string());
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(OnClose(on_close)) = self.on_close.take() {
+            on_close(CloseInfo {
+                connection_id: self.connection_id,
+                close_frame: self.close_frame.take(),
+                error: self.error.take(),
+                duration: self.started.elapsed(),
+                stats: self.stats,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_helpers, Error, Timeout, WebSocket, WebSocketUpgrade};
+    use axum::{routing::get, Router};
+    use std::future::Future;
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::WebSocketStream;
+
+    /// Run `callback` on a server socket and return the result and the connection's close info.
+    async fn close_info<F, Fut>(
+        configure: fn(WebSocketUpgrade) -> WebSocketUpgrade,
+        callback: F,
+    ) -> (
+        Result<(), Error>,
+        CloseInfo,
+        WebSocketStream<tokio::io::DuplexStream>,
+    )
+    where
+        F: FnOnce(WebSocket) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let (result_tx, mut result_rx) = mpsc::unbounded_channel();
+        let (info_tx, mut info_rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                configure(ws)
+                    .on_close(move |info| info_tx.send(info).unwrap())
+                    .on_upgrade(move |socket| async move {
+                        result_tx.send(callback(socket).await).unwrap();
+                    })
+            }),
+        );
+        let client = test_helpers::connect(app, 64).await;
+
+        let result = result_rx.recv().await.unwrap();
+        let info = info_rx.recv().await.unwrap();
+        (result, info, client)
+    }
+
+    #[tokio::test]
+    async fn closing_records_the_close_frame() {
+        let (result, info, _client) =
+            close_info(|ws| ws, |socket| socket.close_with(CloseCode::Away, "bye")).await;
+
+        result.unwrap();
+        assert_eq!(info.close_code(), Some(CloseCode::Away));
+        assert_eq!(info.close_frame().unwrap().reason, "bye");
+        assert_eq!(info.stats().control_frames_sent(), 1);
+        assert_eq!(info.error(), None);
+    }
+
+    #[tokio::test]
+    async fn closing_without_a_frame_counts_it() {
+        for close_now in [false, true] {
+            let (result, info, _client) = close_info(
+                |ws| ws,
+                move |socket| async move {
+                    if close_now {
+                        socket.close_now().await
+                    } else {
+                        socket.close().await
+                    }
+                },
+            )
+            .await;
+
+            result.unwrap();
+            assert_eq!(info.close_frame(), None);
+            assert_eq!(info.stats().control_frames_sent(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn closing_gives_up_after_the_close_timeout() {
+        let (result, info, _client) = close_info(
+            |ws| ws.close_timeout(Duration::from_millis(50)),
+            |mut socket| async move {
+                // fill the transport so the close frame can't be written
+                let _ = tokio::time::timeout(
+                    Duration::from_millis(50),
+                    socket.send(Message::Binary(vec![0; 1024])),
+                )
+                .await;
+                socket.close_now().await
+            },
+        )
+        .await;
+
+        let err = match result {
+            Err(Error::Io(err)) => err,
+            other => panic!("expected a timeout, got {:?}", other),
+        };
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref(),
+            Some(&Timeout::CloseHandshake)
+        );
+        assert!(info.error().is_some());
+    }
+}
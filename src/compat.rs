@@ -0,0 +1,65 @@
+//! Conversions between this crate's types and the types in [`axum::extract::ws`].
+//!
+//! This helps when migrating between the two crates, or when using libraries written against
+//! axum's types. Both sides are defined in other crates so these are functions rather than
+//! [`From`] implementations.
+//!
+//! Requires the `axum-ws` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::extract::ws;
+//! use axum_tungstenite::{compat, Message};
+//!
+//! let msg = compat::message_from_axum(ws::Message::Text("hello".to_owned()));
+//! assert_eq!(msg, Message::Text("hello".to_owned()));
+//!
+//! let msg = compat::message_into_axum(msg).unwrap();
+//! assert_eq!(msg, ws::Message::Text("hello".to_owned()));
+//! ```
+
+use crate::{CloseFrame, Message};
+use axum::extract::ws;
+
+/// Convert an [`axum::extract::ws::Message`] into a [`Message`].
+pub fn message_from_axum(msg: ws::Message) -> Message {
+    match msg {
+        ws::Message::Text(text) => Message::Text(text),
+        ws::Message::Binary(binary) => Message::Binary(binary),
+        ws::Message::Ping(ping) => Message::Ping(ping),
+        ws::Message::Pong(pong) => Message::Pong(pong),
+        ws::Message::Close(frame) => Message::Close(frame.map(close_frame_from_axum)),
+    }
+}
+
+/// Convert a [`Message`] into an [`axum::extract::ws::Message`].
+///
+/// Fails with the original message for [`Message::Frame`], which axum has no equivalent of.
+#[allow(clippy::result_large_err)]
+pub fn message_into_axum(msg: Message) -> Result<ws::Message, Message> {
+    match msg {
+        Message::Text(text) => Ok(ws::Message::Text(text)),
+        Message::Binary(binary) => Ok(ws::Message::Binary(binary)),
+        Message::Ping(ping) => Ok(ws::Message::Ping(ping)),
+        Message::Pong(pong) => Ok(ws::Message::Pong(pong)),
+        Message::Close(frame) => Ok(ws::Message::Close(frame.map(close_frame_into_axum))),
+        msg @ Message::Frame(_) => Err(msg),
+    }
+}
+
+/// Convert an [`axum::extract::ws::CloseFrame`] into a [`CloseFrame`].
+pub fn close_frame_from_axum(frame: ws::CloseFrame<'_>) -> CloseFrame<'_> {
+    CloseFrame {
+        code: frame.code.into(),
+        reason: frame.reason,
+    }
+}
+
+/// Convert a [`CloseFrame`] into an [`axum::extract::ws::CloseFrame`].
+pub fn close_frame_into_axum(frame: CloseFrame<'_>) -> ws::CloseFrame<'_> {
+    ws::CloseFrame {
+        code: frame.code.into(),
+        reason: frame.reason,
+    }
+}
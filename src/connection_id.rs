@@ -0,0 +1,69 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A unique ID assigned to each connection when the [`WebSocketUpgrade`] is extracted.
+///
+/// The same ID is available from [`WebSocketUpgrade::connection_id`],
+/// [`WebSocket::connection_id`], and [`CloseInfo::connection_id`], so logs from the handshake,
+/// the socket, and the `on_close` callback can be correlated.
+///
+/// IDs are [ULIDs](https://github.com/ulid/spec): 128 bits made of a millisecond timestamp
+/// followed by random bits. They are displayed as 26 character Crockford base32 strings and sort
+/// roughly by creation time.
+///
+/// [`WebSocketUpgrade`]: crate::WebSocketUpgrade
+/// [`WebSocketUpgrade::connection_id`]: crate::WebSocketUpgrade::connection_id
+/// [`WebSocket::connection_id`]: crate::WebSocket::connection_id
+/// [`CloseInfo::connection_id`]: crate::CloseInfo::connection_id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionId(u128);
+
+impl ConnectionId {
+    pub(crate) fn new() -> Self {
+        static SEED: OnceLock<RandomState> = OnceLock::new();
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let seed = SEED.get_or_init(RandomState::new);
+        let random = |salt: u8| {
+            let mut hasher = seed.build_hasher();
+            hasher.write_u64(count);
+            hasher.write_u8(salt);
+            hasher.finish()
+        };
+
+        let millis = u128::from(millis & 0xffff_ffff_ffff);
+        let random = (u128::from(random(0) & 0xffff) << 64) | u128::from(random(1));
+        Self((millis << 80) | random)
+    }
+
+    /// The ID as a number.
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+        let mut buf = [0; 26];
+        for (i, c) in buf.iter_mut().enumerate() {
+            let shift = 5 * (25 - i);
+            *c = ALPHABET[((self.0 >> shift) & 0x1f) as usize];
+        }
+        f.write_str(std::str::from_utf8(&buf).unwrap())
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+};
+
+/// Metadata about the connection a WebSocket was upgraded from.
+///
+/// Servers or middleware that know about the connection, for example because they terminate
+/// TLS, can insert a `ConnectionInfo` into the request extensions. [`WebSocketUpgrade`] picks it
+/// up and makes it available through [`WebSocket::connection_info`] for the lifetime of the
+/// socket.
+///
+/// Besides the common TLS properties, arbitrary data can be attached with
+/// [`insert`](Self::insert).
+///
+/// # Example
+///
+/// ```
+/// use axum::{http::Request, middleware::Next, response::Response};
+/// use axum_tungstenite::ConnectionInfo;
+///
+/// async fn add_connection_info<B>(mut request: Request<B>, next: Next<B>) -> Response {
+///     let info = ConnectionInfo::new()
+///         .with_alpn_protocol(b"http/1.1".to_vec())
+///         .with_server_name("example.com");
+///     request.extensions_mut().insert(info);
+///     next.run(request).await
+/// }
+/// ```
+///
+/// [`WebSocketUpgrade`]: crate::WebSocketUpgrade
+/// [`WebSocket::connection_info`]: crate::WebSocket::connection_info
+#[derive(Clone, Default)]
+pub struct ConnectionInfo {
+    alpn_protocol: Option<Vec<u8>>,
+    server_name: Option<String>,
+    peer_certificates: Option<Vec<Vec<u8>>>,
+    extensions: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl ConnectionInfo {
+    /// Create an empty `ConnectionInfo`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the protocol negotiated with ALPN.
+    pub fn with_alpn_protocol(mut self, protocol: Vec<u8>) -> Self {
+        self.alpn_protocol = Some(protocol);
+        self
+    }
+
+    /// Set the server name the client requested with SNI.
+    pub fn with_server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Set the DER encoded certificate chain presented by the client.
+    pub fn with_peer_certificates(mut self, certificates: Vec<Vec<u8>>) -> Self {
+        self.peer_certificates = Some(certificates);
+        self
+    }
+
+    /// The protocol negotiated with ALPN.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The server name the client requested with SNI.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// The DER encoded certificate chain presented by the client, if any.
+    pub fn peer_certificates(&self) -> Option<&[Vec<u8>]> {
+        self.peer_certificates.as_deref()
+    }
+
+    /// Attach arbitrary data, replacing any previous value of the same type.
+    pub fn insert<T>(&mut self, value: T)
+    where
+        T: Send + Sync + 'static,
+    {
+        self.extensions.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Get data previously attached with [`insert`](Self::insert).
+    pub fn get<T>(&self) -> Option<&T>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+}
+
+impl fmt::Debug for ConnectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionInfo")
+            .field("alpn_protocol", &self.alpn_protocol)
+            .field("server_name", &self.server_name)
+            .field("peer_certificates", &self.peer_certificates)
+            .finish_non_exhaustive()
+    }
+}
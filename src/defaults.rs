@@ -0,0 +1,136 @@
+use crate::stats::Quotas;
+#[cfg(feature = "tracing")]
+use crate::MessageLog;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+
+/// Default settings for all [`WebSocketUpgrade`]s of an application.
+///
+/// Insert an `UpgradeDefaults` into the request extensions, typically with
+/// [`axum::Extension`], and [`WebSocketUpgrade`] starts out with its settings instead of the
+/// built-in defaults. Handlers can still override them with the builder methods on
+/// [`WebSocketUpgrade`].
+///
+/// # Example
+///
+/// ```
+/// use axum::{response::Response, routing::get, Extension, Router};
+/// use axum_tungstenite::{UpgradeDefaults, WebSocketUpgrade};
+/// use std::time::Duration;
+///
+/// let defaults = UpgradeDefaults::new()
+///     .max_message_size(64 * 1024)
+///     .liveness_timeout(Duration::from_secs(60));
+///
+/// let app = Router::new()
+///     .route("/chat", get(handler))
+///     .route("/feed", get(handler))
+///     .layer(Extension(defaults));
+///
+/// async fn handler(ws: WebSocketUpgrade) -> Response {
+///     ws.on_upgrade(|socket| async { /* ... */ })
+/// }
+/// # let _: Router = app;
+/// ```
+///
+/// [`WebSocketUpgrade`]: crate::WebSocketUpgrade
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpgradeDefaults {
+    pub(crate) config: WebSocketConfig,
+    pub(crate) liveness_timeout: Option<Duration>,
+    pub(crate) close_timeout: Option<Duration>,
+    pub(crate) close_linger: Option<Duration>,
+    pub(crate) quotas: Quotas,
+    #[cfg(feature = "tracing")]
+    pub(crate) message_log: Option<MessageLog>,
+}
+
+impl UpgradeDefaults {
+    /// Create `UpgradeDefaults` with the built-in defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [`WebSocketUpgrade::write_buffer_size`](crate::WebSocketUpgrade::write_buffer_size).
+    pub fn write_buffer_size(mut self, size: usize) -> Self {
+        self.config.write_buffer_size = size;
+        self
+    }
+
+    /// See
+    /// [`WebSocketUpgrade::max_write_buffer_size`](crate::WebSocketUpgrade::max_write_buffer_size).
+    pub fn max_write_buffer_size(mut self, max: usize) -> Self {
+        self.config.max_write_buffer_size = max;
+        self
+    }
+
+    /// See [`WebSocketUpgrade::max_message_size`](crate::WebSocketUpgrade::max_message_size).
+    pub fn max_message_size(mut self, max: usize) -> Self {
+        self.config.max_message_size = Some(max);
+        self
+    }
+
+    /// See [`WebSocketUpgrade::max_frame_size`](crate::WebSocketUpgrade::max_frame_size).
+    pub fn max_frame_size(mut self, max: usize) -> Self {
+        self.config.max_frame_size = Some(max);
+        self
+    }
+
+    /// See
+    /// [`WebSocketUpgrade::accept_unmasked_frames`](crate::WebSocketUpgrade::accept_unmasked_frames).
+    pub fn accept_unmasked_frames(mut self, accept: bool) -> Self {
+        self.config.accept_unmasked_frames = accept;
+        self
+    }
+
+    /// See [`WebSocketUpgrade::liveness_timeout`](crate::WebSocketUpgrade::liveness_timeout).
+    pub fn liveness_timeout(mut self, timeout: Duration) -> Self {
+        self.liveness_timeout = Some(timeout);
+        self
+    }
+
+    /// See [`WebSocketUpgrade::close_timeout`](crate::WebSocketUpgrade::close_timeout).
+    pub fn close_timeout(mut self, timeout: Duration) -> Self {
+        self.close_timeout = Some(timeout);
+        self
+    }
+
+    /// See [`WebSocketUpgrade::close_linger`](crate::WebSocketUpgrade::close_linger).
+    pub fn close_linger(mut self, linger: Duration) -> Self {
+        self.close_linger = Some(linger);
+        self
+    }
+
+    /// See [`WebSocketUpgrade::max_messages`](crate::WebSocketUpgrade::max_messages).
+    pub fn max_messages(mut self, max: u64) -> Self {
+        self.quotas.max_messages = Some(max);
+        self
+    }
+
+    /// See [`WebSocketUpgrade::max_total_bytes`](crate::WebSocketUpgrade::max_total_bytes).
+    pub fn max_total_bytes(mut self, max: u64) -> Self {
+        self.quotas.max_bytes = Some(max);
+        self
+    }
+
+    /// See
+    /// [`WebSocketUpgrade::max_text_message_size`](crate::WebSocketUpgrade::max_text_message_size).
+    pub fn max_text_message_size(mut self, max: usize) -> Self {
+        self.quotas.max_text_size = Some(max);
+        self
+    }
+
+    /// See
+    /// [`WebSocketUpgrade::max_binary_message_size`](crate::WebSocketUpgrade::max_binary_message_size).
+    pub fn max_binary_message_size(mut self, max: usize) -> Self {
+        self.quotas.max_binary_size = Some(max);
+        self
+    }
+
+    /// See [`WebSocketUpgrade::log_messages`](crate::WebSocketUpgrade::log_messages).
+    #[cfg(feature = "tracing")]
+    pub fn log_messages(mut self, log: MessageLog) -> Self {
+        self.message_log = Some(log);
+        self
+    }
+}
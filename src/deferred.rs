@@ -0,0 +1,84 @@
+use crate::{
+    CallbackResult, DefaultOnFailedUpdgrade, OnFailedUpdgrade, WebSocket, WebSocketUpgrade,
+};
+use axum::response::{IntoResponse, Response};
+use http::StatusCode;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::sync::oneshot;
+
+/// A [`WebSocketUpgrade`] whose handshake is completed later, possibly by another task.
+///
+/// Created with [`WebSocketUpgrade::defer`].
+#[derive(Debug)]
+pub struct DeferredUpgrade<C = DefaultOnFailedUpdgrade> {
+    upgrade: WebSocketUpgrade<C>,
+    respond: oneshot::Sender<Response>,
+}
+
+impl<C> DeferredUpgrade<C> {
+    pub(crate) fn new(upgrade: WebSocketUpgrade<C>) -> (Self, DeferredResponse) {
+        let (respond, response) = oneshot::channel();
+        (Self { upgrade, respond }, DeferredResponse { response })
+    }
+
+    /// The upgrade waiting to be completed.
+    pub fn upgrade(&self) -> &WebSocketUpgrade<C> {
+        &self.upgrade
+    }
+
+    /// Whether the request was abandoned, for example because the client disconnected.
+    ///
+    /// Completing an abandoned upgrade does nothing, so queues can skip them.
+    pub fn is_abandoned(&self) -> bool {
+        self.respond.is_closed()
+    }
+
+    /// Complete the handshake and call the provided callback with the stream.
+    ///
+    /// See [`WebSocketUpgrade::on_upgrade`] for more details.
+    pub fn on_upgrade<F, Fut>(self, callback: F)
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: CallbackResult,
+        C: OnFailedUpdgrade,
+    {
+        if self.is_abandoned() {
+            return;
+        }
+        let _ = self.respond.send(self.upgrade.on_upgrade(callback));
+    }
+
+    /// Respond to the request with `response` instead of upgrading it.
+    pub fn reject<R>(self, response: R)
+    where
+        R: IntoResponse,
+    {
+        let _ = self.respond.send(response.into_response());
+    }
+}
+
+/// The response to a [`DeferredUpgrade`].
+///
+/// Resolves once the upgrade is completed or rejected. If the [`DeferredUpgrade`] is dropped
+/// first this resolves to `503 Service Unavailable`.
+///
+/// Created with [`WebSocketUpgrade::defer`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct DeferredResponse {
+    response: oneshot::Receiver<Response>,
+}
+
+impl Future for DeferredResponse {
+    type Output = Response;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let response = ready!(Pin::new(&mut self.response).poll(cx));
+        Poll::Ready(response.unwrap_or_else(|_| StatusCode::SERVICE_UNAVAILABLE.into_response()))
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{CloseCode, CloseFrame, ConnectionId};
+use axum_core::BoxError;
+use hyper::upgrade::Upgraded;
+use std::{
+    any::Any,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::oneshot;
+use tokio_tungstenite::WebSocketStream;
+
+/// How long to wait for the close frame sent after a callback failed to be written.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The output of a callback passed to [`WebSocketUpgrade::on_upgrade`].
+///
+/// Implemented for `()` and for `Result<(), E>`, so callbacks can use `?`. Errors are handled
+/// according to the upgrade's [`ErrorPolicy`].
+///
+/// [`WebSocketUpgrade::on_upgrade`]: crate::WebSocketUpgrade::on_upgrade
+pub trait CallbackResult: Send + 'static {
+    #[doc(hidden)]
+    fn into_result(self) -> Result<(), BoxError>;
+}
+
+impl CallbackResult for () {
+    fn into_result(self) -> Result<(), BoxError> {
+        Ok(())
+    }
+}
+
+impl<E> CallbackResult for Result<(), E>
+where
+    E: Into<BoxError> + Send + 'static,
+{
+    fn into_result(self) -> Result<(), BoxError> {
+        self.map_err(Into::into)
+    }
+}
+
+/// What to do when the callback passed to [`WebSocketUpgrade::on_upgrade`] returns an error.
+///
+/// By default the client is sent a close frame with [`CloseCode::Error`] (`1011`) and, with
+/// the `tracing` feature, the error is logged.
+///
+/// Panics in the callback are caught and handled the same way, so the client gets a close frame
+/// instead of waiting for the TCP connection to time out. Use [`on_panic`](Self::on_panic) to
+/// record the panic payload. Resources held by the socket, such as the
+/// [`on_close`](crate::WebSocketUpgrade::on_close) hook, are released while unwinding.
+///
+/// The close frame is sent if the [`WebSocket`] was dropped by the time the callback returns,
+/// which is the case unless it was moved elsewhere, such as another task.
+///
+/// Use [`close_on`](Self::close_on) to send specific close codes for specific error types, so
+/// clients can tell a server bug apart from being rejected.
+///
+/// # Example
+///
+/// ```
+/// use axum::response::Response;
+/// use axum_tungstenite::{CloseCode, CloseFrame, ErrorPolicy, Message, WebSocketUpgrade};
+/// use std::{fmt, io};
+///
+/// #[derive(Debug)]
+/// struct Rejected(&'static str);
+///
+/// impl fmt::Display for Rejected {
+///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+///         write!(f, "rejected: {}", self.0)
+///     }
+/// }
+///
+/// impl std::error::Error for Rejected {}
+///
+/// async fn handler(ws: WebSocketUpgrade) -> Response {
+///     let policy = ErrorPolicy::new()
+///         .on_error(|err| eprintln!("connection failed: {err}"))
+///         .close_on(|err: &Rejected| CloseFrame {
+///             code: CloseCode::Policy,
+///             reason: err.0.into(),
+///         })
+///         .close_on(|_: &io::Error| CloseFrame {
+///             code: CloseCode::Again,
+///             reason: "".into(),
+///         });
+///
+///     ws.error_policy(policy).on_upgrade(|mut socket| async move {
+///         while let Some(msg) = socket.recv().await {
+///             let msg = msg?;
+///             if msg.is_binary() {
+///                 return Err(Rejected("binary messages aren't supported").into());
+///             }
+///             socket.send(msg).await?;
+///         }
+///         Ok::<_, axum_core::BoxError>(())
+///     })
+/// }
+/// ```
+///
+/// [`WebSocketUpgrade::on_upgrade`]: crate::WebSocketUpgrade::on_upgrade
+/// [`WebSocket`]: crate::WebSocket
+#[derive(Clone)]
+pub struct ErrorPolicy {
+    on_error: Option<Arc<dyn Fn(&BoxError) + Send + Sync>>,
+    on_panic: Option<Arc<dyn Fn(&(dyn Any + Send)) + Send + Sync>>,
+    close_on: Vec<Arc<dyn Fn(&BoxError) -> Option<CloseFrame<'static>> + Send + Sync>>,
+    close_frame: Arc<dyn Fn(&BoxError) -> Option<CloseFrame<'static>> + Send + Sync>,
+    panic_close_frame: Option<CloseFrame<'static>>,
+    #[cfg(feature = "tracing")]
+    log: bool,
+}
+
+impl ErrorPolicy {
+    /// Create an `ErrorPolicy` with the default behavior.
+    pub fn new() -> Self {
+        Self {
+            on_error: None,
+            on_panic: None,
+            close_on: Vec::new(),
+            close_frame: Arc::new(|_| Some(internal_error())),
+            panic_close_frame: Some(internal_error()),
+            #[cfg(feature = "tracing")]
+            log: true,
+        }
+    }
+
+    /// Call `hook` with every error returned by the callback.
+    pub fn on_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&BoxError) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with the payload of every panic in the callback.
+    ///
+    /// The payload is usually a `&'static str` or a `String`, see [`std::panic::panic_any`].
+    pub fn on_panic<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        self.on_panic = Some(Arc::new(hook));
+        self
+    }
+
+    /// Send the close frame returned by `f` for errors of type `E`.
+    ///
+    /// Mappings are tried in the order they were added, falling back to
+    /// [`close_frame`](Self::close_frame) for errors that match none of them. `E` is matched
+    /// against the error after converting it to a [`BoxError`], so it must be the error type
+    /// returned by the callback, not one wrapped inside it.
+    pub fn close_on<E, F>(mut self, f: F) -> Self
+    where
+        E: std::error::Error + 'static,
+        F: Fn(&E) -> CloseFrame<'static> + Send + Sync + 'static,
+    {
+        self.close_on
+            .push(Arc::new(move |err| err.downcast_ref::<E>().map(&f)));
+        self
+    }
+
+    /// Pick the close frame sent to the client for an error, or `None` to end the connection
+    /// without one.
+    ///
+    /// This is the default for errors not matched by [`close_on`](Self::close_on).
+    pub fn close_frame<F>(mut self, close_frame: F) -> Self
+    where
+        F: Fn(&BoxError) -> Option<CloseFrame<'static>> + Send + Sync + 'static,
+    {
+        self.close_frame = Arc::new(close_frame);
+        self
+    }
+
+    /// Set the close frame sent to the client when the callback panics, or `None` to end the
+    /// connection without one.
+    ///
+    /// Defaults to [`CloseCode::Error`] with an empty reason.
+    pub fn panic_close_frame(mut self, frame: Option<CloseFrame<'static>>) -> Self {
+        self.panic_close_frame = frame;
+        self
+    }
+
+    /// Whether to emit a `tracing` event at `ERROR` level for every error and panic.
+    ///
+    /// Defaults to `true`. Requires the `tracing` feature.
+    #[cfg(feature = "tracing")]
+    pub fn log(mut self, log: bool) -> Self {
+        self.log = log;
+        self
+    }
+
+    pub(crate) async fn handle_error(
+        &self,
+        connection_id: ConnectionId,
+        error: BoxError,
+        reclaimed: oneshot::Receiver<WebSocketStream<Upgraded>>,
+    ) {
+        #[cfg(feature = "tracing")]
+        if self.log {
+            tracing::error!(%connection_id, error = %error, "websocket callback failed");
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = connection_id;
+
+        if let Some(on_error) = &self.on_error {
+            on_error(&error);
+        }
+
+        let frame = self
+            .close_on
+            .iter()
+            .find_map(|close_on| close_on(&error))
+            .or_else(|| (self.close_frame)(&error));
+        close(reclaimed, frame).await;
+    }
+
+    pub(crate) async fn handle_panic(
+        &self,
+        connection_id: ConnectionId,
+        payload: Box<dyn Any + Send>,
+        reclaimed: oneshot::Receiver<WebSocketStream<Upgraded>>,
+    ) {
+        #[cfg(feature = "tracing")]
+        if self.log {
+            let message = panic_message(&*payload);
+            tracing::error!(%connection_id, panic = message, "websocket callback panicked");
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = connection_id;
+
+        if let Some(on_panic) = &self.on_panic {
+            on_panic(&*payload);
+        }
+
+        close(reclaimed, self.panic_close_frame.clone()).await;
+    }
+}
+
+fn internal_error() -> CloseFrame<'static> {
+    CloseFrame {
+        code: CloseCode::Error,
+        reason: "".into(),
+    }
+}
+
+async fn close(
+    mut reclaimed: oneshot::Receiver<WebSocketStream<Upgraded>>,
+    frame: Option<CloseFrame<'static>>,
+) {
+    let Some(frame) = frame else {
+        return;
+    };
+    if let Ok(mut stream) = reclaimed.try_recv() {
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, stream.close(Some(frame))).await;
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErrorPolicy").finish_non_exhaustive()
+    }
+}
+
+/// The stream of a [`WebSocket`](crate::WebSocket), which can be handed back when the socket is
+/// dropped so a close frame can still be sent after the callback failed.
+#[derive(Debug)]
+pub(crate) struct ReclaimableStream {
+    stream: Option<WebSocketStream<Upgraded>>,
+    reclaim: Option<oneshot::Sender<WebSocketStream<Upgraded>>>,
+}
+
+impl ReclaimableStream {
+    pub(crate) fn new(stream: WebSocketStream<Upgraded>) -> Self {
+        Self {
+            stream: Some(stream),
+            reclaim: None,
+        }
+    }
+
+    /// Send the stream to the returned receiver when dropped.
+    pub(crate) fn reclaim_on_drop(&mut self) -> oneshot::Receiver<WebSocketStream<Upgraded>> {
+        let (tx, rx) = oneshot::channel();
+        self.reclaim = Some(tx);
+        rx
+    }
+
+    pub(crate) fn into_inner(mut self) -> WebSocketStream<Upgraded> {
+        self.stream.take().expect("stream is only taken once")
+    }
+}
+
+impl Deref for ReclaimableStream {
+    type Target = WebSocketStream<Upgraded>;
+
+    fn deref(&self) -> &Self::Target {
+        self.stream.as_ref().expect("stream is only taken once")
+    }
+}
+
+impl DerefMut for ReclaimableStream {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.stream.as_mut().expect("stream is only taken once")
+    }
+}
+
+impl Drop for ReclaimableStream {
+    fn drop(&mut self) {
+        if let (Some(stream), Some(reclaim)) = (self.stream.take(), self.reclaim.take()) {
+            let _ = reclaim.send(stream);
+        }
+    }
+}
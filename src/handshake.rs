@@ -0,0 +1,636 @@
+//! Building blocks of the WebSocket handshake.
+//!
+//! These are the functions [`WebSocketUpgrade`](crate::WebSocketUpgrade) uses internally. They
+//! are exposed for custom upgrade flows that don't go through axum's extractors, such as plain
+//! hyper services or proxies.
+//!
+//! # Example
+//!
+//! ```
+//! use axum_tungstenite::handshake;
+//! use http::{Request, Response};
+//! use axum_core::{body::BoxBody, response::IntoResponse};
+//!
+//! fn handle<B>(req: &Request<B>) -> Response<BoxBody> {
+//!     match handshake::validate_request(req.method(), req.headers()) {
+//!         Ok(key) => handshake::upgrade_response(&key, None, &[]),
+//!         Err(rejection) => rejection.into_response(),
+//!     }
+//! }
+//! ```
+
+use crate::rejection::*;
+use axum_core::response::{IntoResponse, Response};
+use bytes::Bytes;
+use http::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    Method, StatusCode,
+};
+use sha1::{Digest, Sha1};
+use std::{borrow::Cow, fmt};
+
+/// Derive the value of the `Sec-WebSocket-Accept` response header from the
+/// `Sec-WebSocket-Key` request header.
+pub fn accept_key(key: &[u8]) -> HeaderValue {
+    use base64::engine::Engine as _;
+
+    let mut sha1 = Sha1::default();
+    sha1.update(key);
+    sha1.update(&b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11"[..]);
+    let b64 = Bytes::from(base64::engine::general_purpose::STANDARD.encode(sha1.finalize()));
+    HeaderValue::from_maybe_shared(b64).expect("base64 is a valid value")
+}
+
+/// Check that the request is a valid WebSocket upgrade request.
+///
+/// On success the value of the `Sec-WebSocket-Key` header is returned.
+pub fn validate_request(
+    method: &Method,
+    headers: &HeaderMap,
+) -> Result<HeaderValue, WebSocketUpgradeRejection> {
+    if method != Method::GET {
+        return Err(MethodNotGet {
+            method: method.clone(),
+        }
+        .into());
+    }
+
+    if !header_contains(headers, header::CONNECTION, "upgrade") {
+        return Err(InvalidConnectionHeader {
+            value: headers.get(header::CONNECTION).cloned(),
+        }
+        .into());
+    }
+
+    if !header_eq(headers, header::UPGRADE, "websocket") {
+        return Err(InvalidUpgradeHeader {
+            value: headers.get(header::UPGRADE).cloned(),
+        }
+        .into());
+    }
+
+    if !header_eq(headers, header::SEC_WEBSOCKET_VERSION, "13") {
+        return Err(InvalidWebSocketVersionHeader {
+            value: headers.get(header::SEC_WEBSOCKET_VERSION).cloned(),
+        }
+        .into());
+    }
+
+    headers
+        .get(header::SEC_WEBSOCKET_KEY)
+        .cloned()
+        .ok_or_else(|| WebSocketKeyHeaderMissing.into())
+}
+
+/// Build the `101 Switching Protocols` response that completes the handshake.
+///
+/// `key` is the value of the `Sec-WebSocket-Key` request header, `protocol` is the selected
+/// subprotocol, if any, and `extensions` are the accepted extensions.
+pub fn upgrade_response(
+    key: &HeaderValue,
+    protocol: Option<HeaderValue>,
+    extensions: &[Extension],
+) -> Response {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const UPGRADE: HeaderValue = HeaderValue::from_static("upgrade");
+    #[allow(clippy::declare_interior_mutable_const)]
+    const WEBSOCKET: HeaderValue = HeaderValue::from_static("websocket");
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONNECTION, UPGRADE);
+    headers.insert(header::UPGRADE, WEBSOCKET);
+    headers.insert(header::SEC_WEBSOCKET_ACCEPT, accept_key(key.as_bytes()));
+
+    if let Some(protocol) = protocol {
+        headers.insert(header::SEC_WEBSOCKET_PROTOCOL, protocol);
+    }
+
+    if !extensions.is_empty() {
+        let value = extensions
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(header::SEC_WEBSOCKET_EXTENSIONS, value);
+        }
+    }
+
+    (StatusCode::SWITCHING_PROTOCOLS, headers).into_response()
+}
+
+/// Check if the header `key` is equal to `value`, ignoring ASCII case.
+pub fn header_eq(headers: &HeaderMap, key: HeaderName, value: &str) -> bool {
+    if let Some(header) = headers.get(&key) {
+        header.as_bytes().eq_ignore_ascii_case(value.as_bytes())
+    } else {
+        false
+    }
+}
+
+/// Check if the header `key` contains `value`, ignoring ASCII case.
+///
+/// `value` must be lowercase.
+pub fn header_contains(headers: &HeaderMap, key: HeaderName, value: &str) -> bool {
+    let header = if let Some(header) = headers.get(&key) {
+        header
+    } else {
+        return false;
+    };
+
+    if let Ok(header) = std::str::from_utf8(header.as_bytes()) {
+        header.to_ascii_lowercase().contains(value)
+    } else {
+        false
+    }
+}
+
+/// Check if `origin` matches `pattern`.
+///
+/// The comparison is case insensitive. A `*` in `pattern` matches one or more characters of a
+/// host name, so `https://*.example.com` matches all subdomains of `example.com`. A pattern of
+/// just `*` matches any origin.
+pub fn origin_matches(origin: &str, pattern: &str) -> bool {
+    fn matches(origin: &[u8], pattern: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => origin.is_empty(),
+            Some((b'*', rest)) => (1..=origin.len())
+                .take_while(|&len| {
+                    let b = origin[len - 1];
+                    b.is_ascii_alphanumeric() || b == b'-' || b == b'.'
+                })
+                .any(|len| matches(&origin[len..], rest)),
+            Some((p, rest)) => match origin.split_first() {
+                Some((o, origin)) => o.eq_ignore_ascii_case(p) && matches(origin, rest),
+                None => false,
+            },
+        }
+    }
+
+    pattern == "*" || matches(origin.as_bytes(), pattern.as_bytes())
+}
+
+/// How the subprotocols offered in the `Sec-WebSocket-Protocol` header are matched against the
+/// ones the server supports.
+///
+/// The default matches exactly, prefers the server's order, and only looks at the first
+/// `Sec-WebSocket-Protocol` header.
+///
+/// # Example
+///
+/// ```
+/// use axum::response::Response;
+/// use axum_tungstenite::{handshake::ProtocolMatching, WebSocketUpgrade};
+///
+/// async fn handler(ws: WebSocketUpgrade) -> Response {
+///     let matching = ProtocolMatching::new()
+///         .client_preference(true)
+///         .case_insensitive(true)
+///         .all_headers(true);
+///
+///     ws.protocols_with(["graphql-ws", "graphql-transport-ws"], matching)
+///         .on_upgrade(|socket| async { /* ... */ })
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProtocolMatching {
+    client_preference: bool,
+    case_insensitive: bool,
+    all_headers: bool,
+}
+
+impl ProtocolMatching {
+    /// Create a new `ProtocolMatching` with the default behavior.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pick the first protocol in the client's order rather than the server's.
+    pub fn client_preference(mut self, enabled: bool) -> Self {
+        self.client_preference = enabled;
+        self
+    }
+
+    /// Compare protocol names ignoring ASCII case.
+    ///
+    /// The response still echoes the protocol as spelled by the client.
+    pub fn case_insensitive(mut self, enabled: bool) -> Self {
+        self.case_insensitive = enabled;
+        self
+    }
+
+    /// Consider all `Sec-WebSocket-Protocol` headers rather than just the first one.
+    pub fn all_headers(mut self, enabled: bool) -> Self {
+        self.all_headers = enabled;
+        self
+    }
+
+    /// Select a protocol offered in `headers` that is also in `supported`.
+    ///
+    /// Returns the protocol as spelled in the request, which is what the response must echo.
+    pub fn select<'a, I>(&self, headers: &'a HeaderMap, supported: I) -> Option<&'a str>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let values = headers.get_all(header::SEC_WEBSOCKET_PROTOCOL).iter();
+        let offered = values
+            .take(if self.all_headers { usize::MAX } else { 1 })
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|protocol| !protocol.is_empty())
+            .collect::<Vec<_>>();
+
+        let eq = |offered: &str, supported: &str| {
+            if self.case_insensitive {
+                offered.eq_ignore_ascii_case(supported)
+            } else {
+                offered == supported
+            }
+        };
+
+        let supported = supported.into_iter().collect::<Vec<_>>();
+
+        if self.client_preference {
+            offered.into_iter().find(|offered| {
+                supported
+                    .iter()
+                    .any(|supported| eq(offered, supported.as_ref()))
+            })
+        } else {
+            supported.iter().find_map(|supported| {
+                offered
+                    .iter()
+                    .find(|offered| eq(offered, supported.as_ref()))
+                    .copied()
+            })
+        }
+    }
+}
+
+/// Where a client placed a token in the `Sec-WebSocket-Protocol` header.
+///
+/// Browsers can't set an `Authorization` header on WebSocket requests, so some clients send
+/// the token as an extra subprotocol instead. See
+/// [`WebSocketUpgrade::extract_protocol_token`](crate::WebSocketUpgrade::extract_protocol_token).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProtocolToken {
+    /// The token is the entry following `marker`, as in
+    /// `Sec-WebSocket-Protocol: access_token, <token>`.
+    ///
+    /// The marker is echoed in the response unless another protocol was selected, since
+    /// browsers fail the connection if none of the offered protocols is echoed.
+    AfterMarker(Cow<'static, str>),
+    /// The token is the rest of the entry starting with `prefix`, as in
+    /// `Sec-WebSocket-Protocol: base64url.bearer.authorization.k8s.io.<token>`.
+    Prefixed(Cow<'static, str>),
+}
+
+impl ProtocolToken {
+    /// Find the token in `offered` and return its index and value, along with the index of the
+    /// marker that should be echoed, if any.
+    pub(crate) fn find<'a>(&self, offered: &[&'a str]) -> Option<(usize, &'a str, Option<usize>)> {
+        match self {
+            Self::AfterMarker(marker) => {
+                let at = offered.iter().position(|protocol| protocol == marker)?;
+                let token = offered.get(at + 1)?;
+                Some((at + 1, token, Some(at)))
+            }
+            Self::Prefixed(prefix) => offered.iter().enumerate().find_map(|(at, protocol)| {
+                let token = protocol.strip_prefix(prefix.as_ref())?;
+                (!token.is_empty()).then_some((at, token, None))
+            }),
+        }
+    }
+}
+
+/// A WebSocket extension as listed in the `Sec-WebSocket-Extensions` header.
+///
+/// Note that tungstenite rejects frames that have any of the reserved bits set, so only
+/// extensions that don't rely on those can be implemented on top of this crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    name: String,
+    params: Vec<(String, Option<String>)>,
+}
+
+impl Extension {
+    /// Create a new extension without any parameters.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Add a parameter, optionally with a value.
+    pub fn with_param(mut self, name: impl Into<String>, value: Option<String>) -> Self {
+        self.params.push((name.into(), value));
+        self
+    }
+
+    /// The name of the extension.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The parameters of the extension, in the order they were given.
+    pub fn params(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.params
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+
+    /// Get the parameter called `name`.
+    ///
+    /// Returns `Some(None)` if the parameter is present but doesn't have a value.
+    pub fn param(&self, name: &str) -> Option<Option<&str>> {
+        self.params()
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| value)
+    }
+}
+
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for (name, value) in &self.params {
+            match value {
+                Some(value) if value.bytes().all(is_token_byte) => {
+                    write!(f, "; {}={}", name, value)?
+                }
+                Some(value) => write!(f, "; {}=\"{}\"", name, value)?,
+                None => write!(f, "; {}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse the extensions offered in the `Sec-WebSocket-Extensions` headers.
+///
+/// Values that fail to parse are skipped.
+pub fn parse_extensions(headers: &HeaderMap) -> Vec<Extension> {
+    parse_extension_values(headers.get_all(header::SEC_WEBSOCKET_EXTENSIONS))
+}
+
+/// Parse the cookies sent in the `Cookie` headers.
+///
+/// Browsers can't set custom headers when opening a WebSocket, so cookies are usually how the
+/// handshake is authenticated. Percent-encoded names and values are decoded. Cookies that fail to
+/// parse are skipped.
+///
+/// Requires the `cookie` feature.
+#[cfg(feature = "cookie")]
+pub fn parse_cookies(headers: &HeaderMap) -> cookie::CookieJar {
+    let mut jar = cookie::CookieJar::new();
+    let cookies = headers
+        .get_all(header::COOKIE)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| cookie::Cookie::split_parse_encoded(value.to_owned()))
+        .filter_map(Result::ok);
+    for cookie in cookies {
+        jar.add_original(cookie);
+    }
+    jar
+}
+
+pub(crate) fn parse_extension_values<'a, I>(values: I) -> Vec<Extension>
+where
+    I: IntoIterator<Item = &'a HeaderValue>,
+{
+    values
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| split_unquoted(value, ','))
+        .filter_map(|extension| {
+            let mut parts = split_unquoted(extension, ';').into_iter();
+            let name = parts.next()?;
+            if name.is_empty() || !name.bytes().all(is_token_byte) {
+                return None;
+            }
+
+            let params = parts
+                .map(|param| match param.split_once('=') {
+                    Some((name, value)) => {
+                        let value = value.trim();
+                        let value = value
+                            .strip_prefix('"')
+                            .and_then(|value| value.strip_suffix('"'))
+                            .unwrap_or(value);
+                        (name.trim().to_owned(), Some(value.to_owned()))
+                    }
+                    None => (param.to_owned(), None),
+                })
+                .collect();
+
+            Some(Extension {
+                name: name.to_owned(),
+                params,
+            })
+        })
+        .collect()
+}
+
+/// Split `value` on `delimiter`, ignoring delimiters inside quoted strings, and trim each part.
+fn split_unquoted(value: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (idx, c) in value.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == delimiter && !in_quotes {
+            parts.push(value[start..idx].trim());
+            start = idx + c.len_utf8();
+        }
+    }
+    parts.push(value[start..].trim());
+
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_wildcard_matches_subdomains_only() {
+        let pattern = "https://*.example.com";
+
+        assert!(origin_matches("https://a.example.com", pattern));
+        assert!(origin_matches("https://a.b.example.com", pattern));
+        // a subdomain of example.com, even if it looks like another domain
+        assert!(origin_matches("https://evil.com.example.com", pattern));
+
+        assert!(!origin_matches("https://example.com", pattern));
+        assert!(!origin_matches("https://.example.com", pattern));
+        assert!(!origin_matches("https://a.example.com.evil.com", pattern));
+        assert!(!origin_matches("https://aexample.com", pattern));
+        assert!(!origin_matches("https://a/.example.com", pattern));
+        assert!(!origin_matches("http://a.example.com", pattern));
+    }
+
+    #[test]
+    fn origin_ports_must_match() {
+        assert!(!origin_matches(
+            "https://x.example.com:8080",
+            "https://*.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://example.com:8080",
+            "https://example.com"
+        ));
+        assert!(!origin_matches(
+            "https://example.com",
+            "https://example.com:8080"
+        ));
+        assert!(origin_matches(
+            "https://example.com:8080",
+            "https://example.com:8080"
+        ));
+        assert!(origin_matches(
+            "https://x.example.com:8080",
+            "https://*.example.com:8080"
+        ));
+    }
+
+    #[test]
+    fn origin_matching_is_case_insensitive() {
+        assert!(origin_matches("HTTPS://Example.COM", "https://example.com"));
+        assert!(origin_matches(
+            "https://A.example.com",
+            "https://*.EXAMPLE.com"
+        ));
+    }
+
+    #[test]
+    fn origin_star_matches_anything() {
+        assert!(origin_matches("https://example.com", "*"));
+        assert!(origin_matches("null", "*"));
+        // `*` only spans host name characters
+        assert!(origin_matches("https://example.com", "https://*"));
+        assert!(!origin_matches("https://example.com:8080", "https://*"));
+        assert!(origin_matches("https://example.com", "https://*.com"));
+    }
+
+    fn offering(values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(
+                header::SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn protocol_matching_default_prefers_server_order() {
+        let headers = offering(&["chat, superchat"]);
+        let matching = ProtocolMatching::new();
+
+        assert_eq!(
+            matching.select(&headers, ["superchat", "chat"]),
+            Some("superchat")
+        );
+        assert_eq!(
+            matching.select(&headers, ["chat", "superchat"]),
+            Some("chat")
+        );
+        assert_eq!(matching.select(&headers, ["other"]), None);
+        assert_eq!(matching.select(&offering(&[]), ["chat"]), None);
+    }
+
+    #[test]
+    fn protocol_matching_client_preference() {
+        let headers = offering(&["chat, superchat"]);
+        let matching = ProtocolMatching::new().client_preference(true);
+
+        assert_eq!(
+            matching.select(&headers, ["superchat", "chat"]),
+            Some("chat")
+        );
+        assert_eq!(matching.select(&headers, ["superchat"]), Some("superchat"));
+    }
+
+    #[test]
+    fn protocol_matching_case() {
+        let headers = offering(&["GraphQL-WS"]);
+
+        assert_eq!(
+            ProtocolMatching::new().select(&headers, ["graphql-ws"]),
+            None
+        );
+        // the client's spelling is echoed
+        assert_eq!(
+            ProtocolMatching::new()
+                .case_insensitive(true)
+                .select(&headers, ["graphql-ws"]),
+            Some("GraphQL-WS"),
+        );
+    }
+
+    #[test]
+    fn protocol_matching_headers() {
+        let headers = offering(&["chat", "superchat"]);
+
+        assert_eq!(
+            ProtocolMatching::new().select(&headers, ["superchat"]),
+            None
+        );
+        assert_eq!(
+            ProtocolMatching::new()
+                .all_headers(true)
+                .select(&headers, ["superchat"]),
+            Some("superchat"),
+        );
+    }
+
+    #[test]
+    fn protocol_matching_duplicates_and_whitespace() {
+        let headers = offering(&[" chat ,, chat,superchat , chat"]);
+
+        for client_preference in [false, true] {
+            let matching = ProtocolMatching::new().client_preference(client_preference);
+            assert_eq!(matching.select(&headers, ["chat"]), Some("chat"));
+            assert_eq!(matching.select(&headers, ["superchat"]), Some("superchat"));
+        }
+        assert_eq!(
+            ProtocolMatching::new()
+                .client_preference(true)
+                .select(&headers, ["superchat", "chat"]),
+            Some("chat"),
+        );
+    }
+
+    #[test]
+    fn protocol_matching_skips_values_that_are_not_utf8() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_bytes(b"\xffchat").unwrap(),
+        );
+        headers.append(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("chat"),
+        );
+
+        assert_eq!(ProtocolMatching::new().select(&headers, ["chat"]), None);
+        assert_eq!(
+            ProtocolMatching::new()
+                .all_headers(true)
+                .select(&headers, ["chat"]),
+            Some("chat"),
+        );
+    }
+}
@@ -0,0 +1,487 @@
+//! [JSON-RPC 2.0] over a WebSocket.
+//!
+//! Register methods on a [`JsonRpc`] and [serve](JsonRpc::serve) a socket with it. Each text
+//! message is handled as a request, a notification, or a batch of those, and responses are sent
+//! back as text messages. Requests in a batch are handled concurrently.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::response::Response;
+//! use axum_tungstenite::{
+//!     jsonrpc::{ErrorObject, JsonRpc, Params},
+//!     WebSocketUpgrade,
+//! };
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     let rpc = JsonRpc::new()
+//!         .method("add", |params: Params| async move {
+//!             let (a, b): (i64, i64) = params.parse()?;
+//!             Ok(a + b)
+//!         })
+//!         .method("fail", |_| async move {
+//!             Err::<(), _>(ErrorObject::new(1, "something went wrong"))
+//!         });
+//!
+//!     ws.on_upgrade(|socket| rpc.serve(socket))
+//! }
+//! ```
+//!
+//! # Custom message loops
+//!
+//! [`JsonRpc::serve`] owns the socket until it closes. To also send messages of your own, such as
+//! server initiated [`Notification`]s, drive the socket yourself and pass incoming text messages
+//! to [`JsonRpc::handle`].
+//!
+//! [JSON-RPC 2.0]: https://www.jsonrpc.org/specification
+
+use crate::{Message, WebSocket};
+use futures_util::future::{join_all, BoxFuture};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, fmt, future::Future, sync::Arc};
+
+type Handler = Arc<dyn Fn(Params) -> BoxFuture<'static, Result<Value, ErrorObject>> + Send + Sync>;
+
+/// A set of JSON-RPC methods.
+///
+/// See the [module docs](self) for more details.
+#[derive(Default, Clone)]
+pub struct JsonRpc {
+    methods: HashMap<String, Handler>,
+}
+
+impl JsonRpc {
+    /// Create a new `JsonRpc` without any methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a method.
+    ///
+    /// The handler is called for both requests and notifications. Its result is only sent for
+    /// requests.
+    pub fn method<F, Fut, T>(mut self, name: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Params) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, ErrorObject>> + Send + 'static,
+        T: Serialize,
+    {
+        let handler = Arc::new(handler);
+        self.methods.insert(
+            name.into(),
+            Arc::new(move |params| {
+                let future = handler(params);
+                Box::pin(async move {
+                    let result = future.await?;
+                    serde_json::to_value(result).map_err(|_| ErrorObject::internal_error())
+                })
+            }),
+        );
+        self
+    }
+
+    /// Handle messages from `socket` until it closes.
+    ///
+    /// Binary messages are ignored.
+    pub async fn serve(self, mut socket: WebSocket) {
+        while let Some(Ok(msg)) = socket.recv().await {
+            let text = match msg {
+                Message::Text(text) => text,
+                // keep receiving after a `Close` so the reply is sent
+                _ => continue,
+            };
+
+            if let Some(response) = self.handle(&text).await {
+                if socket.send(Message::Text(response)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Handle a single JSON-RPC message.
+    ///
+    /// Returns the serialized response, or `None` if nothing should be sent back, which is the
+    /// case for notifications and batches consisting only of notifications.
+    pub async fn handle(&self, text: &str) -> Option<String> {
+        let value = match serde_json::from_str::<Value>(text) {
+            Ok(value) => value,
+            Err(_) => return to_string(&Response::error(Value::Null, ErrorObject::parse_error())),
+        };
+
+        match value {
+            Value::Array(calls) if calls.is_empty() => to_string(&Response::error(
+                Value::Null,
+                ErrorObject::invalid_request(),
+            )),
+            Value::Array(calls) => {
+                let responses = join_all(calls.into_iter().map(|call| self.call(call)))
+                    .await
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>();
+
+                if responses.is_empty() {
+                    None
+                } else {
+                    to_string(&responses)
+                }
+            }
+            call => to_string(&self.call(call).await?),
+        }
+    }
+
+    async fn call(&self, call: Value) -> Option<Response> {
+        let request = match serde_json::from_value::<Request>(call) {
+            Ok(request) if request.jsonrpc == "2.0" => request,
+            _ => return Some(Response::error(Value::Null, ErrorObject::invalid_request())),
+        };
+
+        let result = match self.methods.get(&request.method) {
+            Some(handler) => handler(Params(request.params)).await,
+            None => Err(ErrorObject::method_not_found()),
+        };
+
+        let id = request.id?;
+        Some(match result {
+            Ok(result) => Response::result(id, result),
+            Err(error) => Response::error(id, error),
+        })
+    }
+}
+
+impl fmt::Debug for JsonRpc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonRpc")
+            .field("methods", &self.methods.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+fn to_string<T: Serialize>(value: &T) -> Option<String> {
+    serde_json::to_string(value).ok()
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+    /// `None` for notifications. An explicit `null` is a request with a null ID.
+    #[serde(default, deserialize_with = "deserialize_id")]
+    id: Option<Value>,
+}
+
+fn deserialize_id<'de, D>(deserializer: D) -> Result<Option<Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorObject>,
+    id: Value,
+}
+
+impl Response {
+    fn result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    fn error(id: Value, error: ErrorObject) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+}
+
+/// The parameters of a request or notification.
+#[derive(Debug, Clone)]
+pub struct Params(Option<Value>);
+
+impl Params {
+    /// Deserialize the parameters.
+    ///
+    /// Fails with [`ErrorObject::invalid_params`] if the parameters don't match `T`. Missing
+    /// parameters are deserialized from `null`.
+    pub fn parse<T>(self) -> Result<T, ErrorObject>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_value(self.0.unwrap_or(Value::Null))
+            .map_err(|err| ErrorObject::invalid_params().with_data(err.to_string()))
+    }
+
+    /// Get the raw parameters, if any were given.
+    pub fn into_value(self) -> Option<Value> {
+        self.0
+    }
+}
+
+/// A JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorObject {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl ErrorObject {
+    /// Create a new error object.
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Attach additional data to the error.
+    pub fn with_data(mut self, data: impl Into<Value>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Invalid JSON was received (`-32700`).
+    pub fn parse_error() -> Self {
+        Self::new(-32700, "Parse error")
+    }
+
+    /// The JSON sent is not a valid request object (`-32600`).
+    pub fn invalid_request() -> Self {
+        Self::new(-32600, "Invalid Request")
+    }
+
+    /// The method does not exist (`-32601`).
+    pub fn method_not_found() -> Self {
+        Self::new(-32601, "Method not found")
+    }
+
+    /// Invalid method parameters (`-32602`).
+    pub fn invalid_params() -> Self {
+        Self::new(-32602, "Invalid params")
+    }
+
+    /// Internal JSON-RPC error (`-32603`).
+    pub fn internal_error() -> Self {
+        Self::new(-32603, "Internal error")
+    }
+
+    /// Get the error code.
+    pub fn code(&self) -> i64 {
+        self.code
+    }
+
+    /// Get the error message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Get the additional data, if any.
+    pub fn data(&self) -> Option<&Value> {
+        self.data.as_ref()
+    }
+}
+
+impl fmt::Display for ErrorObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for ErrorObject {}
+
+/// A notification sent to the peer.
+///
+/// ```
+/// use axum_tungstenite::{jsonrpc::Notification, Message};
+///
+/// let msg: Message = Notification::new("tick").params(serde_json::json!([1])).into();
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    jsonrpc: &'static str,
+    method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: Option<Value>,
+}
+
+impl Notification {
+    /// Create a new notification without parameters.
+    pub fn new(method: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            method: method.into(),
+            params: None,
+        }
+    }
+
+    /// Set the parameters.
+    pub fn params(mut self, params: impl Into<Value>) -> Self {
+        self.params = Some(params.into());
+        self
+    }
+}
+
+impl From<Notification> for Message {
+    fn from(notification: Notification) -> Self {
+        Message::Text(serde_json::to_string(&notification).expect("notification serializes"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::json;
+
+    fn rpc() -> JsonRpc {
+        JsonRpc::new()
+            .method("add", |params: Params| async move {
+                let (a, b): (i64, i64) = params.parse()?;
+                Ok(a + b)
+            })
+            .method("fail", |_| async move {
+                Err::<(), _>(ErrorObject::new(1, "failed").with_data("details"))
+            })
+    }
+
+    async fn handle(text: &str) -> Option<Value> {
+        let response = rpc().handle(text).await?;
+        Some(serde_json::from_str(&response).unwrap())
+    }
+
+    #[tokio::test]
+    async fn requests_get_a_result_or_an_error() {
+        assert_eq!(
+            handle(r#"{"jsonrpc":"2.0","method":"add","params":[1,2],"id":1}"#).await,
+            Some(json!({ "jsonrpc": "2.0", "result": 3, "id": 1 })),
+        );
+        assert_eq!(
+            handle(r#"{"jsonrpc":"2.0","method":"fail","id":null}"#).await,
+            Some(json!({
+                "jsonrpc": "2.0",
+                "error": { "code": 1, "message": "failed", "data": "details" },
+                "id": null,
+            })),
+        );
+        assert_eq!(
+            handle(r#"{"jsonrpc":"2.0","method":"nope","id":"a"}"#).await,
+            Some(json!({
+                "jsonrpc": "2.0",
+                "error": { "code": -32601, "message": "Method not found" },
+                "id": "a",
+            })),
+        );
+
+        let response = handle(r#"{"jsonrpc":"2.0","method":"add","params":"x","id":2}"#)
+            .await
+            .unwrap();
+        assert_eq!(response["error"]["code"], -32602);
+        assert!(response["error"]["data"].is_string());
+    }
+
+    #[tokio::test]
+    async fn notifications_get_no_response() {
+        assert_eq!(
+            handle(r#"{"jsonrpc":"2.0","method":"add","params":[1,2]}"#).await,
+            None
+        );
+        assert_eq!(handle(r#"{"jsonrpc":"2.0","method":"nope"}"#).await, None);
+        assert_eq!(
+            handle(r#"[{"jsonrpc":"2.0","method":"fail"},{"jsonrpc":"2.0","method":"nope"}]"#)
+                .await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_messages_get_an_error() {
+        let error = |code, message| {
+            Some(json!({
+                "jsonrpc": "2.0",
+                "error": { "code": code, "message": message },
+                "id": null,
+            }))
+        };
+
+        assert_eq!(handle("{").await, error(-32700, "Parse error"));
+        assert_eq!(handle("[]").await, error(-32600, "Invalid Request"));
+        assert_eq!(
+            handle(r#"{"jsonrpc":"1.0","method":"add","id":1}"#).await,
+            error(-32600, "Invalid Request")
+        );
+        assert_eq!(handle("1").await, error(-32600, "Invalid Request"));
+    }
+
+    #[tokio::test]
+    async fn batches_get_the_responses_of_their_requests() {
+        let response = handle(
+            r#"[
+                {"jsonrpc":"2.0","method":"add","params":[1,2],"id":1},
+                {"jsonrpc":"2.0","method":"add","params":[3,4]},
+                1,
+                {"jsonrpc":"2.0","method":"add","params":[5,6],"id":2}
+            ]"#,
+        )
+        .await;
+
+        assert_eq!(
+            response,
+            Some(json!([
+                { "jsonrpc": "2.0", "result": 3, "id": 1 },
+                {
+                    "jsonrpc": "2.0",
+                    "error": { "code": -32600, "message": "Invalid Request" },
+                    "id": null,
+                },
+                { "jsonrpc": "2.0", "result": 11, "id": 2 },
+            ]))
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_answers_text_messages_until_the_socket_closes() {
+        let mut client = test_helpers::serve(|socket| rpc().serve(socket)).await;
+
+        client.send(Message::Binary(vec![1])).await.unwrap();
+        client
+            .send(Notification::new("add").params(json!([1, 1])).into())
+            .await
+            .unwrap();
+        client
+            .send(Message::Text(
+                r#"{"jsonrpc":"2.0","method":"add","params":[2,2],"id":7}"#.into(),
+            ))
+            .await
+            .unwrap();
+
+        let response = client.next().await.unwrap().unwrap().into_text().unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&response).unwrap(),
+            json!({ "jsonrpc": "2.0", "result": 4, "id": 7 })
+        );
+
+        client.close(None).await.unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap(), Message::Close(None));
+        assert!(client.next().await.is_none());
+    }
+}
@@ -0,0 +1,220 @@
+//! JWT authentication for upgrades.
+//!
+//! [`JwtAuth`] verifies JSON Web Tokens with [`jsonwebtoken`] and plugs into
+//! [`WebSocketUpgrade::authorize`](crate::WebSocketUpgrade::authorize), so the validated claims
+//! are passed to the socket callback. The token is read from an `Authorization: Bearer` header
+//! or, since browsers can't set headers on WebSocket requests, from a query parameter.
+//!
+//! Expiry is always checked. Keys can be a single [`DecodingKey`] or a [`JwkSet`]. Fetching a
+//! JWKS from an identity provider is left to the application, which can refresh the keys at any
+//! time with [`JwtAuth::update_jwks`].
+//!
+//! Requires the `auth-jwt` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::{response::Response, routing::get, Router};
+//! use axum_tungstenite::{
+//!     jwt::{Algorithm, DecodingKey, JwtAuth},
+//!     WebSocketUpgrade,
+//! };
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Claims {
+//!     sub: String,
+//! }
+//!
+//! async fn handler(ws: WebSocketUpgrade, jwt: JwtAuth) -> Response {
+//!     match ws.authorize(|parts| jwt.authorize::<Claims>(parts)).await {
+//!         Ok(ws) => ws.on_upgrade(|socket, claims| async move {
+//!             // `claims.sub` identifies the user
+//!         }),
+//!         Err(response) => response,
+//!     }
+//! }
+//!
+//! let jwt = JwtAuth::new(DecodingKey::from_secret(b"secret"), Algorithm::HS256)
+//!     .audience(&["chat"])
+//!     .query_param("token");
+//!
+//! let app = Router::new().route("/ws", get(move |ws| handler(ws, jwt.clone())));
+//! # let _: Router = app;
+//! ```
+
+use crate::rejection::{InvalidToken, MissingToken};
+use axum_core::response::{IntoResponse, Response};
+use http::{header, request::Parts};
+use jsonwebtoken::{errors::ErrorKind, Validation};
+use serde::de::DeserializeOwned;
+use std::{
+    borrow::Cow,
+    fmt,
+    future::{ready, Ready},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+#[doc(no_inline)]
+pub use jsonwebtoken::{errors::Error, jwk::JwkSet, Algorithm, DecodingKey};
+
+/// Verifies JWTs sent with upgrade requests.
+///
+/// Cloning is cheap and clones share their keys. See the [module docs](self) for an example.
+#[derive(Clone)]
+pub struct JwtAuth {
+    keys: Arc<RwLock<Vec<Key>>>,
+    validation: Validation,
+    query_param: Option<Cow<'static, str>>,
+}
+
+#[derive(Clone)]
+struct Key {
+    id: Option<String>,
+    key: DecodingKey,
+}
+
+impl JwtAuth {
+    /// Verify tokens signed with `key` using `algorithm`.
+    pub fn new(key: DecodingKey, algorithm: Algorithm) -> Self {
+        Self {
+            keys: Arc::new(RwLock::new(vec![Key { id: None, key }])),
+            validation: Validation::new(algorithm),
+            query_param: None,
+        }
+    }
+
+    /// Verify tokens signed with any of the keys in `jwks`.
+    ///
+    /// Tokens carrying a key ID in their header are only checked against the key with that ID.
+    /// Only `RS256` is accepted by default, use [`algorithms`](Self::algorithms) to change that.
+    pub fn from_jwks(jwks: &JwkSet) -> Result<Self, Error> {
+        Ok(Self {
+            keys: Arc::new(RwLock::new(keys_from_jwks(jwks)?)),
+            validation: Validation::new(Algorithm::RS256),
+            query_param: None,
+        })
+    }
+
+    /// Replace the keys with the ones in `jwks`.
+    ///
+    /// This applies to all clones and is how keys rotated by the identity provider are picked
+    /// up. The keys are left unchanged if any of them fail to parse.
+    pub fn update_jwks(&self, jwks: &JwkSet) -> Result<(), Error> {
+        let keys = keys_from_jwks(jwks)?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Set the accepted signing algorithms.
+    pub fn algorithms(mut self, algorithms: &[Algorithm]) -> Self {
+        self.validation.algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Require the `aud` claim to contain one of `audience`.
+    ///
+    /// Tokens with an `aud` claim are rejected unless this is set.
+    pub fn audience(mut self, audience: &[&str]) -> Self {
+        self.validation.set_audience(audience);
+        self
+    }
+
+    /// Require the `iss` claim to be one of `issuers`.
+    pub fn issuer(mut self, issuers: &[&str]) -> Self {
+        self.validation.set_issuer(issuers);
+        self
+    }
+
+    /// Tolerate clock skew of up to `leeway` when checking `exp` and `nbf`.
+    ///
+    /// Defaults to 60 seconds. Only whole seconds are used.
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.validation.leeway = leeway.as_secs();
+        self
+    }
+
+    /// Also accept the token in the query parameter `name`, for clients that can't set headers.
+    ///
+    /// The `Authorization` header takes precedence if both are present.
+    pub fn query_param(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.query_param = Some(name.into());
+        self
+    }
+
+    /// Verify `token` and return its claims.
+    pub fn verify<C>(&self, token: &str) -> Result<C, Error>
+    where
+        C: DeserializeOwned,
+    {
+        let header = jsonwebtoken::decode_header(token)?;
+        let keys = self.keys.read().unwrap();
+        let mut result = Err(Error::from(ErrorKind::InvalidSignature));
+        for key in keys.iter() {
+            if header.kid.is_some() && key.id.is_some() && header.kid != key.id {
+                continue;
+            }
+            result =
+                jsonwebtoken::decode(token, &key.key, &self.validation).map(|data| data.claims);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Verify the token sent with an upgrade request.
+    ///
+    /// Meant to be passed to [`WebSocketUpgrade::authorize`](crate::WebSocketUpgrade::authorize).
+    /// Fails with `401 Unauthorized` if the token is missing or invalid.
+    pub fn authorize<C>(&self, parts: &Parts) -> Ready<Result<C, Response>>
+    where
+        C: DeserializeOwned,
+    {
+        let result = match self.token(parts) {
+            Some(token) => self
+                .verify(&token)
+                .map_err(|_| InvalidToken.into_response()),
+            None => Err(MissingToken.into_response()),
+        };
+        ready(result)
+    }
+
+    fn token(&self, parts: &Parts) -> Option<String> {
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if let Some(token) = bearer {
+            return Some(token.trim().to_owned());
+        }
+
+        let param = self.query_param.as_ref()?;
+        form_urlencoded::parse(parts.uri.query()?.as_bytes())
+            .find(|(name, _)| name == param)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+fn keys_from_jwks(jwks: &JwkSet) -> Result<Vec<Key>, Error> {
+    jwks.keys
+        .iter()
+        .map(|jwk| {
+            Ok(Key {
+                id: jwk.common.key_id.clone(),
+                key: DecodingKey::from_jwk(jwk)?,
+            })
+        })
+        .collect()
+}
+
+impl fmt::Debug for JwtAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtAuth")
+            .field("validation", &self.validation)
+            .field("query_param", &self.query_param)
+            .finish()
+    }
+}
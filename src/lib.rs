@@ -63,7 +63,7 @@
     clippy::all,
     clippy::dbg_macro,
     clippy::todo,
-    clippy::empty_enum,
+    clippy::empty_enums,
     clippy::enum_glob_use,
     clippy::mem_forget,
     clippy::unused_self,
@@ -72,9 +72,7 @@
     clippy::needless_borrow,
     clippy::match_wildcard_for_single_variants,
     clippy::if_let_mutex,
-    clippy::mismatched_target_os,
     clippy::await_holding_lock,
-    clippy::match_on_vec_items,
     clippy::imprecise_flops,
     clippy::suboptimal_flops,
     clippy::lossy_float_literal,
@@ -94,60 +92,264 @@
     missing_debug_implementations,
     missing_docs
 )]
-#![deny(unreachable_pub, private_in_public)]
+#![deny(unreachable_pub)]
 #![allow(elided_lifetimes_in_paths, clippy::type_complexity)]
 #![forbid(unsafe_code)]
 #![cfg_attr(docsrs, feature(doc_auto_cfg, doc_cfg))]
 #![cfg_attr(test, allow(clippy::float_cmp))]
 
-use self::rejection::*;
+use self::{
+    close::{OnClose, Telemetry},
+    error_policy::ReclaimableStream,
+    rejection::*,
+    rtt::RttTracker,
+    sender::Outgoing,
+    slow::{SlowConsumer, Stalled},
+    stats::Quotas,
+};
 use async_trait::async_trait;
+use axum::extract::ConnectInfo;
+#[cfg(feature = "console")]
+use axum::extract::MatchedPath;
 use axum_core::{
     extract::FromRequestParts,
     response::{IntoResponse, Response},
 };
-use bytes::Bytes;
 use futures_util::{
+    future::{poll_fn, FutureExt},
     sink::{Sink, SinkExt},
     stream::{Stream, StreamExt},
 };
 use http::{
     header::{self, HeaderMap, HeaderName, HeaderValue},
     request::Parts,
-    Method, StatusCode,
+    Method, Request,
 };
 use hyper::upgrade::{OnUpgrade, Upgraded};
-use sha1::{Digest, Sha1};
 use std::{
     borrow::Cow,
     future::Future,
+    io,
+    net::SocketAddr,
+    panic::AssertUnwindSafe,
     pin::Pin,
-    task::{Context, Poll},
+    str::FromStr,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWrite,
+    sync::watch,
+    time::{error::Elapsed, Instant, Sleep},
 };
 use tokio_tungstenite::{
     tungstenite::protocol::{self, WebSocketConfig},
     WebSocketStream,
 };
+#[cfg(feature = "tokio-util")]
+use tokio_util::sync::{CancellationToken, DropGuard};
 
 #[doc(no_inline)]
 pub use tokio_tungstenite::tungstenite::error::{
     CapacityError, Error, ProtocolError, TlsError, UrlError,
 };
 #[doc(no_inline)]
+pub use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+#[doc(no_inline)]
 pub use tokio_tungstenite::tungstenite::Message;
 
+#[cfg(feature = "cookie")]
+#[doc(no_inline)]
+pub use cookie;
+
+pub use self::{
+    close::{CloseInfo, ConnectionState},
+    connection_id::ConnectionId,
+    connection_info::ConnectionInfo,
+    defaults::UpgradeDefaults,
+    deferred::{DeferredResponse, DeferredUpgrade},
+    error_policy::{CallbackResult, ErrorPolicy},
+    handshake::{Extension, ProtocolMatching, ProtocolToken},
+    parts::UpgradeParts,
+    rtt::Rtt,
+    sender::{QueuedSender, SendPermit},
+    slow::SlowConsumerPolicy,
+    stats::{PendingWrites, QuotaExceeded, SocketStats},
+    timeout::Timeout,
+};
+
+#[cfg(feature = "tracing")]
+pub use self::message_log::MessageLog;
+
 /// Extractor for establishing WebSocket connections.
 ///
 /// See the [module docs](self) for an example.
+///
+/// `WebSocketUpgrade` implements [`FromRequestParts`] so it never consumes the request body.
+/// It can be used in any position in a handler's arguments, and extracted from middleware with
+/// [`RequestPartsExt::extract`](axum_core::RequestPartsExt::extract).
+///
+/// # Example
+///
+/// ```
+/// use axum::{
+///     http::Request,
+///     middleware::Next,
+///     response::Response,
+///     RequestPartsExt,
+/// };
+/// use axum_tungstenite::WebSocketUpgrade;
+///
+/// async fn middleware<B>(request: Request<B>, next: Next<B>) -> Response {
+///     let (mut parts, body) = request.into_parts();
+///
+///     match parts.extract::<WebSocketUpgrade>().await {
+///         Ok(ws) => ws.on_upgrade(|socket| async { /* ... */ }),
+///         Err(_) => next.run(Request::from_parts(parts, body)).await,
+///     }
+/// }
+/// ```
+///
+/// # Serving plain HTTP requests on the same route
+///
+/// Extract an `Option<WebSocketUpgrade>` to upgrade requests that carry the handshake headers
+/// and respond to all other requests normally.
+///
+/// ```
+/// use axum::response::{IntoResponse, Response};
+/// use axum_tungstenite::WebSocketUpgrade;
+///
+/// async fn handler(ws: Option<WebSocketUpgrade>) -> Response {
+///     match ws {
+///         Some(ws) => ws.on_upgrade(|socket| async { /* ... */ }),
+///         None => "Connect with a WebSocket client to get live updates".into_response(),
+///     }
+/// }
+/// ```
 #[derive(Debug)]
 pub struct WebSocketUpgrade<F = DefaultOnFailedUpdgrade> {
     config: WebSocketConfig,
     /// The chosen protocol sent in the `Sec-WebSocket-Protocol` header of the response.
     protocol: Option<HeaderValue>,
+    /// A token sent as a subprotocol, extracted with `extract_protocol_token`, and the marker
+    /// to echo if no other protocol is selected.
+    protocol_token: Option<(String, Option<HeaderValue>)>,
     sec_websocket_key: HeaderValue,
     on_upgrade: OnUpgrade,
     on_failed_upgrade: F,
-    sec_websocket_protocol: Option<HeaderValue>,
+    connection_id: ConnectionId,
+    liveness_timeout: Option<Duration>,
+    close_timeout: Option<Duration>,
+    close_linger: Option<Duration>,
+    slow_consumer: Option<(Duration, SlowConsumerPolicy)>,
+    error_policy: ErrorPolicy,
+    #[cfg(feature = "tracing")]
+    message_log: Option<MessageLog>,
+    #[cfg(feature = "console")]
+    matched_path: Option<MatchedPath>,
+    quotas: Quotas,
+    on_close: Option<OnClose>,
+    /// The extensions offered by the client in the `Sec-WebSocket-Extensions` header.
+    offered_extensions: Vec<Extension>,
+    /// The extensions sent back in the `Sec-WebSocket-Extensions` header of the response.
+    accepted_extensions: Vec<Extension>,
+    origin: Option<HeaderValue>,
+    allowed_origins: Option<Arc<[String]>>,
+    /// Snapshot of the request head. The only extension is the peer's `ConnectInfo`, if known.
+    request: Parts,
+    connection_info: Option<ConnectionInfo>,
+    peer_addr: Option<SocketAddr>,
+    /// Snapshot of the request passed to the socket.
+    kept_request: Option<Parts>,
+    #[cfg(feature = "cookie")]
+    cookies: cookie::CookieJar,
+    #[cfg(feature = "testing")]
+    faults: Option<testing::Faults>,
+}
+
+impl WebSocketUpgrade {
+    /// Create a `WebSocketUpgrade` from the pieces of a request.
+    ///
+    /// This is useful when handling requests outside of axum's extractors, for example in a
+    /// custom tower service. `on_upgrade` is usually obtained with [`hyper::upgrade::on`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::{body::Body, response::Response};
+    /// use axum_tungstenite::{rejection::WebSocketUpgradeRejection, WebSocketUpgrade};
+    /// use http::Request;
+    ///
+    /// fn handle(mut req: Request<Body>) -> Result<Response, WebSocketUpgradeRejection> {
+    ///     let on_upgrade = hyper::upgrade::on(&mut req);
+    ///     let ws = WebSocketUpgrade::new(req.method(), req.headers(), on_upgrade)?;
+    ///     Ok(ws
+    ///         .max_message_size(1024)
+    ///         .on_upgrade(|socket| async { /* ... */ }))
+    /// }
+    /// ```
+    pub fn new(
+        method: &Method,
+        headers: &HeaderMap,
+        on_upgrade: OnUpgrade,
+    ) -> Result<Self, WebSocketUpgradeRejection> {
+        let sec_websocket_key = handshake::validate_request(method, headers)?;
+        Ok(Self::from_validated(
+            method,
+            headers,
+            sec_websocket_key,
+            on_upgrade,
+        ))
+    }
+
+    fn from_validated(
+        method: &Method,
+        headers: &HeaderMap,
+        sec_websocket_key: HeaderValue,
+        on_upgrade: OnUpgrade,
+    ) -> Self {
+        let offered_extensions = handshake::parse_extensions(headers);
+
+        let origin = headers.get(header::ORIGIN).cloned();
+
+        let (mut request, _) = Request::new(()).into_parts();
+        request.method = method.clone();
+        request.headers = headers.clone();
+
+        Self {
+            config: Default::default(),
+            protocol: None,
+            protocol_token: None,
+            sec_websocket_key,
+            on_upgrade,
+            on_failed_upgrade: DefaultOnFailedUpdgrade,
+            connection_id: ConnectionId::new(),
+            liveness_timeout: None,
+            close_timeout: None,
+            close_linger: None,
+            slow_consumer: None,
+            error_policy: ErrorPolicy::default(),
+            #[cfg(feature = "tracing")]
+            message_log: None,
+            #[cfg(feature = "console")]
+            matched_path: None,
+            quotas: Quotas::default(),
+            on_close: None,
+            offered_extensions,
+            accepted_extensions: Vec::new(),
+            origin,
+            allowed_origins: None,
+            request,
+            connection_info: None,
+            peer_addr: None,
+            kept_request: None,
+            #[cfg(feature = "cookie")]
+            cookies: handshake::parse_cookies(headers),
+            #[cfg(feature = "testing")]
+            faults: None,
+        }
+    }
 }
 
 impl<C> WebSocketUpgrade<C> {
@@ -199,105 +401,194 @@ impl<C> WebSocketUpgrade<C> {
         self
     }
 
-    /// Set the known protocols.
+    /// Close the connection if no frames are received from the client within `timeout`.
     ///
-    /// If the protocol name specified by `Sec-WebSocket-Protocol` header
-    /// to match any of them, the upgrade response will include `Sec-WebSocket-Protocol` header and
-    /// return the protocol name.
+    /// Any incoming message, including `Ping` and `Pong`, resets the timer. Once the timeout
+    /// elapses the server sends a `Close` frame with [`CloseCode::Away`] if the socket can take
+    /// it without waiting, and shuts the connection down. [`WebSocket::recv`] returns an
+    /// [`Error::Io`] with [`io::ErrorKind::TimedOut`] wrapping [`Timeout::Liveness`], after which
+    /// the stream ends and sending fails with [`Error::AlreadyClosed`].
     ///
-    /// The protocols should be listed in decreasing order of preference: if the client offers
-    /// multiple protocols that the server could support, the server will pick the first one in
-    /// this list.
-    pub fn protocols<I>(mut self, protocols: I) -> Self
-    where
-        I: IntoIterator,
-        I::Item: Into<Cow<'static, str>>,
-    {
-        if let Some(req_protocols) = self
-            .sec_websocket_protocol
-            .as_ref()
-            .and_then(|p| p.to_str().ok())
-        {
-            self.protocol = protocols
-                .into_iter()
-                .map(Into::into)
-                .find(|protocol| {
-                    req_protocols
-                        .split(',')
-                        .any(|req_protocol| req_protocol.trim() == protocol)
-                })
-                .map(|protocol| match protocol {
-                    Cow::Owned(s) => HeaderValue::from_str(&s).unwrap(),
-                    Cow::Borrowed(s) => HeaderValue::from_static(s),
-                });
-        }
+    /// This allows reaping dead connections, for example TCP connections silently dropped by a
+    /// NAT, that would otherwise never produce an error. Clients are expected to send something
+    /// periodically, typically by answering `Ping`s sent by the server.
+    ///
+    /// The timeout is only checked while the socket is being received from, for example through
+    /// [`WebSocket::recv`]. A socket that isn't polled isn't closed, and once it's polled again
+    /// it times out right away if the timeout has elapsed in the meantime.
+    ///
+    /// By default there is no timeout.
+    pub fn liveness_timeout(mut self, timeout: Duration) -> Self {
+        self.liveness_timeout = Some(timeout);
+        self
+    }
 
+    /// Give up on the closing handshake if the client doesn't answer a `Close` frame sent by the
+    /// server within `timeout`.
+    ///
+    /// Once the timeout elapses [`WebSocket::recv`] returns an [`Error::Io`] with
+    /// [`io::ErrorKind::TimedOut`] wrapping [`Timeout::CloseHandshake`], after which the stream
+    /// ends and sending fails with [`Error::AlreadyClosed`]. Dropping the socket then closes the
+    /// connection. Without a
+    /// timeout a client that never acknowledges the `Close` frame keeps the connection open for
+    /// as long as the socket is being received from.
+    ///
+    /// [`WebSocket::close`], [`WebSocket::close_now`] and [`WebSocket::close_with`] return the
+    /// same error if the `Close` frame can't be written within `timeout`, for example because
+    /// the client stopped reading.
+    ///
+    /// By default there is no timeout.
+    pub fn close_timeout(mut self, timeout: Duration) -> Self {
+        self.close_timeout = Some(timeout);
         self
     }
 
-    /// Finalize upgrading the connection and call the provided callback with
-    /// the stream.
+    /// Write the messages queued through [`QueuedSender`]s before closing the socket, waiting at
+    /// most `linger`.
     ///
-    /// When using `WebSocketUpgrade`, the response produced by this method
-    /// should be returned from the handler. See the [module docs](self) for an
-    /// example.
-    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
-    where
-        F: FnOnce(WebSocket) -> Fut + Send + 'static,
-        Fut: Future<Output = ()> + Send + 'static,
-        C: OnFailedUpdgrade,
-    {
-        let on_upgrade = self.on_upgrade;
-        let config = self.config;
-        let on_failed_upgrade = self.on_failed_upgrade;
+    /// This applies to [`WebSocket::close`] and [`WebSocket::close_with`] and makes sure final
+    /// messages sent from other tasks reach the client. Messages still queued once `linger`
+    /// elapses are dropped. Use [`WebSocket::close_now`] to close without waiting.
+    ///
+    /// Messages sent directly on the socket are always written before the `Close` frame.
+    ///
+    /// By default queued messages are dropped when closing.
+    pub fn close_linger(mut self, linger: Duration) -> Self {
+        self.close_linger = Some(linger);
+        self
+    }
 
-        let protocol = self.protocol.clone();
+    /// Apply `policy` once the client hasn't accepted any writes for longer than `threshold`.
+    ///
+    /// A client that stops reading eventually fills the socket's buffers, after which sending
+    /// and flushing wait for it. This bounds how long a slow client can hold up the server, for
+    /// example when fanning out market data to many clients.
+    ///
+    /// By default writes wait for the client indefinitely.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::{SlowConsumerPolicy, WebSocketUpgrade};
+    /// use std::time::Duration;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     ws.slow_consumer(Duration::from_secs(5), SlowConsumerPolicy::drop_messages())
+    ///         .on_upgrade(|socket| async { /* ... */ })
+    /// }
+    /// ```
+    pub fn slow_consumer(mut self, threshold: Duration, policy: SlowConsumerPolicy) -> Self {
+        self.slow_consumer = Some((threshold, policy));
+        self
+    }
 
-        tokio::spawn(async move {
-            let upgraded = match on_upgrade.await {
-                Ok(upgraded) => upgraded,
-                Err(err) => {
-                    on_failed_upgrade.call(err);
-                    return;
-                }
-            };
+    /// Set what happens when the callback passed to [`on_upgrade`](Self::on_upgrade) returns an
+    /// error or panics.
+    ///
+    /// See [`ErrorPolicy`] for the default and an example.
+    pub fn error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
 
-            let socket =
-                WebSocketStream::from_raw_socket(upgraded, protocol::Role::Server, Some(config))
-                    .await;
-            let socket = WebSocket {
-                inner: socket,
-                protocol,
-            };
-            callback(socket).await;
-        });
+    /// Emit a [`tracing`] event for every message sent or received on the socket.
+    ///
+    /// See [`MessageLog`] for the recorded fields. Sampling keeps this usable on busy
+    /// connections.
+    ///
+    /// Requires the `tracing` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::{MessageLog, WebSocketUpgrade};
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     ws.log_messages(MessageLog::new().preview(64).sample(100))
+    ///         .on_upgrade(|socket| async { /* ... */ })
+    /// }
+    /// ```
+    #[cfg(feature = "tracing")]
+    pub fn log_messages(mut self, log: MessageLog) -> Self {
+        self.message_log = Some(log);
+        self
+    }
 
-        #[allow(clippy::declare_interior_mutable_const)]
-        const UPGRADE: HeaderValue = HeaderValue::from_static("upgrade");
-        #[allow(clippy::declare_interior_mutable_const)]
-        const WEBSOCKET: HeaderValue = HeaderValue::from_static("websocket");
-
-        let mut headers = HeaderMap::new();
-        headers.insert(header::CONNECTION, UPGRADE);
-        headers.insert(header::UPGRADE, WEBSOCKET);
-        headers.insert(
-            header::SEC_WEBSOCKET_ACCEPT,
-            sign(self.sec_websocket_key.as_bytes()),
-        );
+    /// Limit the number of `Text` and `Binary` messages the client may send over the lifetime of
+    /// the connection.
+    ///
+    /// Once exceeded the connection is closed with `1008 Policy` and [`WebSocket::recv`] returns
+    /// an [`Error::Io`] wrapping [`QuotaExceeded::Messages`], after which the stream ends and
+    /// sending fails with [`Error::AlreadyClosed`]. The `Close` frame is only sent if the socket
+    /// can accept it without waiting, so a client that isn't reading may see the connection end
+    /// without one.
+    ///
+    /// By default there is no limit.
+    pub fn max_messages(mut self, max: u64) -> Self {
+        self.quotas.max_messages = Some(max);
+        self
+    }
 
-        if let Some(protocol) = self.protocol {
-            headers.insert(header::SEC_WEBSOCKET_PROTOCOL, protocol);
-        }
+    /// Limit the total number of payload bytes the client may send over the lifetime of the
+    /// connection, including control frames.
+    ///
+    /// Once exceeded the connection is closed with `1009 Size` and [`WebSocket::recv`] returns an
+    /// [`Error::Io`] wrapping [`QuotaExceeded::Bytes`]. See
+    /// [`max_messages`](Self::max_messages) for details.
+    ///
+    /// By default there is no limit.
+    pub fn max_total_bytes(mut self, max: u64) -> Self {
+        self.quotas.max_bytes = Some(max);
+        self
+    }
+
+    /// Set the maximum size of `Text` messages.
+    ///
+    /// Larger messages close the connection with `1009 Size` and [`WebSocket::recv`] returns an
+    /// [`Error::Io`] wrapping [`QuotaExceeded::MessageSize`]. See
+    /// [`max_messages`](Self::max_messages) for details.
+    ///
+    /// The limit is checked once tungstenite has received the whole message, so
+    /// [`max_message_size`](Self::max_message_size) still bounds how much memory a single
+    /// message can use and must be at least as large as this limit.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::WebSocketUpgrade;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     // small chat messages but large file uploads
+    ///     ws.max_text_message_size(1024 * 1024)
+    ///         .max_binary_message_size(50 * 1024 * 1024)
+    ///         .on_upgrade(|socket| async { /* ... */ })
+    /// }
+    /// ```
+    pub fn max_text_message_size(mut self, max: usize) -> Self {
+        self.quotas.max_text_size = Some(max);
+        self
+    }
 
-        (StatusCode::SWITCHING_PROTOCOLS, headers).into_response()
+    /// Set the maximum size of `Binary` messages.
+    ///
+    /// See [`max_text_message_size`](Self::max_text_message_size) for details.
+    pub fn max_binary_message_size(mut self, max: usize) -> Self {
+        self.quotas.max_binary_size = Some(max);
+        self
     }
 
-    /// Provide a callback to call if upgrading the connection fails.
+    /// Provide a callback to call once the connection has ended.
     ///
-    /// The connection upgrade is performed in a background task. If that fails this callback
-    /// will be called.
+    /// The callback is called when the [`WebSocket`] is dropped, or turned into its inner stream
+    /// with [`WebSocket::into_inner`], and receives the close frame or error that ended the
+    /// connection, how long it was open, and its [`SocketStats`]. This is useful for logging and
+    /// metrics that shouldn't depend on every handler recording its own teardown.
     ///
-    /// By default any errors will be silently ignored.
+    /// The callback isn't called if upgrading the connection fails, see
+    /// [`on_failed_upgrade`](Self::on_failed_upgrade) for that.
     ///
     /// # Example
     ///
@@ -306,336 +597,3342 @@ impl<C> WebSocketUpgrade<C> {
     /// use axum_tungstenite::WebSocketUpgrade;
     ///
     /// async fn handler(ws: WebSocketUpgrade) -> Response {
-    ///     ws.on_failed_upgrade(|error| {
-    ///         report_error(error);
+    ///     ws.on_close(|info| {
+    ///         println!(
+    ///             "connection closed after {:?} with {:?}, {} messages received",
+    ///             info.duration(),
+    ///             info.close_frame(),
+    ///             info.stats().messages_received(),
+    ///         );
     ///     })
     ///     .on_upgrade(|socket| async { /* ... */ })
     /// }
-    /// #
-    /// # fn report_error(_: hyper::Error) {}
     /// ```
-    pub fn on_failed_upgrade<C2>(self, callback: C2) -> WebSocketUpgrade<C2>
+    pub fn on_close<F>(mut self, callback: F) -> Self
     where
-        C2: OnFailedUpdgrade,
+        F: Fn(CloseInfo) + Send + Sync + 'static,
     {
-        WebSocketUpgrade {
-            config: self.config,
-            protocol: self.protocol,
-            sec_websocket_key: self.sec_websocket_key,
-            on_upgrade: self.on_upgrade,
-            on_failed_upgrade: callback,
-            sec_websocket_protocol: self.sec_websocket_protocol,
-        }
+        self.on_close = Some(OnClose(Arc::new(callback)));
+        self
     }
-}
-
-#[async_trait]
-impl<S> FromRequestParts<S> for WebSocketUpgrade
-where
-    S: Sync,
-{
-    type Rejection = WebSocketUpgradeRejection;
-
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        if parts.method != Method::GET {
-            return Err(MethodNotGet.into());
-        }
-
-        if !header_contains(parts, header::CONNECTION, "upgrade") {
-            return Err(InvalidConnectionHeader.into());
-        }
 
-        if !header_eq(parts, header::UPGRADE, "websocket") {
-            return Err(InvalidUpgradeHeader.into());
-        }
-
-        if !header_eq(parts, header::SEC_WEBSOCKET_VERSION, "13") {
-            return Err(InvalidWebSocketVersionHeader.into());
-        }
+    /// Only allow connections from the given origins.
+    ///
+    /// Browsers send the page's origin in the `Origin` header, and checking it prevents other
+    /// sites from opening WebSocket connections on behalf of your users (cross-site WebSocket
+    /// hijacking).
+    ///
+    /// Origins are compared case insensitively. A `*` matches one or more characters of a host
+    /// name, so `https://*.example.com` allows all subdomains of `example.com`, and `*` alone
+    /// allows any origin.
+    ///
+    /// Requests without an `Origin` header, which typically come from non-browser clients, are
+    /// allowed.
+    ///
+    /// The origin is checked by [`on_upgrade`](Self::on_upgrade), which responds with the
+    /// [`OriginNotAllowed`](rejection::OriginNotAllowed) rejection instead of upgrading the
+    /// connection if it isn't allowed. Calling this again replaces the allowed origins.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::WebSocketUpgrade;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     ws.allowed_origins(["https://example.com", "https://*.example.com"])
+    ///         .on_upgrade(|socket| async { /* ... */ })
+    /// }
+    /// ```
+    pub fn allowed_origins<I>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.allowed_origins = Some(
+            origins
+                .into_iter()
+                .map(|origin| origin.as_ref().to_owned())
+                .collect(),
+        );
+        self
+    }
 
-        let sec_websocket_key = if let Some(key) = parts.headers.remove(header::SEC_WEBSOCKET_KEY) {
-            key
-        } else {
-            return Err(WebSocketKeyHeaderMissing.into());
+    /// Check the `Origin` header against [`allowed_origins`](Self::allowed_origins).
+    fn check_origin(&self) -> Result<(), WebSocketUpgradeRejection> {
+        let (Some(origin), Some(patterns)) = (&self.origin, &self.allowed_origins) else {
+            return Ok(());
         };
 
-        let on_upgrade = parts.extensions.remove::<OnUpgrade>().unwrap();
-
-        let sec_websocket_protocol = parts.headers.get(header::SEC_WEBSOCKET_PROTOCOL).cloned();
+        let allowed = origin.to_str().is_ok_and(|origin| {
+            patterns
+                .iter()
+                .any(|pattern| handshake::origin_matches(origin, pattern))
+        });
 
-        Ok(Self {
-            config: Default::default(),
-            protocol: None,
-            sec_websocket_key,
-            on_upgrade,
-            on_failed_upgrade: DefaultOnFailedUpdgrade,
-            sec_websocket_protocol,
-        })
+        if allowed {
+            Ok(())
+        } else {
+            Err(OriginNotAllowed {
+                origin: origin.clone(),
+            }
+            .into())
+        }
     }
-}
 
-fn header_eq(req: &Parts, key: HeaderName, value: &'static str) -> bool {
-    if let Some(header) = req.headers.get(&key) {
-        header.as_bytes().eq_ignore_ascii_case(value.as_bytes())
-    } else {
-        false
+    /// Set the known protocols.
+    ///
+    /// If the protocol name specified by `Sec-WebSocket-Protocol` header
+    /// to match any of them, the upgrade response will include `Sec-WebSocket-Protocol` header and
+    /// return the protocol name.
+    ///
+    /// The protocols should be listed in decreasing order of preference: if the client offers
+    /// multiple protocols that the server could support, the server will pick the first one in
+    /// this list.
+    ///
+    /// Use [`protocols_with`](Self::protocols_with) to customize how protocols are matched.
+    pub fn protocols<I>(self, protocols: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Cow<'static, str>>,
+    {
+        self.protocols_with(protocols, ProtocolMatching::default())
     }
-}
 
-fn header_contains(req: &Parts, key: HeaderName, value: &'static str) -> bool {
-    let header = if let Some(header) = req.headers.get(&key) {
-        header
-    } else {
-        return false;
-    };
-
-    if let Ok(header) = std::str::from_utf8(header.as_bytes()) {
-        header.to_ascii_lowercase().contains(value)
-    } else {
-        false
-    }
-}
+    /// Set the known protocols and how they are matched against the ones offered by the client.
+    ///
+    /// See [`ProtocolMatching`] for the available options.
+    pub fn protocols_with<I>(mut self, protocols: I, matching: ProtocolMatching) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Cow<'static, str>>,
+    {
+        let protocols = protocols.into_iter().map(Into::into).collect::<Vec<_>>();
 
-/// A stream of WebSocket messages.
-#[derive(Debug)]
-pub struct WebSocket {
-    inner: WebSocketStream<Upgraded>,
-    protocol: Option<HeaderValue>,
-}
+        self.protocol = matching
+            .select(&self.request.headers, &protocols)
+            .map(|protocol| HeaderValue::from_str(protocol).unwrap())
+            .or_else(|| {
+                let (_, marker) = self.protocol_token.as_ref()?;
+                marker.clone()
+            });
 
-impl WebSocket {
-    /// Consume `self` and get the inner [`tokio_tungstenite::WebSocketStream`].
-    pub fn into_inner(self) -> WebSocketStream<Upgraded> {
-        self.inner
+        self
     }
 
-    /// Receive another message.
+    /// The protocol selected by [`protocols`](Self::protocols) or
+    /// [`protocols_with`](Self::protocols_with), if any.
     ///
-    /// Returns `None` if the stream has closed.
-    pub async fn recv(&mut self) -> Option<Result<Message, Error>> {
-        self.next().await
-    }
-
-    /// Send a message.
+    /// This allows deciding how to handle the connection before upgrading it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::WebSocketUpgrade;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     let ws = ws.protocols(["graphql-transport-ws", "graphql-ws"]);
+    ///
+    ///     match ws.selected_protocol().and_then(|p| p.to_str().ok()) {
+    ///         Some("graphql-transport-ws") => ws.on_upgrade(|socket| async { /* ... */ }),
+    ///         _ => ws.on_upgrade(|socket| async { /* legacy protocol */ }),
+    ///     }
+    /// }
+    /// ```
+    pub fn selected_protocol(&self) -> Option<&HeaderValue> {
+        self.protocol.as_ref()
+    }
+
+    /// Extract a token the client sent as an entry in the `Sec-WebSocket-Protocol` header.
+    ///
+    /// The entry holding the token is removed from the request, so it isn't negotiated as a
+    /// protocol or copied by [`keep_request_parts`](Self::keep_request_parts), and is never
+    /// echoed in the response. Entries are looked up across all `Sec-WebSocket-Protocol`
+    /// headers, and headers that aren't valid UTF-8 are kept as is. Get the token with
+    /// [`protocol_token`](Self::protocol_token).
+    ///
+    /// With [`ProtocolToken::AfterMarker`] the marker is echoed if no other protocol is
+    /// selected, whether this is called before or after [`protocols`](Self::protocols).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::{http::StatusCode, response::{IntoResponse, Response}};
+    /// use axum_tungstenite::{ProtocolToken, WebSocketUpgrade};
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     // the client connects with `new WebSocket(url, ["access_token", token])`
+    ///     let ws = ws
+    ///         .protocols(["graphql-ws"])
+    ///         .extract_protocol_token(ProtocolToken::AfterMarker("access_token".into()));
+    ///
+    ///     match ws.protocol_token() {
+    ///         Some(token) if is_valid(token) => ws.on_upgrade(|socket| async { /* ... */ }),
+    ///         _ => StatusCode::UNAUTHORIZED.into_response(),
+    ///     }
+    /// }
+    /// #
+    /// # fn is_valid(_: &str) -> bool { true }
+    /// ```
+    pub fn extract_protocol_token(mut self, location: ProtocolToken) -> Self {
+        let headers = &mut self.request.headers;
+        let values = headers
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        // the entries of all header lines, with the index of the line they are on
+        let entries = values
+            .iter()
+            .enumerate()
+            .filter_map(|(at, value)| Some((at, value.to_str().ok()?)))
+            .flat_map(|(at, value)| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|protocol| !protocol.is_empty())
+                    .map(move |protocol| (at, protocol))
+            })
+            .collect::<Vec<_>>();
+        let offered = entries
+            .iter()
+            .map(|&(_, protocol)| protocol)
+            .collect::<Vec<_>>();
+
+        let Some((token_at, token, marker_at)) = location.find(&offered) else {
+            return self;
+        };
+        let marker = marker_at.and_then(|marker_at| offered[marker_at].parse().ok());
+        let token_selected = self
+            .protocol
+            .as_ref()
+            .is_some_and(|protocol| protocol == token);
+        if self.protocol.is_none() || token_selected {
+            self.protocol.clone_from(&marker);
+        }
+        self.protocol_token = Some((token.to_owned(), marker));
+
+        // rebuild the header without the token, keeping lines that aren't valid UTF-8 as is
+        let rebuilt = values
+            .iter()
+            .enumerate()
+            .filter_map(|(at, value)| {
+                if value.to_str().is_err() {
+                    return Some(value.clone());
+                }
+                let remaining = entries
+                    .iter()
+                    .enumerate()
+                    .filter(|&(entry_at, &(line, _))| line == at && entry_at != token_at)
+                    .map(|(_, &(_, protocol))| protocol)
+                    .collect::<Vec<_>>();
+                if remaining.is_empty() {
+                    return None;
+                }
+                remaining.join(", ").parse().ok()
+            })
+            .collect::<Vec<_>>();
+        headers.remove(header::SEC_WEBSOCKET_PROTOCOL);
+        for value in rebuilt {
+            headers.append(header::SEC_WEBSOCKET_PROTOCOL, value);
+        }
+
+        self
+    }
+
+    /// The token extracted with [`extract_protocol_token`](Self::extract_protocol_token), if
+    /// any.
+    pub fn protocol_token(&self) -> Option<&str> {
+        self.protocol_token
+            .as_ref()
+            .map(|(token, _)| token.as_str())
+    }
+
+    /// The protocol selected by [`protocols`](Self::protocols) or
+    /// [`protocols_with`](Self::protocols_with) as a string, if any.
+    pub fn selected_protocol_str(&self) -> Option<&str> {
+        self.protocol
+            .as_ref()
+            .and_then(|protocol| protocol.to_str().ok())
+    }
+
+    /// Parse the selected protocol into `P`.
+    ///
+    /// Returns `None` if no protocol was selected or it fails to parse. Since only the protocols
+    /// passed to [`protocols`](Self::protocols) are ever selected, an enum of them with a
+    /// [`FromStr`](std::str::FromStr) implementation allows matching exhaustively.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::WebSocketUpgrade;
+    /// use std::str::FromStr;
+    ///
+    /// enum Protocol {
+    ///     GraphqlTransportWs,
+    ///     GraphqlWs,
+    /// }
+    ///
+    /// impl FromStr for Protocol {
+    ///     type Err = ();
+    ///
+    ///     fn from_str(s: &str) -> Result<Self, ()> {
+    ///         match s {
+    ///             "graphql-transport-ws" => Ok(Self::GraphqlTransportWs),
+    ///             "graphql-ws" => Ok(Self::GraphqlWs),
+    ///             _ => Err(()),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     let ws = ws.protocols(["graphql-transport-ws", "graphql-ws"]);
+    ///
+    ///     match ws.selected_protocol_as::<Protocol>() {
+    ///         Some(Protocol::GraphqlTransportWs) => ws.on_upgrade(|socket| async { /* ... */ }),
+    ///         Some(Protocol::GraphqlWs) => ws.on_upgrade(|socket| async { /* ... */ }),
+    ///         None => ws.on_upgrade(|socket| async { /* no protocol */ }),
+    ///     }
+    /// }
+    /// ```
+    pub fn selected_protocol_as<P>(&self) -> Option<P>
+    where
+        P: FromStr,
+    {
+        self.selected_protocol_str()?.parse().ok()
+    }
+
+    /// Decode a typed header from the upgrade request.
+    ///
+    /// Returns `None` if the header is missing or fails to decode. See the
+    /// [`typed_headers`] module for the handshake headers.
+    #[cfg(feature = "headers")]
+    pub fn typed_header<H>(&self) -> Option<H>
+    where
+        H: headers::Header,
+    {
+        headers::HeaderMapExt::typed_get(&self.request.headers)
+    }
+
+    /// The cookies sent with the upgrade request.
+    ///
+    /// They are also available from [`WebSocket::cookies`] after upgrading. Verify signed or
+    /// private cookies with [`CookieJar::signed`](cookie::CookieJar::signed) and
+    /// [`CookieJar::private`](cookie::CookieJar::private), which require the `cookie-signed` and
+    /// `cookie-private` features.
+    ///
+    /// Requires the `cookie` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::{http::StatusCode, response::{IntoResponse, Response}};
+    /// use axum_tungstenite::WebSocketUpgrade;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     let session = match ws.cookies().get("session") {
+    ///         Some(cookie) => cookie.value().to_owned(),
+    ///         None => return StatusCode::UNAUTHORIZED.into_response(),
+    ///     };
+    ///
+    ///     ws.on_upgrade(|socket| async move { /* ... */ })
+    /// }
+    /// ```
+    #[cfg(feature = "cookie")]
+    pub fn cookies(&self) -> &cookie::CookieJar {
+        &self.cookies
+    }
+
+    /// The unique ID of this connection.
+    ///
+    /// The socket created by upgrading has the same ID, see [`ConnectionId`].
+    pub fn connection_id(&self) -> ConnectionId {
+        self.connection_id
+    }
+
+    /// The address of the client.
+    ///
+    /// This requires serving the app with
+    /// [`Router::into_make_service_with_connect_info::<SocketAddr>`][connect-info]. It is also
+    /// passed on to [`WebSocket::peer_addr`], so handlers don't need to extract
+    /// [`ConnectInfo`] themselves.
+    ///
+    /// [connect-info]: axum::Router::into_make_service_with_connect_info
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Metadata about the underlying connection, if the server provided any.
+    ///
+    /// See [`ConnectionInfo`] for more details.
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.connection_info.as_ref()
+    }
+
+    /// Keep a snapshot of the request for use inside the socket.
+    ///
+    /// The snapshot contains the request's method, URI, and version, along with the headers
+    /// named in `headers`. It's available through [`WebSocket::request_parts`]. Use
+    /// [`keep_extension`](Self::keep_extension) to add extensions to it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::WebSocketUpgrade;
+    /// use http::header::USER_AGENT;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     ws.keep_request_parts([USER_AGENT])
+    ///         .on_upgrade(|socket| async move {
+    ///             let parts = socket.request_parts().unwrap();
+    ///             let query = parts.uri.query();
+    ///             let user_agent = parts.headers.get(USER_AGENT);
+    ///             // ...
+    ///         })
+    /// }
+    /// ```
+    pub fn keep_request_parts<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        let mut kept_headers = HeaderMap::new();
+        for name in headers {
+            for value in self.request.headers.get_all(&name) {
+                kept_headers.append(name.clone(), value.clone());
+            }
+        }
+        self.kept_request().headers.extend(kept_headers);
+        self
+    }
+
+    /// Add an extension to the request snapshot available through
+    /// [`WebSocket::request_parts`].
+    ///
+    /// Extensions of the original request cannot be copied automatically, so this can be used to
+    /// pass along values obtained with other extractors.
+    pub fn keep_extension<T>(mut self, value: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.kept_request().extensions.insert(value);
+        self
+    }
+
+    fn kept_request(&mut self) -> &mut Parts {
+        let request = &self.request;
+        self.kept_request.get_or_insert_with(|| {
+            let (mut parts, _) = Request::new(()).into_parts();
+            parts.method = request.method.clone();
+            parts.uri = request.uri.clone();
+            parts.version = request.version;
+            parts
+        })
+    }
+
+    /// The extensions offered by the client in the `Sec-WebSocket-Extensions` header.
+    pub fn extensions(&self) -> &[Extension] {
+        &self.offered_extensions
+    }
+
+    /// Set the extensions to accept.
+    ///
+    /// They will be included in the `Sec-WebSocket-Extensions` header of the response. Use
+    /// [`extensions`](Self::extensions) to see what the client offered.
+    ///
+    /// Note that the extensions must be implemented by the application. This crate only
+    /// negotiates them.
+    pub fn accept_extensions<I>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = Extension>,
+    {
+        self.accepted_extensions = extensions.into_iter().collect();
+        self
+    }
+
+    /// Finalize upgrading the connection and call the provided callback with
+    /// the stream.
+    ///
+    /// When using `WebSocketUpgrade`, the response produced by this method
+    /// should be returned from the handler. See the [module docs](self) for an
+    /// example.
+    ///
+    /// With the `console` feature and `--cfg tokio_unstable`, the connection's task is named
+    /// `websocket <route> <connection id>`, so tools like
+    /// [tokio-console](https://github.com/tokio-rs/console) show which task belongs to which
+    /// socket.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: CallbackResult,
+        C: OnFailedUpdgrade,
+    {
+        #[cfg(all(tokio_unstable, feature = "console"))]
+        let name = self.task_name();
+        let (response, connection) = self.on_upgrade_manual(callback);
+        #[cfg(all(tokio_unstable, feature = "console"))]
+        tokio::task::Builder::new()
+            .name(&name)
+            .spawn(connection)
+            .expect("failed to spawn connection task");
+        #[cfg(not(all(tokio_unstable, feature = "console")))]
+        tokio::spawn(connection);
+        response
+    }
+
+    #[cfg(all(tokio_unstable, feature = "console"))]
+    fn task_name(&self) -> String {
+        match &self.matched_path {
+            Some(path) => format!("websocket {} {}", path.as_str(), self.connection_id),
+            None => format!("websocket {}", self.connection_id),
+        }
+    }
+
+    /// Finalize upgrading the connection without spawning a task for it.
+    ///
+    /// Returns the response to send to the client and a future that completes the upgrade and
+    /// runs `callback`. Unlike [`on_upgrade`](Self::on_upgrade) the future isn't spawned, so it
+    /// can be driven by the caller, for example in a [`JoinSet`](tokio::task::JoinSet) or under
+    /// custom supervision.
+    ///
+    /// The upgrade only completes after the response has been sent, so the future must not be
+    /// awaited before the response is returned from the handler.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::{extract::State, response::Response};
+    /// use axum_tungstenite::WebSocketUpgrade;
+    /// use std::{future::Future, pin::Pin};
+    /// use tokio::sync::mpsc;
+    ///
+    /// type Connection = Pin<Box<dyn Future<Output = ()> + Send>>;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade, State(tx): State<mpsc::Sender<Connection>>) -> Response {
+    ///     let (response, connection) = ws.on_upgrade_manual(|socket| async { /* ... */ });
+    ///     // hand the connection to a supervisor task
+    ///     let _ = tx.send(Box::pin(connection)).await;
+    ///     response
+    /// }
+    /// ```
+    pub fn on_upgrade_manual<F, Fut>(
+        self,
+        callback: F,
+    ) -> (Response, impl Future<Output = ()> + Send + 'static)
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: CallbackResult,
+        C: OnFailedUpdgrade,
+    {
+        let rejection = self.check_origin().err();
+        let rejected = rejection.is_some();
+        #[cfg(feature = "tracing")]
+        let span = self.connection_span();
+        let on_upgrade = self.on_upgrade;
+        let config = self.config;
+        let on_failed_upgrade = self.on_failed_upgrade;
+        let connection_id = self.connection_id;
+        let liveness_timeout = self.liveness_timeout;
+        let close_timeout = self.close_timeout;
+        let close_linger = self.close_linger;
+        let slow_consumer = self.slow_consumer;
+        let error_policy = self.error_policy;
+        #[cfg(feature = "tracing")]
+        let message_log = self.message_log;
+        let quotas = self.quotas;
+        let on_close = self.on_close;
+        let connection_info = self.connection_info;
+        let peer_addr = self.peer_addr;
+        let kept_request = self.kept_request;
+        #[cfg(feature = "cookie")]
+        let cookies = self.cookies;
+        #[cfg(feature = "testing")]
+        let faults = self.faults;
+        let extensions = self.accepted_extensions.clone();
+
+        let protocol = self.protocol.clone();
+
+        let connection = async move {
+            if rejected {
+                return;
+            }
+            let upgraded = match on_upgrade.await {
+                Ok(upgraded) => upgraded,
+                Err(err) => {
+                    on_failed_upgrade.call(err);
+                    return;
+                }
+            };
+
+            let socket =
+                WebSocketStream::from_raw_socket(upgraded, protocol::Role::Server, Some(config))
+                    .await;
+            let telemetry = Telemetry::new(connection_id, on_close);
+            #[cfg(feature = "tracing")]
+            let telemetry = telemetry.with_message_log(message_log);
+            let mut socket = WebSocket {
+                liveness: liveness_timeout.map(Liveness::new),
+                close_timeout,
+                close_linger,
+                slow_consumer: slow_consumer
+                    .map(|(threshold, policy)| SlowConsumer::new(threshold, policy)),
+                quotas,
+                connection_info,
+                peer_addr,
+                request: kept_request,
+                #[cfg(feature = "cookie")]
+                cookies,
+                #[cfg(feature = "testing")]
+                faults: faults.map(testing::FaultState::new),
+                ..WebSocket::new(socket, protocol, extensions, telemetry)
+            };
+            let reclaimed = socket.inner.reclaim_on_drop();
+            match AssertUnwindSafe(async move { callback(socket).await })
+                .catch_unwind()
+                .await
+            {
+                Ok(output) => {
+                    if let Err(err) = output.into_result() {
+                        error_policy
+                            .handle_error(connection_id, err, reclaimed)
+                            .await;
+                    }
+                }
+                Err(payload) => {
+                    error_policy
+                        .handle_panic(connection_id, payload, reclaimed)
+                        .await;
+                }
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let connection = tracing::Instrument::instrument(connection, span);
+
+        if let Some(rejection) = rejection {
+            return (rejection.into_response(), connection);
+        }
+
+        let response = handshake::upgrade_response(
+            &self.sec_websocket_key,
+            self.protocol,
+            &self.accepted_extensions,
+        );
+
+        (response, connection)
+    }
+
+    /// The span the connection runs in, parented to the trace context sent with the request
+    /// when the `otel` feature is enabled.
+    #[cfg(feature = "tracing")]
+    fn connection_span(&self) -> tracing::Span {
+        let span = tracing::info_span!(
+            "websocket connection",
+            connection_id = %self.connection_id,
+        );
+        #[cfg(feature = "otel")]
+        otel::set_parent(&span, &self.request.headers);
+        span
+    }
+
+    /// Run an asynchronous authorization check before upgrading the connection.
+    ///
+    /// `authorize` receives the request's method, URI, version, and headers. Request extensions
+    /// are not available, except for the client's [`ConnectInfo<SocketAddr>`] when serving with
+    /// [`Router::into_make_service_with_connect_info::<SocketAddr>`][connect-info]. If it returns
+    /// `Err` the response is returned to the client and the connection is not upgraded.
+    /// Otherwise the returned value is passed to the callback given to
+    /// [`AuthorizedUpgrade::on_upgrade`] alongside the socket.
+    ///
+    /// The future returned by `authorize` cannot borrow the request, so copy what you need out
+    /// of it first.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::{http::StatusCode, response::{IntoResponse, Response}};
+    /// use axum_tungstenite::WebSocketUpgrade;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     let ws = ws
+    ///         .authorize(|parts| {
+    ///             let token = parts.headers.get("x-token").cloned();
+    ///             async move {
+    ///                 match token {
+    ///                     Some(token) => lookup_user(token.as_bytes()).await,
+    ///                     None => Err(StatusCode::UNAUTHORIZED.into_response()),
+    ///                 }
+    ///             }
+    ///         })
+    ///         .await;
+    ///
+    ///     match ws {
+    ///         Ok(ws) => ws.on_upgrade(|socket, user| async move { /* ... */ }),
+    ///         Err(response) => response,
+    ///     }
+    /// }
+    ///
+    /// struct User;
+    ///
+    /// async fn lookup_user(token: &[u8]) -> Result<User, Response> {
+    ///     // ...
+    ///     # Ok(User)
+    /// }
+    /// ```
+    ///
+    /// [connect-info]: axum::Router::into_make_service_with_connect_info
+    pub async fn authorize<A, Fut, T>(
+        self,
+        authorize: A,
+    ) -> Result<AuthorizedUpgrade<T, C>, Response>
+    where
+        A: FnOnce(&Parts) -> Fut,
+        Fut: Future<Output = Result<T, Response>>,
+    {
+        let value = authorize(&self.request).await?;
+        Ok(AuthorizedUpgrade {
+            upgrade: self,
+            value,
+        })
+    }
+
+    /// Complete the handshake later, for example from a background job.
+    ///
+    /// The handler should return the [`DeferredResponse`] once it resolves, which happens when
+    /// the [`DeferredUpgrade`] is completed or rejected. The `DeferredUpgrade` is `Send` and
+    /// `'static`, so it can be put in a queue, letting an admission control task accept
+    /// connections only as capacity frees up. Clients usually time out handshakes that take too
+    /// long, so the wait should be bounded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::{extract::State, http::StatusCode, response::Response};
+    /// use axum_tungstenite::{DeferredUpgrade, WebSocketUpgrade};
+    /// use std::{sync::Arc, time::Duration};
+    /// use tokio::sync::{mpsc, Semaphore};
+    ///
+    /// async fn handler(
+    ///     State(queue): State<mpsc::Sender<DeferredUpgrade>>,
+    ///     ws: WebSocketUpgrade,
+    /// ) -> Response {
+    ///     let (deferred, response) = ws.defer();
+    ///     if let Err(err) = queue.try_send(deferred) {
+    ///         err.into_inner().reject(StatusCode::SERVICE_UNAVAILABLE);
+    ///     }
+    ///     response.await
+    /// }
+    ///
+    /// async fn admission_control(mut queue: mpsc::Receiver<DeferredUpgrade>) {
+    ///     let capacity = Arc::new(Semaphore::new(1000));
+    ///
+    ///     while let Some(deferred) = queue.recv().await {
+    ///         if deferred.is_abandoned() {
+    ///             continue;
+    ///         }
+    ///         let permit = match tokio::time::timeout(
+    ///             Duration::from_secs(5),
+    ///             capacity.clone().acquire_owned(),
+    ///         )
+    ///         .await
+    ///         {
+    ///             Ok(Ok(permit)) => permit,
+    ///             _ => {
+    ///                 deferred.reject(StatusCode::SERVICE_UNAVAILABLE);
+    ///                 continue;
+    ///             }
+    ///         };
+    ///         deferred.on_upgrade(|socket| async move {
+    ///             let _permit = permit;
+    ///             // ...
+    ///         });
+    ///     }
+    /// }
+    /// ```
+    pub fn defer(self) -> (DeferredUpgrade<C>, DeferredResponse) {
+        DeferredUpgrade::new(self)
+    }
+
+    /// Provide a callback to call if upgrading the connection fails.
+    ///
+    /// The connection upgrade is performed in a background task. If that fails this callback
+    /// will be called.
+    ///
+    /// By default any errors will be silently ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::WebSocketUpgrade;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     ws.on_failed_upgrade(|error| {
+    ///         report_error(error);
+    ///     })
+    ///     .on_upgrade(|socket| async { /* ... */ })
+    /// }
+    /// #
+    /// # fn report_error(_: hyper::Error) {}
+    /// ```
+    pub fn on_failed_upgrade<C2>(self, callback: C2) -> WebSocketUpgrade<C2>
+    where
+        C2: OnFailedUpdgrade,
+    {
+        WebSocketUpgrade {
+            config: self.config,
+            protocol: self.protocol,
+            protocol_token: self.protocol_token,
+            sec_websocket_key: self.sec_websocket_key,
+            on_upgrade: self.on_upgrade,
+            on_failed_upgrade: callback,
+            connection_id: self.connection_id,
+            liveness_timeout: self.liveness_timeout,
+            close_timeout: self.close_timeout,
+            close_linger: self.close_linger,
+            slow_consumer: self.slow_consumer,
+            error_policy: self.error_policy,
+            #[cfg(feature = "tracing")]
+            message_log: self.message_log,
+            #[cfg(feature = "console")]
+            matched_path: self.matched_path,
+            quotas: self.quotas,
+            on_close: self.on_close,
+            offered_extensions: self.offered_extensions,
+            accepted_extensions: self.accepted_extensions,
+            origin: self.origin,
+            allowed_origins: self.allowed_origins,
+            request: self.request,
+            connection_info: self.connection_info,
+            peer_addr: self.peer_addr,
+            kept_request: self.kept_request,
+            #[cfg(feature = "cookie")]
+            cookies: self.cookies,
+            #[cfg(feature = "testing")]
+            faults: self.faults,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for WebSocketUpgrade
+where
+    S: Sync,
+{
+    type Rejection = WebSocketUpgradeRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Self::from_request_head(parts)
+    }
+}
+
+impl WebSocketUpgrade {
+    pub(crate) fn from_request_head(parts: &mut Parts) -> Result<Self, WebSocketUpgradeRejection> {
+        let sec_websocket_key = handshake::validate_request(&parts.method, &parts.headers)?;
+        let on_upgrade = parts
+            .extensions
+            .remove::<OnUpgrade>()
+            .ok_or(ConnectionNotUpgradable)?;
+
+        let mut ws =
+            Self::from_validated(&parts.method, &parts.headers, sec_websocket_key, on_upgrade);
+        // the key is consumed by the handshake, like the `OnUpgrade`
+        parts.headers.remove(header::SEC_WEBSOCKET_KEY);
+        if let Some(defaults) = parts.extensions.get::<UpgradeDefaults>() {
+            ws.config = defaults.config;
+            ws.liveness_timeout = defaults.liveness_timeout;
+            ws.close_timeout = defaults.close_timeout;
+            ws.close_linger = defaults.close_linger;
+            ws.quotas = defaults.quotas;
+            #[cfg(feature = "tracing")]
+            {
+                ws.message_log = defaults.message_log;
+            }
+        }
+        ws.request.uri = parts.uri.clone();
+        ws.request.version = parts.version;
+        ws.connection_info = parts.extensions.get::<ConnectionInfo>().cloned();
+        #[cfg(feature = "console")]
+        {
+            ws.matched_path = parts.extensions.get::<MatchedPath>().cloned();
+        }
+        ws.peer_addr = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+        if let Some(addr) = ws.peer_addr {
+            ws.request.extensions.insert(ConnectInfo(addr));
+        }
+        #[cfg(feature = "testing")]
+        {
+            ws.faults = parts.extensions.get::<testing::Faults>().copied();
+        }
+        Ok(ws)
+    }
+}
+
+/// A [`WebSocketUpgrade`] that has passed an authorization check.
+///
+/// Created with [`WebSocketUpgrade::authorize`].
+#[derive(Debug)]
+pub struct AuthorizedUpgrade<T, F = DefaultOnFailedUpdgrade> {
+    upgrade: WebSocketUpgrade<F>,
+    value: T,
+}
+
+impl<T, C> AuthorizedUpgrade<T, C> {
+    /// The value produced by the authorization check.
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// Finalize upgrading the connection and call the provided callback with the stream and the
+    /// value produced by the authorization check.
+    ///
+    /// See [`WebSocketUpgrade::on_upgrade`] for more details.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
+    where
+        F: FnOnce(WebSocket, T) -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: CallbackResult,
+        T: Send + 'static,
+        C: OnFailedUpdgrade,
+    {
+        let value = self.value;
+        self.upgrade
+            .on_upgrade(move |socket| callback(socket, value))
+    }
+
+    /// Get the upgrade and the value produced by the authorization check.
+    pub fn into_parts(self) -> (WebSocketUpgrade<C>, T) {
+        (self.upgrade, self.value)
+    }
+}
+
+/// A stream of WebSocket messages.
+///
+/// The underlying connection is hyper's type erased [`Upgraded`], so sockets work the same
+/// regardless of how the server accepts connections, be it TCP, Unix domain sockets, or a custom
+/// accept loop.
+#[derive(Debug)]
+pub struct WebSocket {
+    inner: ReclaimableStream,
+    protocol: Option<HeaderValue>,
+    extensions: Vec<Extension>,
+    liveness: Option<Liveness>,
+    close_timeout: Option<Duration>,
+    /// Armed once a `Close` frame has been sent.
+    close_deadline: Option<Pin<Box<Sleep>>>,
+    close_linger: Option<Duration>,
+    slow_consumer: Option<SlowConsumer>,
+    /// Set once the liveness or close timeout elapsed or a quota was exceeded.
+    terminated: bool,
+    /// An item that was received but not yet returned, yielded before reading any further.
+    buffered: Option<Result<Message, Error>>,
+    quotas: Quotas,
+    connection_info: Option<ConnectionInfo>,
+    peer_addr: Option<SocketAddr>,
+    request: Option<Parts>,
+    #[cfg(feature = "cookie")]
+    cookies: cookie::CookieJar,
+    outgoing: Option<Outgoing>,
+    telemetry: Telemetry,
+    rtt: RttTracker,
+    #[cfg(feature = "testing")]
+    faults: Option<testing::FaultState>,
+    #[cfg(feature = "json")]
+    validator: Option<validate::Validator>,
+    /// Set to `true` once the connection is closed. Dropping it also resolves `closed` futures.
+    closed: watch::Sender<bool>,
+    /// Cancels the token returned by `cancellation_token` when closed or dropped.
+    #[cfg(feature = "tokio-util")]
+    cancellation: Option<(CancellationToken, DropGuard)>,
+}
+
+#[derive(Debug)]
+struct Liveness {
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl Liveness {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            sleep: Box::pin(tokio::time::sleep(timeout)),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.sleep.as_mut().reset(Instant::now() + self.timeout);
+    }
+}
+
+impl WebSocket {
+    fn new(
+        inner: WebSocketStream<Upgraded>,
+        protocol: Option<HeaderValue>,
+        extensions: Vec<Extension>,
+        telemetry: Telemetry,
+    ) -> Self {
+        Self {
+            inner: ReclaimableStream::new(inner),
+            protocol,
+            extensions,
+            liveness: None,
+            close_timeout: None,
+            close_deadline: None,
+            close_linger: None,
+            slow_consumer: None,
+            terminated: false,
+            buffered: None,
+            quotas: Quotas::default(),
+            connection_info: None,
+            peer_addr: None,
+            request: None,
+            #[cfg(feature = "cookie")]
+            cookies: cookie::CookieJar::new(),
+            outgoing: None,
+            telemetry,
+            rtt: RttTracker::default(),
+            #[cfg(feature = "testing")]
+            faults: None,
+            #[cfg(feature = "json")]
+            validator: None,
+            closed: watch::channel(false).0,
+            #[cfg(feature = "tokio-util")]
+            cancellation: None,
+        }
+    }
+
+    /// Consume `self` and get the inner [`tokio_tungstenite::WebSocketStream`].
+    pub fn into_inner(self) -> WebSocketStream<Upgraded> {
+        self.inner.into_inner()
+    }
+
+    /// Receive another message.
+    ///
+    /// Returns `None` if the stream has closed.
+    ///
+    /// Incoming `Ping`s are returned like any other message. tungstenite 0.20 queues a `Pong`
+    /// carrying the same payload for every `Ping` and has no option to turn that off, so the
+    /// automatic reply can't be disabled. It's only written once the socket is received from or
+    /// sent to again though, and a `Pong` sent before that replaces it, so handlers that answer
+    /// pings with a custom payload should do so right away.
+    ///
+    /// Text messages that aren't valid UTF-8 are rejected by tungstenite with [`Error::Utf8`],
+    /// after which the stream ends. The validation happens inside tungstenite and the payload is
+    /// discarded, so it can neither be skipped nor delivered as `Binary` instead. Clients that
+    /// may send arbitrary bytes should use `Binary` messages.
+    pub async fn recv(&mut self) -> Option<Result<Message, Error>> {
+        self.next().await
+    }
+
+    /// Wait for the next message without consuming it.
+    ///
+    /// The message is returned again by the next call to [`recv`](Self::recv) or any other
+    /// receiving method. Returns `None` if the stream has closed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::{Message, WebSocket};
+    ///
+    /// async fn dispatch(mut socket: WebSocket) {
+    ///     match socket.peek().await {
+    ///         Some(Ok(Message::Binary(_))) => binary_protocol(socket).await,
+    ///         Some(Ok(_)) => text_protocol(socket).await,
+    ///         Some(Err(_)) | None => {}
+    ///     }
+    /// }
+    ///
+    /// async fn binary_protocol(mut socket: WebSocket) {
+    ///     // the first message is still there to be received
+    ///     while let Some(Ok(msg)) = socket.recv().await { /* ... */ }
+    /// }
+    /// # async fn text_protocol(socket: WebSocket) {}
+    /// ```
+    pub async fn peek(&mut self) -> Option<&Result<Message, Error>> {
+        if self.buffered.is_none() {
+            self.buffered = Some(self.next().await?);
+        }
+        self.buffered.as_ref()
+    }
+
+    /// Receive another message, waiting at most `timeout`.
+    ///
+    /// Returns `Err` if no message arrived in time. The socket remains usable afterwards.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::{Message, WebSocket};
+    /// use std::time::Duration;
+    ///
+    /// async fn wait_for_hello(mut socket: WebSocket) {
+    ///     match socket.recv_timeout(Duration::from_secs(5)).await {
+    ///         Ok(Some(Ok(Message::Text(text)))) if text == "hello" => { /* ... */ }
+    ///         Ok(_) => { /* unexpected message or the socket closed */ }
+    ///         Err(_) => { /* timed out */ }
+    ///     }
+    /// }
+    /// ```
+    pub async fn recv_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<Result<Message, Error>>, Elapsed> {
+        tokio::time::timeout(timeout, self.recv()).await
+    }
+
+    /// Receive a message if one is immediately available, without waiting.
+    ///
+    /// Returns `Ok(None)` if no message is ready yet and [`Error::ConnectionClosed`] once the
+    /// stream has closed. This is useful for servers that poll their sockets on a fixed tick.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::WebSocket;
+    /// use std::time::Duration;
+    ///
+    /// async fn game_loop(mut socket: WebSocket) {
+    ///     let mut tick = tokio::time::interval(Duration::from_millis(50));
+    ///     loop {
+    ///         tick.tick().await;
+    ///         loop {
+    ///             match socket.try_recv() {
+    ///                 Ok(Some(msg)) => { /* apply input */ }
+    ///                 Ok(None) => break,
+    ///                 Err(_) => return,
+    ///             }
+    ///         }
+    ///         // advance the simulation
+    ///     }
+    /// }
+    /// ```
+    #[allow(clippy::result_large_err)]
+    pub fn try_recv(&mut self) -> Result<Option<Message>, Error> {
+        match self.next().now_or_never() {
+            Some(Some(result)) => result.map(Some),
+            Some(None) => Err(Error::ConnectionClosed),
+            None => Ok(None),
+        }
+    }
+
+    /// Receive all immediately available messages, up to `limit`, into `buffer`.
+    ///
+    /// Waits until at least one message is available and then takes as many more as are
+    /// ready without waiting, like [`tokio::sync::mpsc::Receiver::recv_many`]. Returns the
+    /// number of messages added to `buffer`, which is `0` if the stream has closed or `limit`
+    /// is `0`.
+    ///
+    /// An error that occurs after some messages have been received is returned by the next
+    /// call.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::{Error, WebSocket};
+    ///
+    /// async fn process_in_batches(mut socket: WebSocket) -> Result<(), Error> {
+    ///     let mut batch = Vec::with_capacity(64);
+    ///     while socket.recv_many(&mut batch, 64).await? > 0 {
+    ///         // handle the whole batch at once
+    ///         batch.clear();
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn recv_many(
+        &mut self,
+        buffer: &mut Vec<Message>,
+        limit: usize,
+    ) -> Result<usize, Error> {
+        if limit == 0 {
+            return Ok(0);
+        }
+
+        match self.recv().await {
+            Some(Ok(msg)) => buffer.push(msg),
+            Some(Err(err)) => return Err(err),
+            None => return Ok(0),
+        }
+
+        let mut received = 1;
+        while received < limit {
+            match self.next().now_or_never() {
+                Some(Some(Ok(msg))) => {
+                    buffer.push(msg);
+                    received += 1;
+                }
+                Some(Some(Err(err))) => {
+                    self.buffered = Some(Err(err));
+                    break;
+                }
+                Some(None) | None => break,
+            }
+        }
+        Ok(received)
+    }
+
+    /// Send a message.
+    ///
+    /// With [`SlowConsumerPolicy::drop_messages`], sending a `Text` or `Binary` message returns
+    /// without waiting for the flush once the client is stalled.
     pub async fn send(&mut self, msg: Message) -> Result<(), Error> {
-        self.inner.send(msg).await
+        poll_fn(|cx| Pin::new(&mut *self).poll_ready(cx)).await?;
+        let data = is_data(&msg);
+        Pin::new(&mut *self).start_send(msg)?;
+        poll_fn(|cx| match Pin::new(&mut *self).poll_flush(cx) {
+            Poll::Pending if data && self.dropping() => Poll::Ready(Ok(())),
+            poll => poll,
+        })
+        .await
+    }
+
+    /// Send a message, waiting at most `timeout` for it to be written.
+    ///
+    /// Returns `Err` if the client didn't accept the message in time, for example because it
+    /// stopped reading. The message may or may not have been buffered for sending at that
+    /// point.
+    ///
+    /// To disconnect a stuck client, drop the socket. Closing it gracefully with
+    /// [`close`](Self::close) would wait on the same client.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::{Message, WebSocket};
+    /// use std::time::Duration;
+    ///
+    /// async fn broadcast(mut socket: WebSocket, msg: Message) -> Option<WebSocket> {
+    ///     match socket.send_timeout(msg, Duration::from_secs(5)).await {
+    ///         Ok(Ok(())) => Some(socket),
+    ///         // the client is gone or too slow, drop the connection
+    ///         Ok(Err(_)) | Err(_) => None,
+    ///     }
+    /// }
+    /// ```
+    pub async fn send_timeout(
+        &mut self,
+        msg: Message,
+        timeout: Duration,
+    ) -> Result<Result<(), Error>, Elapsed> {
+        tokio::time::timeout(timeout, self.send(msg)).await
+    }
+
+    /// Queue a message without flushing.
+    ///
+    /// The message is written once the write buffer exceeds
+    /// [`write_buffer_size`](WebSocketUpgrade::write_buffer_size) or when [`flush`](Self::flush)
+    /// is called. This is more efficient than [`send`](Self::send) when writing bursts of
+    /// messages.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::{Error, Message, WebSocket};
+    ///
+    /// async fn send_all(socket: &mut WebSocket, msgs: Vec<Message>) -> Result<(), Error> {
+    ///     for msg in msgs {
+    ///         socket.feed(msg).await?;
+    ///     }
+    ///     socket.flush().await
+    /// }
+    /// ```
+    pub async fn feed(&mut self, msg: Message) -> Result<(), Error> {
+        SinkExt::feed(self, msg).await
+    }
+
+    /// Wait until the socket can accept another message.
+    ///
+    /// Like [`tokio::sync::mpsc::Sender::reserve`], this allows producers to wait for capacity
+    /// before building an expensive message. The message passed to [`SendPermit::send`] is
+    /// queued like with [`feed`](Self::feed).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::{Error, Message, WebSocket};
+    ///
+    /// async fn stream_snapshots(mut socket: WebSocket) -> Result<(), Error> {
+    ///     loop {
+    ///         let permit = socket.reserve().await?;
+    ///         // only render the snapshot once it can actually be sent
+    ///         permit.send(Message::Binary(render_snapshot()))?;
+    ///         socket.flush().await?;
+    ///     }
+    /// }
+    /// # fn render_snapshot() -> Vec<u8> { Vec::new() }
+    /// ```
+    pub async fn reserve(&mut self) -> Result<SendPermit<'_>, Error> {
+        poll_fn(|cx| self.poll_ready_unpin(cx)).await?;
+        Ok(SendPermit::new(self))
+    }
+
+    /// Write all queued messages.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        SinkExt::flush(self).await
+    }
+
+    /// Get a cloneable handle for queueing messages from other tasks.
+    ///
+    /// Messages sent through the handle are queued and written while the socket is polled, for
+    /// example by [`recv`](Self::recv) or [`send`](Self::send), so keep receiving on the socket
+    /// for them to go out. See [`QueuedSender`] for details. The handle stops working once the
+    /// socket is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::{Message, WebSocket};
+    /// use std::time::Duration;
+    ///
+    /// async fn handle(mut socket: WebSocket) {
+    ///     let sender = socket.queued_sender();
+    ///     tokio::spawn(async move {
+    ///         loop {
+    ///             tokio::time::sleep(Duration::from_secs(1)).await;
+    ///             if sender.send(Message::Text("tick".into())).await.is_err() {
+    ///                 break;
+    ///             }
+    ///         }
+    ///     });
+    ///
+    ///     while let Some(Ok(msg)) = socket.recv().await {
+    ///         // replies sent directly on the socket are interleaved with the ticks
+    ///         if socket.send(msg).await.is_err() {
+    ///             break;
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn queued_sender(&mut self) -> QueuedSender {
+        self.outgoing.get_or_insert_with(Outgoing::new).sender()
+    }
+
+    /// Record all messages sent and received from now on to `sink`.
+    ///
+    /// Replaces any previous sink. See the [`transcript`] module for details.
+    pub fn record<S>(&mut self, sink: S)
+    where
+        S: transcript::TranscriptSink,
+    {
+        self.telemetry.recorder = Some(transcript::Recorder::new(sink));
+    }
+
+    /// Check inbound JSON messages with `validator` before they are returned.
+    ///
+    /// Replaces any previous validator. See the [`validate`] module for details.
+    #[cfg(feature = "json")]
+    pub fn validate_json<F>(&mut self, validator: F, on_invalid: validate::OnInvalid)
+    where
+        F: Fn(&serde_json::Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.validator = Some(validate::Validator::new(validator, on_invalid));
+    }
+
+    /// Convert this socket into a [`Stream`] and [`Sink`] with [`io::Error`] as the error type.
+    ///
+    /// See [`transport::IoCompat`] for details.
+    pub fn into_io_compat(self) -> transport::IoCompat {
+        transport::IoCompat::new(self)
+    }
+
+    /// Convert this socket into a byte stream over `Binary` messages.
+    ///
+    /// See [`transport::ByteStream`] for details.
+    pub fn into_byte_stream(self) -> transport::ByteStream {
+        transport::ByteStream::new(self)
+    }
+
+    /// Gracefully close this WebSocket.
+    ///
+    /// If [`WebSocketUpgrade::close_linger`] is set, messages queued through [`QueuedSender`]s are
+    /// written first.
+    pub async fn close(self) -> Result<(), Error> {
+        self.close_inner(None, true).await
+    }
+
+    /// Gracefully close this WebSocket without writing the messages queued through
+    /// [`QueuedSender`]s.
+    pub async fn close_now(self) -> Result<(), Error> {
+        self.close_inner(None, false).await
+    }
+
+    /// Gracefully close this WebSocket with the given close code and reason.
+    ///
+    /// Like [`close`](Self::close), this honors [`WebSocketUpgrade::close_linger`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::{CloseCode, Error, WebSocket};
+    ///
+    /// async fn shutdown(socket: WebSocket) -> Result<(), Error> {
+    ///     socket.close_with(CloseCode::Away, "server is restarting").await
+    /// }
+    /// ```
+    pub async fn close_with(
+        self,
+        code: CloseCode,
+        reason: impl Into<Cow<'static, str>>,
+    ) -> Result<(), Error> {
+        let frame = CloseFrame {
+            code,
+            reason: reason.into(),
+        };
+        self.close_inner(Some(frame), true).await
+    }
+
+    /// Send a `Close` frame and flush it, giving up once the close timeout elapses.
+    async fn close_inner(
+        mut self,
+        frame: Option<CloseFrame<'static>>,
+        linger: bool,
+    ) -> Result<(), Error> {
+        if self.terminated {
+            return Err(Error::AlreadyClosed);
+        }
+        if linger {
+            self.linger().await;
+        }
+        let msg = Message::Close(frame.clone());
+        self.telemetry.record_sent(&msg);
+        self.telemetry.record_close(&msg);
+        self.arm_close_deadline();
+
+        let this = &mut self;
+        let close = this.inner.close(frame);
+        match &mut this.close_deadline {
+            Some(deadline) => tokio::select! {
+                result = close => result,
+                _ = deadline.as_mut() => {
+                    let err = Error::Io(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        Timeout::CloseHandshake,
+                    ));
+                    this.telemetry.record_error(&err);
+                    Err(err)
+                }
+            },
+            None => close.await,
+        }
+    }
+
+    /// Wait for the client to complete the closing handshake.
+    ///
+    /// Call this after sending a `Close` frame. Incoming messages are discarded until the
+    /// client's `Close` frame arrives or the connection ends. Returns the client's close frame,
+    /// or `None` if it didn't send one.
+    ///
+    /// Use [`WebSocketUpgrade::close_timeout`] to stop waiting on clients that never answer.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::{CloseCode, CloseFrame, Error, Message, WebSocket};
+    ///
+    /// async fn kick(mut socket: WebSocket) -> Result<(), Error> {
+    ///     let frame = CloseFrame {
+    ///         code: CloseCode::Policy,
+    ///         reason: "kicked by an admin".into(),
+    ///     };
+    ///     socket.send(Message::Close(Some(frame))).await?;
+    ///     let reply = socket.await_close().await?;
+    ///     println!("client acknowledged with {:?}", reply);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn await_close(&mut self) -> Result<Option<CloseFrame<'static>>, Error> {
+        while let Some(msg) = self.recv().await {
+            match msg {
+                Ok(Message::Close(frame)) => {
+                    // tungstenite's reply to a close initiated by the client goes out on flush
+                    let _ = self.flush().await;
+                    return Ok(frame);
+                }
+                Ok(_) => {}
+                Err(Error::ConnectionClosed) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Return the selected WebSocket subprotocol, if one has been chosen.
+    pub fn protocol(&self) -> Option<&HeaderValue> {
+        self.protocol.as_ref()
+    }
+
+    /// Return the selected WebSocket subprotocol as a string, if one has been chosen.
+    pub fn protocol_str(&self) -> Option<&str> {
+        self.protocol
+            .as_ref()
+            .and_then(|protocol| protocol.to_str().ok())
+    }
+
+    /// Parse the selected WebSocket subprotocol into `P`.
+    ///
+    /// Returns `None` if no protocol was chosen or it fails to parse. See
+    /// [`WebSocketUpgrade::selected_protocol_as`] for an example.
+    pub fn protocol_as<P>(&self) -> Option<P>
+    where
+        P: FromStr,
+    {
+        self.protocol_str()?.parse().ok()
+    }
+
+    /// Return the extensions negotiated for this connection.
+    ///
+    /// These are the extensions accepted with [`WebSocketUpgrade::accept_extensions`].
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    /// The unique ID of this connection.
+    ///
+    /// See [`ConnectionId`] for more details.
+    pub fn connection_id(&self) -> ConnectionId {
+        self.telemetry.connection_id
+    }
+
+    /// Metadata about the underlying connection, if the server provided any.
+    ///
+    /// See [`ConnectionInfo`] for more details.
+    pub fn connection_info(&self) -> Option<&ConnectionInfo> {
+        self.connection_info.as_ref()
+    }
+
+    /// The address of the remote peer.
+    ///
+    /// This requires serving the app with
+    /// [`Router::into_make_service_with_connect_info::<SocketAddr>`][connect-info]. Returns
+    /// `None` otherwise, for example when serving over a Unix domain socket.
+    ///
+    /// [connect-info]: axum::Router::into_make_service_with_connect_info
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// The snapshot of the request kept with [`WebSocketUpgrade::keep_request_parts`] or
+    /// [`WebSocketUpgrade::keep_extension`].
+    pub fn request_parts(&self) -> Option<&Parts> {
+        self.request.as_ref()
+    }
+
+    /// The cookies sent with the upgrade request.
+    ///
+    /// See [`WebSocketUpgrade::cookies`] for more details.
+    #[cfg(feature = "cookie")]
+    pub fn cookies(&self) -> &cookie::CookieJar {
+        &self.cookies
+    }
+
+    /// Send a `Ping` for measuring the round trip time to the client.
+    ///
+    /// The measurement completes when the client's `Pong` is received, so keep receiving on
+    /// the socket. The `Pong` is returned from [`recv`](Self::recv) like any other message.
+    /// Sending another ping before the `Pong` arrives abandons the previous measurement.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum_tungstenite::WebSocket;
+    /// use std::time::Duration;
+    ///
+    /// async fn handle(mut socket: WebSocket) {
+    ///     let mut interval = tokio::time::interval(Duration::from_secs(10));
+    ///     loop {
+    ///         tokio::select! {
+    ///             _ = interval.tick() => {
+    ///                 if socket.send_rtt_ping().await.is_err() {
+    ///                     break;
+    ///                 }
+    ///                 if let Some(rtt) = socket.rtt() {
+    ///                     println!("round trip time: {:?}", rtt.smoothed());
+    ///                 }
+    ///             }
+    ///             msg = socket.recv() => match msg {
+    ///                 Some(Ok(msg)) => { /* ... */ }
+    ///                 _ => break,
+    ///             },
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub async fn send_rtt_ping(&mut self) -> Result<(), Error> {
+        let ping = self.rtt.ping();
+        self.send(ping).await
+    }
+
+    /// The round trip times measured with [`send_rtt_ping`](Self::send_rtt_ping), if any.
+    pub fn rtt(&self) -> Option<Rtt> {
+        self.rtt.rtt()
+    }
+
+    /// Counters of the messages and bytes sent and received so far.
+    ///
+    /// See [`SocketStats`] for what is counted.
+    pub fn stats(&self) -> SocketStats {
+        self.telemetry.stats
+    }
+
+    /// When the last message, including control frames, was received.
+    ///
+    /// `None` if nothing has been received yet.
+    pub fn last_received_at(&self) -> Option<Instant> {
+        self.telemetry.last_received_at
+    }
+
+    /// When the last message, including control frames, was sent.
+    ///
+    /// `None` if nothing has been sent yet. `Pong`s sent automatically in reply to `Ping`s
+    /// aren't included.
+    pub fn last_sent_at(&self) -> Option<Instant> {
+        self.telemetry.last_sent_at
+    }
+
+    /// The state of the connection.
+    ///
+    /// This is cheap and doesn't touch the connection, so it reflects what the socket has
+    /// observed so far. A client that disappears without closing is only noticed once reading
+    /// or writing fails.
+    pub fn state(&self) -> ConnectionState {
+        if self.terminated {
+            return ConnectionState::Closed;
+        }
+        self.telemetry.state()
+    }
+
+    /// Returns `true` if the connection is [`ConnectionState::Open`].
+    pub fn is_open(&self) -> bool {
+        self.state() == ConnectionState::Open
+    }
+
+    /// Returns a future that resolves once the connection is closed.
+    ///
+    /// The future doesn't borrow the socket, so it can be moved into tasks spawned for the
+    /// connection to stop them when it ends. The socket notices the connection closing while it
+    /// is being received from or sent to, and when it is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::WebSocketUpgrade;
+    /// use std::time::Duration;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     ws.on_upgrade(|mut socket| async move {
+    ///         let closed = socket.closed();
+    ///         tokio::spawn(async move {
+    ///             tokio::select! {
+    ///                 _ = closed => {}
+    ///                 _ = refresh_forever() => {}
+    ///             }
+    ///         });
+    ///
+    ///         while let Some(Ok(msg)) = socket.recv().await {
+    ///             // ...
+    ///         }
+    ///     })
+    /// }
+    /// #
+    /// # async fn refresh_forever() {}
+    /// ```
+    pub fn closed(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut closed = self.closed.subscribe();
+        let already_closed = self.state() == ConnectionState::Closed;
+        async move {
+            if already_closed {
+                return;
+            }
+            while !*closed.borrow_and_update() {
+                if closed.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Returns a [`CancellationToken`] that is cancelled once the connection is closed.
+    ///
+    /// Like [`closed`](Self::closed), but for code that is already structured around
+    /// cancellation tokens. All calls return clones of the same token. Use
+    /// [`CancellationToken::child_token`] to derive tokens that can also be cancelled
+    /// separately.
+    ///
+    /// Requires the `tokio-util` feature.
+    #[cfg(feature = "tokio-util")]
+    pub fn cancellation_token(&mut self) -> CancellationToken {
+        if let Some((token, _)) = &self.cancellation {
+            return token.clone();
+        }
+        let token = CancellationToken::new();
+        if self.state() == ConnectionState::Closed {
+            token.cancel();
+        } else {
+            self.cancellation = Some((token.clone(), token.clone().drop_guard()));
+        }
+        token
+    }
+
+    /// The messages written to the socket that haven't been flushed to the client yet.
+    ///
+    /// This includes messages sent through [`QueuedSender`]s once the socket has picked them up, but
+    /// not those still waiting in their queue. A growing backlog means the client isn't keeping
+    /// up, which can be used to skip optional updates:
+    ///
+    /// ```
+    /// use axum_tungstenite::{Message, WebSocket};
+    ///
+    /// async fn publish(socket: &mut WebSocket, update: String, optional: bool) {
+    ///     if optional && socket.pending().bytes() > 64 * 1024 {
+    ///         return;
+    ///     }
+    ///     let _ = socket.send(Message::Text(update)).await;
+    /// }
+    /// ```
+    pub fn pending(&self) -> PendingWrites {
+        self.telemetry.unflushed
+    }
+}
+
+impl WebSocket {
+    /// Fail like a severed connection once [`testing::Faults::sever_after`] has been reached.
+    #[cfg(feature = "testing")]
+    #[allow(clippy::result_large_err)]
+    fn check_severed(&mut self) -> Result<(), Error> {
+        match self.faults.as_ref().and_then(testing::FaultState::severed) {
+            Some(err) => {
+                self.terminated = true;
+                self.notify_closed();
+                let err = Error::Io(err);
+                self.telemetry.record_error(&err);
+                Err(err)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Resolve [`closed`](Self::closed) futures once the connection is closed.
+    fn notify_closed(&mut self) {
+        if self.state() != ConnectionState::Closed {
+            return;
+        }
+        self.closed.send_replace(true);
+        #[cfg(feature = "tokio-util")]
+        drop(self.cancellation.take());
+    }
+
+    /// Whether data messages are dropped instead of sent because the client is stalled.
+    fn dropping(&self) -> bool {
+        self.slow_consumer
+            .as_ref()
+            .is_some_and(|slow| slow.dropping)
+    }
+
+    /// Apply the slow consumer policy to a write that couldn't make progress.
+    #[allow(clippy::result_large_err)]
+    fn check_slow_consumer(
+        &mut self,
+        poll: Poll<Result<(), Error>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let Some(slow_consumer) = &mut self.slow_consumer else {
+            return poll;
+        };
+        if poll.is_ready() {
+            slow_consumer.progress();
+            return poll;
+        }
+        match ready!(slow_consumer.poll_stalled(cx)) {
+            Stalled::Skip => Poll::Ready(Ok(())),
+            Stalled::Terminate => {
+                self.terminated = true;
+                self.notify_closed();
+                let err = Error::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    Timeout::SlowConsumer,
+                ));
+                self.telemetry.record_error(&err);
+                Poll::Ready(Err(err))
+            }
+        }
+    }
+
+    /// Write the messages queued through `QueuedSender`s, waiting at most `close_linger`.
+    async fn linger(&mut self) {
+        let linger = match self.close_linger {
+            Some(linger) => linger,
+            None => return,
+        };
+
+        let drain = poll_fn(|cx| {
+            let this = &mut *self;
+            match &mut this.outgoing {
+                Some(outgoing) => {
+                    outgoing.poll_write(Pin::new(&mut *this.inner), &mut this.telemetry, cx);
+                    if outgoing.is_drained() {
+                        Poll::Ready(())
+                    } else {
+                        Poll::Pending
+                    }
+                }
+                None => Poll::Ready(()),
+            }
+        });
+        let _ = tokio::time::timeout(linger, drain).await;
+    }
+
+    /// Start the close timeout, unless it's already running.
+    fn arm_close_deadline(&mut self) {
+        if let (Some(timeout), None) = (self.close_timeout, &self.close_deadline) {
+            self.close_deadline = Some(Box::pin(tokio::time::sleep(timeout)));
+        }
+    }
+
+    /// Start closing the connection without waiting, for use while polling.
+    fn send_close_frame(&mut self, code: CloseCode, cx: &mut Context<'_>) {
+        let frame = CloseFrame {
+            code,
+            reason: "".into(),
+        };
+        self.send_now(Message::Close(Some(frame)), cx);
+    }
+
+    /// Send `msg` if the socket can accept it without waiting, otherwise drop it.
+    fn send_now(&mut self, msg: Message, cx: &mut Context<'_>) {
+        let mut inner = Pin::new(&mut *self.inner);
+        if let Poll::Ready(Ok(())) = inner.as_mut().poll_ready(cx) {
+            if inner.as_mut().start_send(msg).is_ok() {
+                let _ = inner.poll_flush(cx);
+            }
+        }
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.as_mut().poll_recv(cx);
+        if poll.is_ready() {
+            self.notify_closed();
+        }
+        poll
+    }
+}
+
+impl WebSocket {
+    fn poll_recv(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message, Error>>> {
+        if let Some(item) = self.buffered.take() {
+            return Poll::Ready(Some(item));
+        }
+
+        if self.terminated {
+            return Poll::Ready(None);
+        }
+
+        #[cfg(feature = "testing")]
+        if let Err(err) = self.check_severed() {
+            return Poll::Ready(Some(Err(err)));
+        }
+
+        let this = &mut *self;
+        if let Some(outgoing) = &mut this.outgoing {
+            outgoing.poll_write(Pin::new(&mut *this.inner), &mut this.telemetry, cx);
+        }
+
+        // loops past messages that fail validation
+        #[cfg_attr(not(feature = "json"), allow(clippy::never_loop))]
+        while let Poll::Ready(item) = self.inner.poll_next_unpin(cx) {
+            if let Some(liveness) = &mut self.liveness {
+                liveness.reset();
+            }
+            match &item {
+                Some(Ok(msg)) => {
+                    self.telemetry.record_close(msg);
+                    self.rtt.record_pong(msg);
+                    #[cfg(feature = "testing")]
+                    if let Some(faults) = &mut self.faults {
+                        faults.received();
+                    }
+                }
+                Some(Err(err)) => {
+                    self.telemetry.record_error(err);
+                    self.telemetry.record_ended();
+                }
+                None => self.telemetry.record_ended(),
+            }
+            if let Some(Ok(msg)) = &item {
+                if let Some(exceeded) = self.quotas.check(&self.telemetry.stats, msg) {
+                    self.terminated = true;
+                    self.telemetry.record_error(&exceeded);
+                    self.send_close_frame(exceeded.close_code(), cx);
+                    return Poll::Ready(Some(Err(Error::Io(io::Error::other(exceeded)))));
+                }
+                self.telemetry.record_received(msg);
+
+                #[cfg(feature = "json")]
+                if let Some((on_invalid, reason)) = self.validator.as_ref().and_then(|validator| {
+                    let reason = validator.check(msg).err()?;
+                    Some((validator.on_invalid, reason))
+                }) {
+                    match on_invalid {
+                        validate::OnInvalid::Reply => {
+                            self.send_now(validate::Validator::error_reply(&reason), cx);
+                            continue;
+                        }
+                        validate::OnInvalid::Close => {
+                            self.terminated = true;
+                            let err = Error::Io(io::Error::new(io::ErrorKind::InvalidData, reason));
+                            self.telemetry.record_error(&err);
+                            self.send_close_frame(CloseCode::Invalid, cx);
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    }
+                }
+            }
+            return Poll::Ready(item);
+        }
+
+        if let Some(liveness) = &mut self.liveness {
+            if liveness.sleep.as_mut().poll(cx).is_ready() {
+                self.terminated = true;
+                let err = Error::Io(io::Error::new(io::ErrorKind::TimedOut, Timeout::Liveness));
+                self.telemetry.record_error(&err);
+                self.send_close_frame(CloseCode::Away, cx);
+                let _ = Pin::new(self.inner.get_mut()).poll_shutdown(cx);
+                return Poll::Ready(Some(Err(err)));
+            }
+        }
+
+        if let Some(deadline) = &mut self.close_deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                self.terminated = true;
+                let err = Error::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    Timeout::CloseHandshake,
+                ));
+                self.telemetry.record_error(&err);
+                return Poll::Ready(Some(Err(err)));
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Sink<Message> for WebSocket {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.terminated {
+            return Poll::Ready(Err(Error::AlreadyClosed));
+        }
+        #[cfg(feature = "testing")]
+        {
+            self.check_severed()?;
+            if let Some(faults) = &mut self.faults {
+                ready!(faults.poll_send_delay(cx));
+            }
+        }
+        let poll = Pin::new(&mut *self.inner).poll_ready(cx);
+        self.check_slow_consumer(poll, cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        if self.terminated {
+            return Err(Error::AlreadyClosed);
+        }
+        if is_data(&item) && self.dropping() {
+            return Ok(());
+        }
+        self.telemetry.record_sent(&item);
+        self.telemetry.record_close(&item);
+        if let Message::Close(_) = item {
+            self.arm_close_deadline();
+        }
+        #[cfg(feature = "testing")]
+        if let Some(faults) = &mut self.faults {
+            if !faults.sent(matches!(item, Message::Text(_) | Message::Binary(_))) {
+                return Ok(());
+            }
+        }
+        Pin::new(&mut *self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.terminated {
+            return Poll::Ready(Err(Error::AlreadyClosed));
+        }
+        let this = &mut *self;
+        if let Some(outgoing) = &mut this.outgoing {
+            outgoing.poll_write(Pin::new(&mut *this.inner), &mut this.telemetry, cx);
+        }
+        #[cfg(feature = "testing")]
+        if let Some(faults) = &mut self.faults {
+            ready!(faults.poll_flush_delay(cx));
+        }
+        let poll = Pin::new(&mut *self.inner).poll_flush(cx);
+        if let Poll::Ready(Ok(())) = poll {
+            self.telemetry.record_flushed();
+        }
+        if poll.is_pending() {
+            // the policy may end the connection, but skipping doesn't make the buffered data
+            // any less pending
+            ready!(self.check_slow_consumer(poll, cx))?;
+            return Poll::Pending;
+        }
+        ready!(self.check_slow_consumer(poll, cx))?;
+        #[cfg(feature = "testing")]
+        if let Some(faults) = &mut self.faults {
+            faults.flushed();
+        }
+        self.notify_closed();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.terminated {
+            return Poll::Ready(Err(Error::AlreadyClosed));
+        }
+        let result = ready!(Pin::new(&mut *self.inner).poll_close(cx));
+        self.notify_closed();
+        Poll::Ready(result)
+    }
+}
+
+fn is_data(msg: &Message) -> bool {
+    matches!(msg, Message::Text(_) | Message::Binary(_))
+}
+
+/// What to do when a connection upgrade fails.
+///
+/// See [`WebSocketUpgrade::on_failed_upgrade`] for more details.
+pub trait OnFailedUpdgrade: Send + 'static {
+    /// Call the callback.
+    fn call(self, error: hyper::Error);
+}
+
+impl<F> OnFailedUpdgrade for F
+where
+    F: FnOnce(hyper::Error) + Send + 'static,
+{
+    fn call(self, error: hyper::Error) {
+        self(error)
+    }
+}
+
+/// The default `OnFailedUpdgrade` used by `WebSocketUpgrade`.
+///
+/// It simply ignores the error.
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct DefaultOnFailedUpdgrade;
+
+impl OnFailedUpdgrade for DefaultOnFailedUpdgrade {
+    #[inline]
+    fn call(self, _error: hyper::Error) {}
+}
+
+pub mod actor;
+pub mod auth;
+#[cfg(feature = "client")]
+pub mod client;
+mod close;
+#[cfg(feature = "axum-ws")]
+pub mod compat;
+mod connection_id;
+mod connection_info;
+mod defaults;
+mod deferred;
+mod error_policy;
+pub mod handshake;
+#[cfg(feature = "jsonrpc")]
+pub mod jsonrpc;
+#[cfg(feature = "auth-jwt")]
+pub mod jwt;
+#[cfg(feature = "tracing")]
+mod message_log;
+pub mod mux;
+pub mod origin;
+#[cfg(feature = "otel")]
+mod otel;
+mod parts;
+pub mod pump;
+#[cfg(feature = "client")]
+pub mod reconnect;
+pub mod routes;
+pub mod rpc;
+mod rtt;
+mod sender;
+pub mod service;
+#[cfg(feature = "session")]
+pub mod session;
+mod slow;
+mod stats;
+#[cfg(feature = "stomp")]
+pub mod stomp;
+#[cfg(test)]
+mod test_helpers;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod timeout;
+pub mod transcript;
+pub mod transport;
+#[cfg(feature = "json")]
+pub mod typed;
+#[cfg(feature = "headers")]
+pub mod typed_headers;
+#[cfg(feature = "json")]
+pub mod validate;
+
+pub mod rejection {
+    //! WebSocket specific rejections.
+
+    use super::*;
+    use axum_core::BoxError;
+    use bytes::Bytes;
+    use futures_util::ready;
+    use http::{Method, Request};
+    use pin_project_lite::pin_project;
+    use std::marker::PhantomData;
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    macro_rules! define_rejection {
+        (
+            $(#[headers = $headers:expr])?
+            #[status = $status:ident]
+            #[body = $body:expr]
+            $(#[$m:meta])*
+            pub struct $name:ident;
+        ) => {
+            $(#[$m])*
+            #[derive(Debug)]
+            #[non_exhaustive]
+            pub struct $name;
+
+            define_rejection!(@impls $name, $status, $body, [$($headers)?]);
+        };
+
+        (
+            $(#[headers = $headers:expr])?
+            #[status = $status:ident]
+            #[body = $body:expr]
+            $(#[$m:meta])*
+            pub struct $name:ident {
+                $($field:ident: $ty:ty),+
+                $(,)?
+            }
+        ) => {
+            $(#[$m])*
+            #[derive(Debug)]
+            pub struct $name {
+                $(pub(crate) $field: $ty,)+
+            }
+
+            define_rejection!(@impls $name, $status, $body, [$($headers)?]);
+        };
+
+        (@impls $name:ident, $status:ident, $body:expr, [$($headers:expr)?]) => {
+            impl $name {
+                /// Get the response body text used for this rejection.
+                pub fn body_text(&self) -> String {
+                    $body.into()
+                }
+
+                /// Get the status code used for this rejection.
+                pub fn status(&self) -> http::StatusCode {
+                    http::StatusCode::$status
+                }
+
+                /// Get the headers included in the response for this rejection.
+                pub fn headers(&self) -> HeaderMap {
+                    #[allow(unused_mut)]
+                    let mut headers = HeaderMap::new();
+                    $(
+                        for (name, value) in $headers {
+                            headers.insert(name, HeaderValue::from_static(value));
+                        }
+                    )?
+                    headers
+                }
+            }
+
+            impl IntoResponse for $name {
+                fn into_response(self) -> Response {
+                    (self.status(), self.headers(), self.body_text()).into_response()
+                }
+            }
+
+            impl std::fmt::Display for $name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(f, "{}", $body)
+                }
+            }
+
+            impl std::error::Error for $name {}
+        };
+    }
+
+    define_rejection! {
+        #[status = METHOD_NOT_ALLOWED]
+        #[body = "Request method must be `GET`"]
+        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
+        pub struct MethodNotGet {
+            method: Method,
+        }
+    }
+
+    impl MethodNotGet {
+        /// The method the request was sent with.
+        pub fn method(&self) -> &Method {
+            &self.method
+        }
+    }
+
+    define_rejection! {
+        #[status = BAD_REQUEST]
+        #[body = "Connection header did not include 'upgrade'"]
+        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
+        pub struct InvalidConnectionHeader {
+            value: Option<HeaderValue>,
+        }
+    }
+
+    impl InvalidConnectionHeader {
+        /// The `Connection` header sent by the client, if any.
+        pub fn value(&self) -> Option<&HeaderValue> {
+            self.value.as_ref()
+        }
+    }
+
+    define_rejection! {
+        #[status = BAD_REQUEST]
+        #[body = "`Upgrade` header did not include 'websocket'"]
+        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
+        pub struct InvalidUpgradeHeader {
+            value: Option<HeaderValue>,
+        }
+    }
+
+    impl InvalidUpgradeHeader {
+        /// The `Upgrade` header sent by the client, if any.
+        pub fn value(&self) -> Option<&HeaderValue> {
+            self.value.as_ref()
+        }
+    }
+
+    define_rejection! {
+        #[headers = [(header::SEC_WEBSOCKET_VERSION, "13")]]
+        #[status = BAD_REQUEST]
+        #[body = "`Sec-WebSocket-Version` header did not include '13'"]
+        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
+        pub struct InvalidWebSocketVersionHeader {
+            value: Option<HeaderValue>,
+        }
+    }
+
+    impl InvalidWebSocketVersionHeader {
+        /// The `Sec-WebSocket-Version` header sent by the client, if any.
+        pub fn value(&self) -> Option<&HeaderValue> {
+            self.value.as_ref()
+        }
+    }
+
+    define_rejection! {
+        #[status = BAD_REQUEST]
+        #[body = "`Sec-WebSocket-Key` header missing"]
+        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
+        pub struct WebSocketKeyHeaderMissing;
+    }
+
+    define_rejection! {
+        #[status = UPGRADE_REQUIRED]
+        #[body = "WebSocket request couldn't be upgraded since no upgrade state was present"]
+        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
+        ///
+        /// This rejection is returned if the connection cannot be upgraded, for example because
+        /// the request was sent over HTTP/2 or wasn't received through hyper.
+        pub struct ConnectionNotUpgradable;
+    }
+
+    define_rejection! {
+        #[status = FORBIDDEN]
+        #[body = "`Origin` header is not allowed"]
+        /// Rejection type for [`WebSocketUpgrade::allowed_origins`](super::WebSocketUpgrade::allowed_origins).
+        pub struct OriginNotAllowed {
+            origin: HeaderValue,
+        }
+    }
+
+    impl OriginNotAllowed {
+        /// The `Origin` header sent by the client.
+        pub fn origin(&self) -> &HeaderValue {
+            &self.origin
+        }
+    }
+
+    define_rejection! {
+        #[status = FORBIDDEN]
+        #[body = "`Origin` header is missing"]
+        /// Rejection type for [`OriginLayer`](crate::origin::OriginLayer).
+        ///
+        /// Returned when [`OriginLayer::allow_missing_origin`] is disabled and the request has
+        /// no `Origin` header.
+        ///
+        /// [`OriginLayer::allow_missing_origin`]: crate::origin::OriginLayer::allow_missing_origin
+        pub struct OriginMissing;
+    }
+
+    define_rejection! {
+        #[status = UNAUTHORIZED]
+        #[body = "Authentication token missing"]
+        /// Rejection type for
+        /// [`AuthenticatedWebSocketUpgrade`](crate::auth::AuthenticatedWebSocketUpgrade).
+        pub struct MissingToken;
+    }
+
+    define_rejection! {
+        #[status = UNAUTHORIZED]
+        #[body = "Authentication token is invalid"]
+        /// Rejection type for
+        /// [`AuthenticatedWebSocketUpgrade`](crate::auth::AuthenticatedWebSocketUpgrade).
+        pub struct InvalidToken;
+    }
+
+    define_rejection! {
+        #[status = INTERNAL_SERVER_ERROR]
+        #[body = "Missing `QueryTokenAuth` request extension. Is it added with `Router::layer(Extension(...))`?"]
+        /// Rejection type for
+        /// [`AuthenticatedWebSocketUpgrade`](crate::auth::AuthenticatedWebSocketUpgrade).
+        ///
+        /// This rejection is returned if no [`QueryTokenAuth`](crate::auth::QueryTokenAuth) for
+        /// the extracted type has been added to the request extensions.
+        pub struct MissingQueryTokenAuth;
+    }
+
+    macro_rules! composite_rejection {
+        (
+            $(#[$m:meta])*
+            pub enum $name:ident {
+                $($variant:ident),+
+                $(,)?
+            }
+        ) => {
+            $(#[$m])*
+            #[derive(Debug)]
+            #[non_exhaustive]
+            pub enum $name {
+                $(
+                    #[allow(missing_docs)]
+                    $variant($variant)
+                ),+
+            }
+
+            impl $name {
+                /// Get the response body text used for this rejection.
+                pub fn body_text(&self) -> String {
+                    match self {
+                        $(
+                            Self::$variant(inner) => inner.body_text(),
+                        )+
+                    }
+                }
+
+                /// Get the status code used for this rejection.
+                pub fn status(&self) -> http::StatusCode {
+                    match self {
+                        $(
+                            Self::$variant(inner) => inner.status(),
+                        )+
+                    }
+                }
+
+                /// Get the headers included in the response for this rejection.
+                pub fn headers(&self) -> HeaderMap {
+                    match self {
+                        $(
+                            Self::$variant(inner) => inner.headers(),
+                        )+
+                    }
+                }
+
+                /// Get the status code and body text used for this rejection.
+                ///
+                /// Useful for building custom responses with a [`RejectionHandler`]. Use
+                /// [`headers`](Self::headers) beforehand to keep the headers of the default
+                /// response.
+                pub fn into_parts(self) -> (http::StatusCode, String) {
+                    (self.status(), self.body_text())
+                }
+            }
+
+            impl IntoResponse for $name {
+                fn into_response(self) -> Response {
+                    let mut res =
+                        (self.status(), self.headers(), self.body_text()).into_response();
+                    // allows `HandleRejection` to find the rejection and replace the response
+                    private::Sealed::put(self, res.extensions_mut());
+                    res
+                }
+            }
+
+            $(
+                impl From<$variant> for $name {
+                    fn from(inner: $variant) -> Self {
+                        Self::$variant(inner)
+                    }
+                }
+            )+
+
+            impl std::fmt::Display for $name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        $(
+                            Self::$variant(inner) => write!(f, "{}", inner),
+                        )+
+                    }
+                }
+            }
+
+            impl std::error::Error for $name {
+                fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                    match self {
+                        $(
+                            Self::$variant(inner) => Some(inner),
+                        )+
+                    }
+                }
+            }
+        };
+    }
+
+    composite_rejection! {
+        /// Rejection used for [`WebSocketUpgrade`](super::WebSocketUpgrade).
+        ///
+        /// Contains one variant for each way the [`WebSocketUpgrade`](super::WebSocketUpgrade)
+        /// extractor can fail.
+        pub enum WebSocketUpgradeRejection {
+            MethodNotGet,
+            InvalidConnectionHeader,
+            InvalidUpgradeHeader,
+            InvalidWebSocketVersionHeader,
+            WebSocketKeyHeaderMissing,
+            ConnectionNotUpgradable,
+            OriginNotAllowed,
+            OriginMissing,
+        }
+    }
+
+    composite_rejection! {
+        /// Rejection used for
+        /// [`AuthenticatedWebSocketUpgrade`](crate::auth::AuthenticatedWebSocketUpgrade).
+        pub enum AuthenticatedWebSocketUpgradeRejection {
+            WebSocketUpgradeRejection,
+            MissingToken,
+            InvalidToken,
+            MissingQueryTokenAuth,
+        }
+    }
+
+    /// Rejections that [`HandleRejectionLayer`] and [`OnRejectLayer`] can find in responses.
+    ///
+    /// Implemented for [`WebSocketUpgradeRejection`] and
+    /// [`AuthenticatedWebSocketUpgradeRejection`]. A
+    /// [`AuthenticatedWebSocketUpgradeRejection::WebSocketUpgradeRejection`] is stored in the
+    /// response as the inner [`WebSocketUpgradeRejection`], so layers for
+    /// [`WebSocketUpgradeRejection`] handle failed handshakes of both extractors, and layers for
+    /// [`AuthenticatedWebSocketUpgradeRejection`] see failed handshakes of
+    /// [`WebSocketUpgrade`](super::WebSocketUpgrade) as well.
+    pub trait UpgradeRejection: private::Sealed + Send + Sync + 'static {}
+
+    impl UpgradeRejection for WebSocketUpgradeRejection {}
+
+    impl UpgradeRejection for AuthenticatedWebSocketUpgradeRejection {}
+
+    mod private {
+        use super::*;
+
+        pub trait Sealed: Sized {
+            fn take(extensions: &mut http::Extensions) -> Option<Self>;
+
+            fn put(self, extensions: &mut http::Extensions);
+        }
+
+        impl Sealed for WebSocketUpgradeRejection {
+            fn take(extensions: &mut http::Extensions) -> Option<Self> {
+                extensions.remove()
+            }
+
+            fn put(self, extensions: &mut http::Extensions) {
+                extensions.insert(self);
+            }
+        }
+
+        impl Sealed for AuthenticatedWebSocketUpgradeRejection {
+            fn take(extensions: &mut http::Extensions) -> Option<Self> {
+                extensions.remove::<Self>().or_else(|| {
+                    extensions
+                        .remove::<WebSocketUpgradeRejection>()
+                        .map(Self::from)
+                })
+            }
+
+            fn put(self, extensions: &mut http::Extensions) {
+                match self {
+                    Self::WebSocketUpgradeRejection(inner) => inner.put(extensions),
+                    rejection => {
+                        extensions.insert(rejection);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Customize the responses produced by [`WebSocketUpgradeRejection`]s, or by any other
+    /// [`UpgradeRejection`].
+    ///
+    /// Install it with [`HandleRejectionLayer`]. It's implemented for closures of the form
+    /// `Fn(R) -> Response`.
+    pub trait RejectionHandler<R = WebSocketUpgradeRejection>:
+        Clone + Send + Sync + 'static
+    {
+        /// Produce the response for `rejection`.
+        fn call(&self, rejection: R) -> Response;
+    }
+
+    impl<F, R> RejectionHandler<R> for F
+    where
+        F: Fn(R) -> Response + Clone + Send + Sync + 'static,
+    {
+        fn call(&self, rejection: R) -> Response {
+            self(rejection)
+        }
+    }
+
+    /// A [`RejectionHandler`] that responds to requests that aren't upgrade requests with
+    /// `426 Upgrade Required` and `Upgrade: websocket`, as suggested by [RFC 7231].
+    ///
+    /// Some API gateways expect this instead of the default `400 Bad Request`. Other rejections
+    /// get their default responses.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::{response::Response, routing::get, Router};
+    /// use axum_tungstenite::{
+    ///     rejection::{upgrade_required, HandleRejectionLayer},
+    ///     WebSocketUpgrade,
+    /// };
+    ///
+    /// let app = Router::new()
+    ///     .route("/ws", get(handler))
+    ///     // use `route_layer` instead to only apply it to some routes
+    ///     .layer(HandleRejectionLayer::new(upgrade_required));
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     ws.on_upgrade(|socket| async { /* ... */ })
+    /// }
+    /// # let _: Router = app;
+    /// ```
+    ///
+    /// [RFC 7231]: https://www.rfc-editor.org/rfc/rfc7231#section-6.5.15
+    pub fn upgrade_required(rejection: WebSocketUpgradeRejection) -> Response {
+        match rejection {
+            WebSocketUpgradeRejection::InvalidConnectionHeader(_)
+            | WebSocketUpgradeRejection::InvalidUpgradeHeader(_) => {
+                let mut res = (
+                    http::StatusCode::UPGRADE_REQUIRED,
+                    [
+                        (header::CONNECTION, "upgrade"),
+                        (header::UPGRADE, "websocket"),
+                    ],
+                    rejection.body_text(),
+                )
+                    .into_response();
+                res.extensions_mut().insert(rejection);
+                res
+            }
+            rejection => rejection.into_response(),
+        }
+    }
+
+    /// [`Layer`] that replaces the responses of [`WebSocketUpgradeRejection`]s using a
+    /// [`RejectionHandler`].
+    ///
+    /// This allows changing the rejection responses, for example to use JSON bodies, without
+    /// reimplementing the extractor. Use a handler taking an
+    /// [`AuthenticatedWebSocketUpgradeRejection`] to also replace the responses of failed
+    /// authentication, see [`UpgradeRejection`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::{
+    ///     response::{IntoResponse, Response},
+    ///     routing::get,
+    ///     Json, Router,
+    /// };
+    /// use axum_tungstenite::{
+    ///     rejection::{HandleRejectionLayer, WebSocketUpgradeRejection},
+    ///     WebSocketUpgrade,
+    /// };
+    ///
+    /// let app = Router::new()
+    ///     .route("/ws", get(handler))
+    ///     .layer(HandleRejectionLayer::new(|rejection: WebSocketUpgradeRejection| {
+    ///         let (status, message) = rejection.into_parts();
+    ///         (status, Json(serde_json::json!({ "error": message }))).into_response()
+    ///     }));
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     ws.on_upgrade(|socket| async { /* ... */ })
+    /// }
+    /// # let _: Router = app;
+    /// ```
+    #[derive(Debug)]
+    pub struct HandleRejectionLayer<H, R = WebSocketUpgradeRejection> {
+        handler: H,
+        _rejection: PhantomData<fn() -> R>,
+    }
+
+    impl<H, R> HandleRejectionLayer<H, R> {
+        /// Create a new `HandleRejectionLayer`.
+        pub fn new(handler: H) -> Self {
+            Self {
+                handler,
+                _rejection: PhantomData,
+            }
+        }
+    }
+
+    impl<H, R> Clone for HandleRejectionLayer<H, R>
+    where
+        H: Clone,
+    {
+        fn clone(&self) -> Self {
+            Self::new(self.handler.clone())
+        }
+    }
+
+    impl<S, H, R> Layer<S> for HandleRejectionLayer<H, R>
+    where
+        H: Clone,
+    {
+        type Service = HandleRejection<S, H, R>;
+
+        fn layer(&self, inner: S) -> Self::Service {
+            HandleRejection {
+                inner,
+                handler: self.handler.clone(),
+                _rejection: PhantomData,
+            }
+        }
+    }
+
+    /// Middleware that replaces the responses of [`WebSocketUpgradeRejection`]s.
+    ///
+    /// Created with [`HandleRejectionLayer`].
+    #[derive(Debug)]
+    pub struct HandleRejection<S, H, R = WebSocketUpgradeRejection> {
+        inner: S,
+        handler: H,
+        _rejection: PhantomData<fn() -> R>,
+    }
+
+    impl<S, H, R> Clone for HandleRejection<S, H, R>
+    where
+        S: Clone,
+        H: Clone,
+    {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+                handler: self.handler.clone(),
+                _rejection: PhantomData,
+            }
+        }
+    }
+
+    impl<S, H, R, ReqBody, ResBody> Service<Request<ReqBody>> for HandleRejection<S, H, R>
+    where
+        S: Service<Request<ReqBody>, Response = http::Response<ResBody>>,
+        H: RejectionHandler<R>,
+        R: UpgradeRejection,
+        ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+        ResBody::Error: Into<BoxError>,
+    {
+        type Response = Response;
+        type Error = S::Error;
+        type Future = HandleRejectionFuture<S::Future, H, R>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+            HandleRejectionFuture {
+                future: self.inner.call(req),
+                handler: Some(self.handler.clone()),
+                _rejection: PhantomData,
+            }
+        }
     }
 
-    /// Gracefully close this WebSocket.
-    pub async fn close(mut self) -> Result<(), Error> {
-        self.inner.close(None).await
+    pin_project! {
+        /// Response future for [`HandleRejection`].
+        pub struct HandleRejectionFuture<F, H, R = WebSocketUpgradeRejection> {
+            #[pin]
+            future: F,
+            handler: Option<H>,
+            _rejection: PhantomData<fn() -> R>,
+        }
     }
 
-    /// Return the selected WebSocket subprotocol, if one has been chosen.
-    pub fn protocol(&self) -> Option<&HeaderValue> {
-        self.protocol.as_ref()
+    impl<F, H, R> std::fmt::Debug for HandleRejectionFuture<F, H, R> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("HandleRejectionFuture")
+                .finish_non_exhaustive()
+        }
     }
-}
 
-impl Stream for WebSocket {
-    type Item = Result<Message, Error>;
+    impl<F, H, R, ResBody, E> Future for HandleRejectionFuture<F, H, R>
+    where
+        F: Future<Output = Result<http::Response<ResBody>, E>>,
+        H: RejectionHandler<R>,
+        R: UpgradeRejection,
+        ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+        ResBody::Error: Into<BoxError>,
+    {
+        type Output = Result<Response, E>;
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        self.inner.poll_next_unpin(cx)
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let this = self.project();
+            let mut res = ready!(this.future.poll(cx))?;
+
+            if let Some(rejection) = R::take(res.extensions_mut()) {
+                let handler = this.handler.take().expect("future polled after completion");
+                return Poll::Ready(Ok(handler.call(rejection)));
+            }
+
+            Poll::Ready(Ok(res.map(axum_core::body::boxed)))
+        }
     }
-}
 
-impl Sink<Message> for WebSocket {
-    type Error = Error;
+    /// [`Layer`] that calls a callback for every request rejected with a
+    /// [`WebSocketUpgradeRejection`].
+    ///
+    /// The callback receives the rejection and the request's method, URI, version, and headers,
+    /// before the response is returned. The response itself isn't changed, use
+    /// [`HandleRejectionLayer`] for that. This is useful for logging and counting failed upgrade
+    /// attempts. Use a callback taking an [`AuthenticatedWebSocketUpgradeRejection`] to also
+    /// observe failed authentication, see [`UpgradeRejection`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::{response::Response, routing::get, Router};
+    /// use axum_tungstenite::{
+    ///     rejection::{OnRejectLayer, WebSocketUpgradeRejection},
+    ///     WebSocketUpgrade,
+    /// };
+    /// use http::request::Parts;
+    ///
+    /// let app = Router::new()
+    ///     .route("/ws", get(handler))
+    ///     .layer(OnRejectLayer::new(|rejection: &WebSocketUpgradeRejection, parts: &Parts| {
+    ///         println!("rejected upgrade to {}: {}", parts.uri, rejection);
+    ///     }));
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     ws.on_upgrade(|socket| async { /* ... */ })
+    /// }
+    /// # let _: Router = app;
+    /// ```
+    #[derive(Debug)]
+    pub struct OnRejectLayer<F, R = WebSocketUpgradeRejection> {
+        callback: F,
+        _rejection: PhantomData<fn() -> R>,
+    }
 
-    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Pin::new(&mut self.inner).poll_ready(cx)
+    impl<F, R> OnRejectLayer<F, R> {
+        /// Create a new `OnRejectLayer`.
+        pub fn new(callback: F) -> Self {
+            Self {
+                callback,
+                _rejection: PhantomData,
+            }
+        }
     }
 
-    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
-        Pin::new(&mut self.inner).start_send(item)
+    impl<F, R> Clone for OnRejectLayer<F, R>
+    where
+        F: Clone,
+    {
+        fn clone(&self) -> Self {
+            Self::new(self.callback.clone())
+        }
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Pin::new(&mut self.inner).poll_flush(cx)
+    impl<S, F, R> Layer<S> for OnRejectLayer<F, R>
+    where
+        F: Clone,
+    {
+        type Service = OnReject<S, F, R>;
+
+        fn layer(&self, inner: S) -> Self::Service {
+            OnReject {
+                inner,
+                callback: self.callback.clone(),
+                _rejection: PhantomData,
+            }
+        }
     }
 
-    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Pin::new(&mut self.inner).poll_close(cx)
+    /// Middleware that calls a callback for rejected upgrade requests.
+    ///
+    /// Created with [`OnRejectLayer`].
+    #[derive(Debug)]
+    pub struct OnReject<S, F, R = WebSocketUpgradeRejection> {
+        inner: S,
+        callback: F,
+        _rejection: PhantomData<fn() -> R>,
     }
-}
 
-fn sign(key: &[u8]) -> HeaderValue {
-    use base64::engine::Engine as _;
+    impl<S, F, R> Clone for OnReject<S, F, R>
+    where
+        S: Clone,
+        F: Clone,
+    {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+                callback: self.callback.clone(),
+                _rejection: PhantomData,
+            }
+        }
+    }
 
-    let mut sha1 = Sha1::default();
-    sha1.update(key);
-    sha1.update(&b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11"[..]);
-    let b64 = Bytes::from(base64::engine::general_purpose::STANDARD.encode(sha1.finalize()));
-    HeaderValue::from_maybe_shared(b64).expect("base64 is a valid value")
-}
+    impl<S, F, R, ReqBody, ResBody> Service<Request<ReqBody>> for OnReject<S, F, R>
+    where
+        S: Service<Request<ReqBody>, Response = http::Response<ResBody>>,
+        F: Fn(&R, &Parts) + Clone,
+        R: UpgradeRejection,
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = OnRejectFuture<S::Future, F, R>;
 
-/// What to do when a connection upgrade fails.
-///
-/// See [`WebSocketUpgrade::on_failed_upgrade`] for more details.
-pub trait OnFailedUpdgrade: Send + 'static {
-    /// Call the callback.
-    fn call(self, error: hyper::Error);
-}
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
 
-impl<F> OnFailedUpdgrade for F
-where
-    F: FnOnce(hyper::Error) + Send + 'static,
-{
-    fn call(self, error: hyper::Error) {
-        self(error)
+        fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+            let (mut parts, _) = Request::new(()).into_parts();
+            parts.method = req.method().clone();
+            parts.uri = req.uri().clone();
+            parts.version = req.version();
+            parts.headers = req.headers().clone();
+
+            OnRejectFuture {
+                future: self.inner.call(req),
+                callback: self.callback.clone(),
+                parts,
+                _rejection: PhantomData,
+            }
+        }
     }
-}
 
-/// The default `OnFailedUpdgrade` used by `WebSocketUpgrade`.
-///
-/// It simply ignores the error.
-#[non_exhaustive]
-#[derive(Debug)]
-pub struct DefaultOnFailedUpdgrade;
+    pin_project! {
+        /// Response future for [`OnReject`].
+        pub struct OnRejectFuture<Fut, F, R = WebSocketUpgradeRejection> {
+            #[pin]
+            future: Fut,
+            callback: F,
+            parts: Parts,
+            _rejection: PhantomData<fn() -> R>,
+        }
+    }
 
-impl OnFailedUpdgrade for DefaultOnFailedUpdgrade {
-    #[inline]
-    fn call(self, _error: hyper::Error) {}
-}
+    impl<Fut, F, R> std::fmt::Debug for OnRejectFuture<Fut, F, R> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("OnRejectFuture").finish_non_exhaustive()
+        }
+    }
 
-pub mod rejection {
-    //! WebSocket specific rejections.
+    impl<Fut, F, R, ResBody, E> Future for OnRejectFuture<Fut, F, R>
+    where
+        Fut: Future<Output = Result<http::Response<ResBody>, E>>,
+        F: Fn(&R, &Parts),
+        R: UpgradeRejection,
+    {
+        type Output = Fut::Output;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let this = self.project();
+            let mut res = ready!(this.future.poll(cx))?;
+
+            if let Some(rejection) = R::take(res.extensions_mut()) {
+                (this.callback)(&rejection, this.parts);
+                // leave the rejection for `HandleRejection`
+                rejection.put(res.extensions_mut());
+            }
+
+            Poll::Ready(Ok(res))
+        }
+    }
+}
 
+#[cfg(test)]
+mod tests {
     use super::*;
+    use crate::test_helpers::upgrade_offering;
 
-    macro_rules! define_rejection {
-        (
-            #[status = $status:ident]
-            #[body = $body:expr]
-            $(#[$m:meta])*
-            pub struct $name:ident;
-        ) => {
-            $(#[$m])*
-            #[derive(Debug)]
-            #[non_exhaustive]
-            pub struct $name;
+    fn access_token() -> ProtocolToken {
+        ProtocolToken::AfterMarker("access_token".into())
+    }
 
-            impl IntoResponse for $name {
-                fn into_response(self) -> Response {
-                    (http::StatusCode::$status, $body).into_response()
-                }
-            }
+    fn remaining_protocols(ws: &WebSocketUpgrade) -> Vec<&[u8]> {
+        ws.request
+            .headers
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .map(HeaderValue::as_bytes)
+            .collect()
+    }
 
-            impl std::fmt::Display for $name {
-                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                    write!(f, "{}", $body)
-                }
-            }
+    #[test]
+    fn protocol_token_on_one_line() {
+        let ws = upgrade_offering(&["graphql-ws, access_token, secret"])
+            .extract_protocol_token(access_token());
 
-            impl std::error::Error for $name {}
-        };
+        assert_eq!(ws.protocol_token(), Some("secret"));
+        assert_eq!(ws.selected_protocol_str(), Some("access_token"));
+        assert_eq!(
+            remaining_protocols(&ws),
+            [b"graphql-ws, access_token".as_slice()]
+        );
     }
 
-    define_rejection! {
-        #[status = METHOD_NOT_ALLOWED]
-        #[body = "Request method must be `GET`"]
-        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
-        pub struct MethodNotGet;
+    #[test]
+    fn protocol_token_split_across_lines() {
+        let ws = upgrade_offering(&["graphql-ws", "access_token", "secret"])
+            .extract_protocol_token(access_token());
+
+        assert_eq!(ws.protocol_token(), Some("secret"));
+        assert_eq!(
+            remaining_protocols(&ws),
+            [b"graphql-ws".as_slice(), b"access_token"]
+        );
+
+        let ws = upgrade_offering(&["access_token", "secret, graphql-ws"])
+            .extract_protocol_token(access_token());
+
+        assert_eq!(ws.protocol_token(), Some("secret"));
+        assert_eq!(
+            remaining_protocols(&ws),
+            [b"access_token".as_slice(), b"graphql-ws"]
+        );
     }
 
-    define_rejection! {
-        #[status = BAD_REQUEST]
-        #[body = "Connection header did not include 'upgrade'"]
-        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
-        pub struct InvalidConnectionHeader;
+    #[test]
+    fn protocol_token_that_is_also_the_selected_protocol() {
+        let ws = upgrade_offering(&["access_token, graphql-ws"])
+            .protocols(["graphql-ws"])
+            .extract_protocol_token(access_token());
+
+        // the token is never echoed, the marker is echoed instead
+        assert_eq!(ws.protocol_token(), Some("graphql-ws"));
+        assert_eq!(ws.selected_protocol_str(), Some("access_token"));
     }
 
-    define_rejection! {
-        #[status = BAD_REQUEST]
-        #[body = "`Upgrade` header did not include 'websocket'"]
-        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
-        pub struct InvalidUpgradeHeader;
+    #[test]
+    fn protocol_token_before_and_after_protocols() {
+        for offered in ["graphql-ws, access_token, secret", "access_token, secret"] {
+            let after = upgrade_offering(&[offered])
+                .protocols(["graphql-ws"])
+                .extract_protocol_token(access_token());
+            let before = upgrade_offering(&[offered])
+                .extract_protocol_token(access_token())
+                .protocols(["graphql-ws"]);
+
+            assert_eq!(before.protocol_token(), Some("secret"));
+            assert_eq!(
+                before.selected_protocol(),
+                after.selected_protocol(),
+                "{offered}"
+            );
+        }
     }
 
-    define_rejection! {
-        #[status = BAD_REQUEST]
-        #[body = "`Sec-WebSocket-Version` header did not include '13'"]
-        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
-        pub struct InvalidWebSocketVersionHeader;
+    #[test]
+    fn protocol_token_keeps_values_that_are_not_utf8() {
+        let mut ws = upgrade_offering(&["access_token, secret"]);
+        let invalid = HeaderValue::from_bytes(b"\xffchat").unwrap();
+        ws.request
+            .headers
+            .append(header::SEC_WEBSOCKET_PROTOCOL, invalid.clone());
+        let ws = ws.extract_protocol_token(access_token());
+
+        assert_eq!(ws.protocol_token(), Some("secret"));
+        assert_eq!(
+            remaining_protocols(&ws),
+            [b"access_token".as_slice(), invalid.as_bytes()]
+        );
     }
 
-    define_rejection! {
-        #[status = BAD_REQUEST]
-        #[body = "`Sec-WebSocket-Key` header missing"]
-        /// Rejection type for [`WebSocketUpgrade`](super::WebSocketUpgrade).
-        pub struct WebSocketKeyHeaderMissing;
+    #[test]
+    fn prefixed_protocol_token() {
+        let ws = upgrade_offering(&[
+            "base64url.bearer.authorization.k8s.io.c2VjcmV0, v4.channel.k8s.io",
+        ])
+        .extract_protocol_token(ProtocolToken::Prefixed(
+            "base64url.bearer.authorization.k8s.io.".into(),
+        ))
+        .protocols(["v4.channel.k8s.io"]);
+
+        assert_eq!(ws.protocol_token(), Some("c2VjcmV0"));
+        assert_eq!(ws.selected_protocol_str(), Some("v4.channel.k8s.io"));
+        assert_eq!(remaining_protocols(&ws), [b"v4.channel.k8s.io".as_slice()]);
     }
 
-    macro_rules! composite_rejection {
-        (
-            $(#[$m:meta])*
-            pub enum $name:ident {
-                $($variant:ident),+
-                $(,)?
-            }
-        ) => {
-            $(#[$m])*
-            #[derive(Debug)]
-            #[non_exhaustive]
-            pub enum $name {
-                $(
-                    #[allow(missing_docs)]
-                    $variant($variant)
-                ),+
-            }
+    #[tokio::test(start_paused = true)]
+    async fn liveness_timeout_closes_silent_connections() {
+        use axum::{routing::get, Router};
+        use futures_util::StreamExt;
+        use tokio::sync::mpsc;
+
+        let (error_tx, mut error_rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.liveness_timeout(Duration::from_secs(10)).on_upgrade(
+                    move |mut socket| async move {
+                        error_tx.send(socket.recv().await).unwrap();
+                        // keep the socket so the connection only ends through the shutdown
+                        std::future::pending::<()>().await
+                    },
+                )
+            }),
+        );
+        let mut client = crate::test_helpers::connect(app, 1024).await;
 
-            impl IntoResponse for $name {
-                fn into_response(self) -> Response {
-                    match self {
-                        $(
-                            Self::$variant(inner) => inner.into_response(),
-                        )+
-                    }
-                }
-            }
+        let frame = match client.next().await {
+            Some(Ok(Message::Close(frame))) => frame.unwrap(),
+            other => panic!("expected a close frame, got {:?}", other),
+        };
+        assert_eq!(frame.code, CloseCode::Away);
+        assert!(matches!(client.next().await, None | Some(Err(_))));
 
-            $(
-                impl From<$variant> for $name {
-                    fn from(inner: $variant) -> Self {
-                        Self::$variant(inner)
-                    }
-                }
-            )+
+        let err = match error_rx.recv().await.unwrap() {
+            Some(Err(Error::Io(err))) => err,
+            other => panic!("expected a timeout, got {:?}", other),
+        };
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref(),
+            Some(&Timeout::Liveness)
+        );
+    }
 
-            impl std::fmt::Display for $name {
-                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                    match self {
-                        $(
-                            Self::$variant(inner) => write!(f, "{}", inner),
-                        )+
+    #[tokio::test]
+    async fn pong_sent_right_away_replaces_the_automatic_one() {
+        use axum::{routing::get, Router};
+        use futures_util::{SinkExt, StreamExt};
+
+        let app = Router::new().route(
+            "/",
+            get(|ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|mut socket| async move {
+                    if let Some(Ok(Message::Ping(_))) = socket.recv().await {
+                        socket
+                            .send(Message::Pong(b"custom".to_vec()))
+                            .await
+                            .unwrap();
                     }
-                }
+                    socket.send(Message::Text("done".to_owned())).await.unwrap();
+                })
+            }),
+        );
+        let mut client = crate::test_helpers::connect(app, 1024).await;
+
+        client.send(Message::Ping(b"ping".to_vec())).await.unwrap();
+
+        let pong = client.next().await.unwrap().unwrap();
+        assert_eq!(pong, Message::Pong(b"custom".to_vec()));
+        let done = client.next().await.unwrap().unwrap();
+        assert_eq!(done, Message::Text("done".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn allowed_origins_are_checked_on_upgrade() {
+        fn upgrade(origin: &'static str) -> WebSocketUpgrade {
+            let mut headers = crate::test_helpers::upgrade_headers();
+            headers.insert(header::ORIGIN, HeaderValue::from_static(origin));
+            let on_upgrade = hyper::upgrade::on(Request::new(()));
+            WebSocketUpgrade::new(&Method::GET, &headers, on_upgrade).unwrap()
+        }
+
+        let res = upgrade("https://chat.example.com")
+            .allowed_origins(["https://*.example.com"])
+            .on_upgrade(|_| async {});
+        assert_eq!(res.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+
+        let res = upgrade("https://evil.com")
+            .allowed_origins(["https://*.example.com"])
+            .on_upgrade(|_| async {});
+        assert_eq!(res.status(), http::StatusCode::FORBIDDEN);
+        assert!(matches!(
+            res.extensions().get::<WebSocketUpgradeRejection>(),
+            Some(WebSocketUpgradeRejection::OriginNotAllowed(_))
+        ));
+    }
+
+    #[test]
+    fn extracting_removes_the_key_header() {
+        let (mut parts, _) = Request::new(()).into_parts();
+        parts.headers = crate::test_helpers::upgrade_headers();
+
+        // without upgrade state the request is left alone for other extractors
+        assert!(WebSocketUpgrade::from_request_head(&mut parts).is_err());
+        assert!(parts.headers.contains_key(header::SEC_WEBSOCKET_KEY));
+
+        let on_upgrade = hyper::upgrade::on(Request::new(()));
+        parts.extensions.insert(on_upgrade);
+        let ws = WebSocketUpgrade::from_request_head(&mut parts).unwrap();
+        assert!(!parts.headers.contains_key(header::SEC_WEBSOCKET_KEY));
+        assert_eq!(ws.sec_websocket_key, "dGhlIHNhbXBsZSBub25jZQ==");
+    }
+
+    #[tokio::test]
+    async fn recv_timeout_leaves_the_socket_usable() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio::sync::oneshot;
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = crate::test_helpers::serve(|mut socket| async move {
+            assert!(socket
+                .recv_timeout(Duration::from_millis(20))
+                .await
+                .is_err());
+            socket
+                .send(Message::Text("ready".to_owned()))
+                .await
+                .unwrap();
+
+            let msg = socket.recv_timeout(Duration::from_secs(5)).await;
+            assert!(matches!(msg, Ok(Some(Ok(Message::Text(text)))) if text == "hello"));
+            done_tx.send(()).unwrap();
+        })
+        .await;
+
+        client.next().await.unwrap().unwrap();
+        client
+            .send(Message::Text("hello".to_owned()))
+            .await
+            .unwrap();
+        done_rx.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn try_recv_returns_messages_that_already_arrived() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio::sync::oneshot;
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = crate::test_helpers::serve(|mut socket| async move {
+            assert!(matches!(socket.try_recv(), Ok(None)));
+            socket
+                .send(Message::Text("ready".to_owned()))
+                .await
+                .unwrap();
+
+            // let all messages arrive
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            for expected in ["a", "b", "c"] {
+                let msg = socket.try_recv().unwrap();
+                assert_eq!(msg, Some(Message::Text(expected.to_owned())));
             }
+            assert!(matches!(socket.try_recv(), Ok(None)));
+            done_tx.send(()).unwrap();
+        })
+        .await;
 
-            impl std::error::Error for $name {
-                fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-                    match self {
-                        $(
-                            Self::$variant(inner) => Some(inner),
-                        )+
-                    }
-                }
+        client.next().await.unwrap().unwrap();
+        for msg in ["a", "b", "c"] {
+            client.send(Message::Text(msg.to_owned())).await.unwrap();
+        }
+        done_rx.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn try_recv_returns_the_peeked_message() {
+        use futures_util::SinkExt;
+        use tokio::sync::oneshot;
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = crate::test_helpers::serve(|mut socket| async move {
+            socket.peek().await.unwrap().as_ref().unwrap();
+            let msg = socket.try_recv().unwrap();
+            assert_eq!(msg, Some(Message::Text("a".to_owned())));
+            done_tx.send(()).unwrap();
+        })
+        .await;
+
+        client.send(Message::Text("a".to_owned())).await.unwrap();
+        done_rx.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recv_many_takes_what_is_ready_up_to_the_limit() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio::sync::oneshot;
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = crate::test_helpers::serve(|mut socket| async move {
+            let mut batch = Vec::new();
+            assert_eq!(socket.recv_many(&mut batch, 0).await.unwrap(), 0);
+
+            // let all messages arrive
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(socket.recv_many(&mut batch, 2).await.unwrap(), 2);
+            assert_eq!(socket.recv_many(&mut batch, 10).await.unwrap(), 1);
+            let texts = batch
+                .into_iter()
+                .map(|msg| msg.into_text().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(texts, ["a", "b", "c"]);
+            socket
+                .send(Message::Text("checked".to_owned()))
+                .await
+                .unwrap();
+
+            let mut batch = Vec::new();
+            while socket.recv_many(&mut batch, 10).await.unwrap() > 0 {}
+            assert!(matches!(batch.last(), Some(Message::Close(_))));
+            done_tx.send(()).unwrap();
+        })
+        .await;
+
+        for msg in ["a", "b", "c"] {
+            client.send(Message::Text(msg.to_owned())).await.unwrap();
+        }
+        client.next().await.unwrap().unwrap();
+        client.close(None).await.unwrap();
+        done_rx.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn peeked_messages_are_received_next() {
+        use futures_util::SinkExt;
+        use tokio::sync::oneshot;
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = crate::test_helpers::serve(|mut socket| async move {
+            for _ in 0..2 {
+                let peeked = socket.peek().await.unwrap().as_ref().unwrap();
+                assert_eq!(peeked, &Message::Text("a".to_owned()));
             }
-        };
+            let msg = socket.recv().await.unwrap().unwrap();
+            assert_eq!(msg, Message::Text("a".to_owned()));
+
+            let peeked = socket.peek().await.unwrap().as_ref().unwrap();
+            assert_eq!(peeked, &Message::Text("b".to_owned()));
+            let mut batch = Vec::new();
+            socket.recv_many(&mut batch, 1).await.unwrap();
+            assert_eq!(batch, [Message::Text("b".to_owned())]);
+            done_tx.send(()).unwrap();
+        })
+        .await;
+
+        client.send(Message::Text("a".to_owned())).await.unwrap();
+        client.send(Message::Text("b".to_owned())).await.unwrap();
+        done_rx.await.unwrap();
     }
 
-    composite_rejection! {
-        /// Rejection used for [`WebSocketUpgrade`](super::WebSocketUpgrade).
-        ///
-        /// Contains one variant for each way the [`WebSocketUpgrade`](super::WebSocketUpgrade)
-        /// extractor can fail.
-        pub enum WebSocketUpgradeRejection {
-            MethodNotGet,
-            InvalidConnectionHeader,
-            InvalidUpgradeHeader,
-            InvalidWebSocketVersionHeader,
-            WebSocketKeyHeaderMissing,
+    #[tokio::test]
+    async fn close_timeout_ends_unanswered_close_handshakes() {
+        use axum::{routing::get, Router};
+        use tokio::sync::mpsc;
+
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.close_timeout(Duration::from_millis(50)).on_upgrade(
+                    move |mut socket| async move {
+                        socket.send(Message::Close(None)).await.unwrap();
+                        let err = match socket.recv().await {
+                            Some(Err(Error::Io(err))) => err,
+                            other => panic!("expected a timeout, got {:?}", other),
+                        };
+                        assert_eq!(
+                            err.get_ref().unwrap().downcast_ref(),
+                            Some(&Timeout::CloseHandshake)
+                        );
+                        assert!(socket.recv().await.is_none());
+                        assert!(matches!(
+                            socket.send(Message::Text("late".to_owned())).await,
+                            Err(Error::AlreadyClosed)
+                        ));
+                        done_tx.send(()).unwrap();
+                    },
+                )
+            }),
+        );
+        // the client never reads, so it never answers the close frame
+        let _client = crate::test_helpers::connect(app, 1024).await;
+
+        done_rx.recv().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn close_linger_writes_queued_messages_first() {
+        use axum::{routing::get, Router};
+        use futures_util::StreamExt;
+
+        async fn received(linger: bool) -> Vec<Message> {
+            let app = Router::new().route(
+                "/",
+                get(move |ws: WebSocketUpgrade| async move {
+                    ws.close_linger(Duration::from_secs(1)).on_upgrade(
+                        move |mut socket| async move {
+                            let sender = socket.queued_sender();
+                            for msg in ["a", "b"] {
+                                sender.send(Message::Text(msg.to_owned())).await.unwrap();
+                            }
+                            if linger {
+                                socket.close().await.unwrap();
+                            } else {
+                                socket.close_now().await.unwrap();
+                            }
+                        },
+                    )
+                }),
+            );
+            let client = crate::test_helpers::connect(app, 1024).await;
+            client.filter_map(|msg| async { msg.ok() }).collect().await
         }
+
+        assert_eq!(
+            received(true).await,
+            [
+                Message::Text("a".to_owned()),
+                Message::Text("b".to_owned()),
+                Message::Close(None),
+            ]
+        );
+        assert_eq!(received(false).await, [Message::Close(None)]);
     }
 }
@@ -0,0 +1,167 @@
+use crate::{transcript::Direction, ConnectionId, Message};
+use std::fmt::Write as _;
+use tracing::Level;
+
+/// Emits a [`tracing`] event for every message sent or received on a socket.
+///
+/// Each event has the connection's ID, the direction (`inbound` or `outbound`), the opcode,
+/// the payload size in bytes, and optionally a preview of the payload. Text previews are
+/// truncated at a character boundary and other payloads are previewed as hex. Events are
+/// emitted with the target `axum_tungstenite::message_log`.
+///
+/// With the `tracing` feature, every connection runs in a `websocket connection` span carrying
+/// its ID, so these events and any emitted by the handler are grouped by connection. With the
+/// `otel` feature the span's parent is the trace context sent with the upgrade request, such as
+/// W3C `traceparent` and `baggage` headers, read with the propagator set with
+/// `opentelemetry::global::set_text_map_propagator`. This requires
+/// [`tracing-opentelemetry`](https://docs.rs/tracing-opentelemetry)'s layer to be installed.
+///
+/// See [`WebSocketUpgrade::log_messages`](crate::WebSocketUpgrade::log_messages).
+///
+/// Requires the `tracing` feature.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageLog {
+    level: Level,
+    preview: Option<usize>,
+    sample: u64,
+}
+
+impl MessageLog {
+    /// Log every message at `DEBUG` level, without payload previews.
+    pub fn new() -> Self {
+        Self {
+            level: Level::DEBUG,
+            preview: None,
+            sample: 1,
+        }
+    }
+
+    /// Set the level events are emitted at.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Include up to `max` bytes of each payload in the events.
+    ///
+    /// Payloads can contain credentials or personal data, so previews are off by default.
+    pub fn preview(mut self, max: usize) -> Self {
+        self.preview = Some(max);
+        self
+    }
+
+    /// Only log one of every `n` messages of each connection.
+    ///
+    /// The first message is always logged. Close frames are logged regardless of sampling.
+    /// Defaults to `1`, logging every message.
+    pub fn sample(mut self, n: u64) -> Self {
+        self.sample = n.max(1);
+        self
+    }
+}
+
+impl Default for MessageLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct MessageLogger {
+    config: MessageLog,
+    connection_id: ConnectionId,
+    seen: u64,
+}
+
+impl MessageLogger {
+    pub(crate) fn new(config: MessageLog, connection_id: ConnectionId) -> Self {
+        Self {
+            config,
+            connection_id,
+            seen: 0,
+        }
+    }
+
+    pub(crate) fn log(&mut self, direction: Direction, msg: &Message) {
+        let sampled = self.seen.is_multiple_of(self.config.sample);
+        self.seen += 1;
+        if !sampled && !msg.is_close() {
+            return;
+        }
+
+        let direction = match direction {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
+        };
+        let preview = self.config.preview.map(|max| preview(msg, max));
+        let preview = preview.as_deref();
+
+        macro_rules! emit {
+            ($level:expr) => {
+                tracing::event!(
+                    target: "axum_tungstenite::message_log",
+                    $level,
+                    connection_id = %self.connection_id,
+                    direction,
+                    opcode = opcode(msg),
+                    size = msg.len(),
+                    preview,
+                    "websocket message",
+                )
+            };
+        }
+
+        match self.config.level {
+            Level::ERROR => emit!(Level::ERROR),
+            Level::WARN => emit!(Level::WARN),
+            Level::INFO => emit!(Level::INFO),
+            Level::DEBUG => emit!(Level::DEBUG),
+            Level::TRACE => emit!(Level::TRACE),
+        }
+    }
+}
+
+fn opcode(msg: &Message) -> &'static str {
+    match msg {
+        Message::Text(_) => "text",
+        Message::Binary(_) => "binary",
+        Message::Ping(_) => "ping",
+        Message::Pong(_) => "pong",
+        Message::Close(_) => "close",
+        Message::Frame(_) => "frame",
+    }
+}
+
+fn preview(msg: &Message, max: usize) -> String {
+    match msg {
+        Message::Text(text) => truncate(text, max),
+        Message::Close(Some(frame)) => {
+            format!("{} {}", u16::from(frame.code), truncate(&frame.reason, max))
+        }
+        Message::Close(None) => String::new(),
+        Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => hex(data, max),
+        Message::Frame(frame) => hex(frame.payload(), max),
+    }
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_owned();
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &text[..end])
+}
+
+fn hex(data: &[u8], max: usize) -> String {
+    let mut out = String::with_capacity(data.len().min(max) * 2 + 3);
+    for byte in data.iter().take(max) {
+        let _ = write!(out, "{byte:02x}");
+    }
+    if data.len() > max {
+        out.push_str("...");
+    }
+    out
+}
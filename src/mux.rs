@@ -0,0 +1,707 @@
+//! Logical channels multiplexed over a single WebSocket.
+//!
+//! [`Mux`] takes ownership of a [`WebSocket`] and splits it into any number of [`Channel`]s,
+//! each of which is a [`Stream`] of incoming payloads and a [`Sink`] for outgoing payloads. This
+//! allows applications to use one connection where they would otherwise open several.
+//!
+//! # Wire format
+//!
+//! Every frame is sent as a binary WebSocket message that starts with a 4 byte big endian
+//! channel ID followed by a 1 byte frame kind:
+//!
+//! - `0`, data: the rest of the message is the payload.
+//! - `1`, credit: the rest of the message is a 4 byte big endian number of additional data
+//!   frames the peer may send on the channel.
+//! - `2`, close: the channel is closed.
+//!
+//! Channels are opened implicitly by sending a data frame on a channel ID that isn't in use.
+//! Both peers must pick IDs that don't collide, for example by having clients use odd IDs and
+//! servers even IDs. A data frame that would open more than
+//! [`max_channels`](MuxConfig::max_channels) channels is answered with a close frame instead.
+//!
+//! A peer answers the close frame of a channel with one of its own. The ID stays in use until
+//! then, so data that was already on its way isn't mistaken for a new channel.
+//!
+//! # Flow control
+//!
+//! Each side may send at most [`window`](MuxConfig::window) data frames on a channel before the
+//! peer grants more credit. Credit is granted as the application consumes incoming payloads, so
+//! a slow consumer on one channel doesn't hold up the others. Both peers must use the same
+//! window size. A channel on which the peer sends more data frames than it has credit for is
+//! closed.
+//!
+//! At most one window of data frames, across all channels, is queued for the socket at once.
+//! Sending on a channel waits while the queue is full.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::response::Response;
+//! use axum_tungstenite::{mux::Mux, WebSocketUpgrade};
+//! use futures_util::{SinkExt, StreamExt};
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|socket| async move {
+//!         let mut mux = Mux::new(socket);
+//!
+//!         // echo every payload back on the channel it arrived on
+//!         while let Some(mut channel) = mux.accept().await {
+//!             tokio::spawn(async move {
+//!                 while let Some(payload) = channel.next().await {
+//!                     if channel.send(payload).await.is_err() {
+//!                         break;
+//!                     }
+//!                 }
+//!             });
+//!         }
+//!     })
+//! }
+//! ```
+
+use crate::{Error, Message, WebSocket};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::{sink::Sink, stream::Stream};
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll, Waker},
+};
+use tokio::sync::{mpsc, Notify};
+
+const DATA: u8 = 0;
+const CREDIT: u8 = 1;
+const CLOSE: u8 = 2;
+
+const DEFAULT_WINDOW: u32 = 32;
+const DEFAULT_MAX_CHANNELS: usize = 256;
+
+/// Configuration for a [`Mux`].
+#[derive(Debug, Clone, Copy)]
+pub struct MuxConfig {
+    window: u32,
+    max_channels: usize,
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        Self {
+            window: DEFAULT_WINDOW,
+            max_channels: DEFAULT_MAX_CHANNELS,
+        }
+    }
+}
+
+impl MuxConfig {
+    /// Create a new `MuxConfig` with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how many data frames each side may send per channel before waiting for credit.
+    ///
+    /// Defaults to 32.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn window(mut self, window: u32) -> Self {
+        assert!(window > 0, "window must be greater than zero");
+        self.window = window;
+        self
+    }
+
+    /// Set how many channels may be open at once.
+    ///
+    /// This includes channels opened by the peer that haven't been
+    /// [accepted](Mux::accept) yet, and closed channels whose close frame the peer hasn't
+    /// answered yet. Defaults to 256.
+    pub fn max_channels(mut self, max_channels: usize) -> Self {
+        self.max_channels = max_channels;
+        self
+    }
+}
+
+/// Multiplexes [`Channel`]s over a [`WebSocket`].
+///
+/// See the [module docs](self) for more details.
+#[derive(Debug)]
+pub struct Mux {
+    shared: Arc<Shared>,
+    accept_rx: mpsc::UnboundedReceiver<Channel>,
+}
+
+impl Mux {
+    /// Create a new `Mux` with the default [`MuxConfig`].
+    ///
+    /// This spawns a task that drives the socket. The task ends when the socket closes or the
+    /// `Mux` is dropped, after which all channels end.
+    pub fn new(socket: WebSocket) -> Self {
+        Self::with_config(socket, MuxConfig::default())
+    }
+
+    /// Create a new `Mux` where each side may send `window` data frames per channel before
+    /// waiting for credit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn with_window(socket: WebSocket, window: u32) -> Self {
+        Self::with_config(socket, MuxConfig::new().window(window))
+    }
+
+    /// Create a new `Mux` with the given [`MuxConfig`].
+    pub fn with_config(socket: WebSocket, config: MuxConfig) -> Self {
+        let (accept_tx, accept_rx) = mpsc::unbounded_channel();
+
+        let shared = Arc::new(Shared {
+            config,
+            outgoing: Mutex::default(),
+            frame_queued: Notify::new(),
+            channels: Mutex::new(Some(HashMap::new())),
+        });
+
+        tokio::spawn(drive(socket, shared.clone(), accept_tx));
+
+        Self { shared, accept_rx }
+    }
+
+    /// Open a channel with the given ID.
+    ///
+    /// Returns `None` if a channel with that ID is already open,
+    /// [`max_channels`](MuxConfig::max_channels) channels are open, or the socket has closed.
+    pub fn open(&self, id: u32) -> Option<Channel> {
+        let mut channels = self.shared.channels.lock().unwrap();
+        self.shared.register(channels.as_mut()?, id)
+    }
+
+    /// Wait for the peer to open a channel.
+    ///
+    /// Returns `None` once the socket has closed.
+    pub async fn accept(&mut self) -> Option<Channel> {
+        self.accept_rx.recv().await
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    config: MuxConfig,
+    outgoing: Mutex<Outgoing>,
+    /// Notifies the driver task when a frame is queued.
+    frame_queued: Notify,
+    /// `None` once the socket has closed.
+    channels: Mutex<Option<HashMap<u32, ChannelState>>>,
+}
+
+/// Frames waiting to be written to the socket.
+#[derive(Debug, Default)]
+struct Outgoing {
+    frames: VecDeque<Bytes>,
+    /// How many of `frames` are data frames.
+    data: usize,
+    /// Channels waiting for room to queue a data frame.
+    waiters: Vec<Waker>,
+    /// Set once the socket has closed.
+    closed: bool,
+}
+
+impl Shared {
+    /// Queue a credit or close frame.
+    ///
+    /// These don't count against the limit on queued data frames. They're bounded by the data
+    /// frames and channels that caused them, and the driver task sends them before reading more.
+    fn queue_control(&self, frame: Bytes) {
+        let mut outgoing = self.outgoing.lock().unwrap();
+        if !outgoing.closed {
+            outgoing.frames.push_back(frame);
+            self.frame_queued.notify_one();
+        }
+    }
+
+    fn poll_data_room(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut outgoing = self.outgoing.lock().unwrap();
+        if outgoing.closed || outgoing.data < self.config.window as usize {
+            Poll::Ready(())
+        } else {
+            outgoing.waiters.push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Returns `false` if the socket has closed.
+    fn queue_data(&self, frame: Bytes) -> bool {
+        let mut outgoing = self.outgoing.lock().unwrap();
+        if outgoing.closed {
+            return false;
+        }
+        outgoing.frames.push_back(frame);
+        outgoing.data += 1;
+        self.frame_queued.notify_one();
+        true
+    }
+
+    fn next_frame(&self) -> Option<Bytes> {
+        let mut outgoing = self.outgoing.lock().unwrap();
+        let frame = outgoing.frames.pop_front()?;
+        if frame[4] == DATA {
+            outgoing.data -= 1;
+            outgoing.waiters.drain(..).for_each(Waker::wake);
+        }
+        Some(frame)
+    }
+
+    fn close(&self) {
+        let mut outgoing = self.outgoing.lock().unwrap();
+        outgoing.closed = true;
+        outgoing.frames.clear();
+        outgoing.waiters.drain(..).for_each(Waker::wake);
+        drop(outgoing);
+
+        if let Some(channels) = self.channels.lock().unwrap().take() {
+            for state in channels.into_values() {
+                state.credit.lock().unwrap().close();
+            }
+        }
+    }
+
+    fn register(
+        self: &Arc<Self>,
+        channels: &mut HashMap<u32, ChannelState>,
+        id: u32,
+    ) -> Option<Channel> {
+        if channels.contains_key(&id) || channels.len() >= self.config.max_channels {
+            return None;
+        }
+
+        let (payloads_tx, payloads_rx) = mpsc::unbounded_channel();
+        let credit = Arc::new(Mutex::new(Credit {
+            available: self.config.window,
+            closed: false,
+            waker: None,
+        }));
+        channels.insert(
+            id,
+            ChannelState {
+                payloads_tx: Some(payloads_tx),
+                receive_window: self.config.window,
+                credit: credit.clone(),
+            },
+        );
+
+        Some(Channel {
+            id,
+            shared: self.clone(),
+            payloads_rx,
+            credit,
+            consumed: 0,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ChannelState {
+    /// `None` once the local side has closed the channel and waits for the peer to answer.
+    payloads_tx: Option<mpsc::UnboundedSender<Bytes>>,
+    /// How many more data frames the peer may send before it needs more credit.
+    receive_window: u32,
+    credit: Arc<Mutex<Credit>>,
+}
+
+impl ChannelState {
+    fn close(&mut self) {
+        self.payloads_tx = None;
+        self.credit.lock().unwrap().close();
+    }
+}
+
+#[derive(Debug)]
+struct Credit {
+    available: u32,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl Credit {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+async fn drive(
+    mut socket: WebSocket,
+    shared: Arc<Shared>,
+    accept_tx: mpsc::UnboundedSender<Channel>,
+) {
+    loop {
+        // send queued frames first, so close frames answering the peer can't pile up while it
+        // isn't reading
+        if let Some(frame) = shared.next_frame() {
+            if socket.send(Message::Binary(frame.to_vec())).await.is_err() {
+                break;
+            }
+            continue;
+        }
+
+        tokio::select! {
+            biased;
+
+            // the `Mux` was dropped
+            _ = accept_tx.closed() => break,
+            _ = shared.frame_queued.notified() => {}
+            msg = socket.recv() => {
+                let frame = match msg {
+                    Some(Ok(Message::Binary(frame))) => frame,
+                    Some(Ok(_)) => continue,
+                    Some(Err(_)) | None => break,
+                };
+                // the channels lock is released by now, a `Channel` that can't be accepted
+                // locks it again when dropped
+                if let Some(channel) = handle_frame(&shared, &frame) {
+                    let _ = accept_tx.send(channel);
+                }
+            }
+        }
+    }
+
+    shared.close();
+    let _ = socket.close().await;
+}
+
+/// Returns the channel to accept if the frame opened one.
+fn handle_frame(shared: &Arc<Shared>, frame: &[u8]) -> Option<Channel> {
+    if frame.len() < 5 {
+        return None;
+    }
+    let id = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+    let kind = frame[4];
+    let payload = &frame[5..];
+
+    let mut channels = shared.channels.lock().unwrap();
+    let channels = channels.as_mut()?;
+
+    match kind {
+        DATA => {
+            let mut opened = None;
+            if !channels.contains_key(&id) {
+                match shared.register(channels, id) {
+                    Some(channel) => opened = Some(channel),
+                    None => {
+                        // too many channels are open
+                        shared.queue_control(encode(id, CLOSE, &[]));
+                        return None;
+                    }
+                }
+            }
+
+            let state = channels.get_mut(&id)?;
+            // the local side closed the channel before this frame arrived
+            let Some(payloads_tx) = &state.payloads_tx else {
+                return opened;
+            };
+
+            if state.receive_window == 0 {
+                // the peer sent data without credit
+                state.close();
+                shared.queue_control(encode(id, CLOSE, &[]));
+                return opened;
+            }
+            state.receive_window -= 1;
+            let _ = payloads_tx.send(Bytes::copy_from_slice(payload));
+            opened
+        }
+        CREDIT if payload.len() == 4 => {
+            let n = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+            if let Some(state) = channels.get(&id) {
+                let mut credit = state.credit.lock().unwrap();
+                credit.available = credit.available.saturating_add(n);
+                if let Some(waker) = credit.waker.take() {
+                    waker.wake();
+                }
+            }
+            None
+        }
+        CLOSE => {
+            if let Some(mut state) = channels.remove(&id) {
+                // otherwise this answers our own close frame
+                if state.payloads_tx.is_some() {
+                    state.close();
+                    shared.queue_control(encode(id, CLOSE, &[]));
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+fn encode(id: u32, kind: u8, payload: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(5 + payload.len());
+    frame.put_u32(id);
+    frame.put_u8(kind);
+    frame.put_slice(payload);
+    frame.freeze()
+}
+
+/// A logical channel of a [`Mux`].
+///
+/// Incoming payloads are received through its [`Stream`] implementation, which ends when either
+/// side closes the channel or the socket closes. Payloads are sent through its [`Sink`]
+/// implementation, which waits for credit from the peer.
+///
+/// Dropping a `Channel` closes it.
+#[derive(Debug)]
+pub struct Channel {
+    id: u32,
+    shared: Arc<Shared>,
+    payloads_rx: mpsc::UnboundedReceiver<Bytes>,
+    credit: Arc<Mutex<Credit>>,
+    consumed: u32,
+}
+
+impl Channel {
+    /// The ID of the channel.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Stream for Channel {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let payload = match self.payloads_rx.poll_recv(cx) {
+            Poll::Ready(Some(payload)) => payload,
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        // grant more credit once half the window has been consumed
+        self.consumed += 1;
+        if self.consumed >= (self.shared.config.window / 2).max(1) {
+            let consumed = std::mem::take(&mut self.consumed);
+            self.grant(consumed);
+        }
+
+        Poll::Ready(Some(payload))
+    }
+}
+
+impl Sink<Bytes> for Channel {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        {
+            let mut credit = self.credit.lock().unwrap();
+            if credit.closed {
+                return Poll::Ready(Err(Error::AlreadyClosed));
+            } else if credit.available == 0 {
+                credit.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+
+        ready!(self.shared.poll_data_room(cx));
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let mut credit = self.credit.lock().unwrap();
+        if credit.closed {
+            return Err(Error::AlreadyClosed);
+        }
+        credit.available = credit.available.saturating_sub(1);
+        if self.shared.queue_data(encode(self.id, DATA, &item)) {
+            Ok(())
+        } else {
+            Err(Error::AlreadyClosed)
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.close_channel();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Channel {
+    /// Call `f` with the state of this channel, unless the ID has been reused since it closed.
+    fn with_state(&self, f: impl FnOnce(&mut ChannelState)) {
+        let mut channels = self.shared.channels.lock().unwrap();
+        if let Some(state) = channels
+            .as_mut()
+            .and_then(|channels| channels.get_mut(&self.id))
+            .filter(|state| Arc::ptr_eq(&state.credit, &self.credit))
+        {
+            f(state);
+        }
+    }
+
+    fn grant(&self, n: u32) {
+        self.with_state(|state| {
+            if state.payloads_tx.is_some() {
+                state.receive_window = state.receive_window.saturating_add(n);
+                self.shared
+                    .queue_control(encode(self.id, CREDIT, &n.to_be_bytes()));
+            }
+        });
+    }
+
+    fn close_channel(&self) {
+        self.with_state(|state| {
+            if state.payloads_tx.is_some() {
+                state.close();
+                self.shared.queue_control(encode(self.id, CLOSE, &[]));
+            }
+        });
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.close_channel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_helpers, WebSocketUpgrade};
+    use axum::{routing::get, Router};
+    use futures_util::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio::{io::DuplexStream, time::timeout};
+    use tokio_tungstenite::WebSocketStream;
+
+    type Client = WebSocketStream<DuplexStream>;
+
+    /// Connect to a `Mux` that reports the first payload of every accepted channel, holding on
+    /// to the channels unless `drop_channels` is set.
+    async fn connect(
+        config: MuxConfig,
+        drop_channels: bool,
+    ) -> (Client, mpsc::UnboundedReceiver<Option<Bytes>>) {
+        let (accepted_tx, accepted_rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(move |socket| async move {
+                    let mut mux = Mux::with_config(socket, config);
+                    let mut held = Vec::new();
+                    while let Some(mut channel) = mux.accept().await {
+                        accepted_tx.send(channel.next().await).unwrap();
+                        if !drop_channels {
+                            held.push(channel);
+                        }
+                    }
+                })
+            }),
+        );
+        let client = test_helpers::connect(app, 1024).await;
+        (client, accepted_rx)
+    }
+
+    async fn send(client: &mut Client, id: u32, kind: u8, payload: &[u8]) {
+        let frame = encode(id, kind, payload);
+        client.send(Message::Binary(frame.to_vec())).await.unwrap();
+    }
+
+    async fn recv(client: &mut Client) -> (u32, u8) {
+        let msg = timeout(Duration::from_secs(1), client.next())
+            .await
+            .expect("no frame received")
+            .unwrap()
+            .unwrap();
+        let frame = msg.into_data();
+        (
+            u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]),
+            frame[4],
+        )
+    }
+
+    #[tokio::test]
+    async fn data_without_credit_closes_the_channel() {
+        let (mut client, mut accepted) = connect(MuxConfig::new().window(2), false).await;
+
+        for _ in 0..3 {
+            send(&mut client, 1, DATA, b"x").await;
+        }
+
+        assert_eq!(accepted.recv().await.unwrap().unwrap(), "x");
+        assert_eq!(recv(&mut client).await, (1, CLOSE));
+    }
+
+    #[tokio::test]
+    async fn channels_beyond_the_limit_are_refused() {
+        let (mut client, mut accepted) = connect(MuxConfig::new().max_channels(1), false).await;
+
+        send(&mut client, 1, DATA, b"a").await;
+        send(&mut client, 3, DATA, b"b").await;
+
+        assert_eq!(recv(&mut client).await, (3, CLOSE));
+        assert_eq!(accepted.recv().await.unwrap().unwrap(), "a");
+        assert!(accepted.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn closed_ids_are_reused_once_the_close_is_answered() {
+        let (mut client, mut accepted) = connect(MuxConfig::new(), true).await;
+
+        send(&mut client, 1, DATA, b"a").await;
+        assert_eq!(accepted.recv().await.unwrap().unwrap(), "a");
+        assert_eq!(recv(&mut client).await, (1, CLOSE));
+
+        // sent before the client saw the close frame
+        send(&mut client, 1, DATA, b"late").await;
+        send(&mut client, 1, CLOSE, &[]).await;
+        send(&mut client, 1, DATA, b"b").await;
+
+        assert_eq!(accepted.recv().await.unwrap().unwrap(), "b");
+        assert_eq!(recv(&mut client).await, (1, CLOSE));
+    }
+
+    #[tokio::test]
+    async fn dropping_the_mux_closes_the_socket() {
+        let app = Router::new().route(
+            "/",
+            get(|ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|socket| async move {
+                    drop(Mux::new(socket));
+                    std::future::pending::<()>().await
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 1024).await;
+
+        send(&mut client, 1, DATA, b"hi").await;
+
+        let msg = timeout(Duration::from_secs(1), client.next())
+            .await
+            .expect("socket wasn't closed")
+            .unwrap()
+            .unwrap();
+        assert!(msg.is_close());
+    }
+
+    #[test]
+    fn channels_that_cant_be_accepted_are_closed() {
+        let shared = Arc::new(Shared {
+            config: MuxConfig::new(),
+            outgoing: Mutex::default(),
+            frame_queued: Notify::new(),
+            channels: Mutex::new(Some(HashMap::new())),
+        });
+
+        let channel = handle_frame(&shared, &encode(1, DATA, b"hi")).unwrap();
+        // what the driver does when the `Mux` is gone
+        drop(channel);
+
+        assert_eq!(shared.next_frame().unwrap(), encode(1, CLOSE, &[]));
+    }
+}
@@ -0,0 +1,291 @@
+//! Enforcing allowed origins across many WebSocket routes.
+//!
+//! [`OriginLayer`] checks the `Origin` header of WebSocket upgrade requests before they reach
+//! the handlers, so routes don't each have to call
+//! [`WebSocketUpgrade::allowed_origins`](crate::WebSocketUpgrade::allowed_origins). Other
+//! requests pass through untouched, so the layer can wrap a router that also serves regular
+//! routes.
+//!
+//! Rejected requests get the response of a
+//! [`WebSocketUpgradeRejection`], so they can be customized with
+//! [`HandleRejectionLayer`](crate::rejection::HandleRejectionLayer) and observed with
+//! [`OnRejectLayer`](crate::rejection::OnRejectLayer).
+//!
+//! # Example
+//!
+//! ```
+//! use axum::{response::Response, routing::get, Router};
+//! use axum_tungstenite::{origin::OriginLayer, WebSocketUpgrade};
+//!
+//! async fn chat(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|socket| async { /* ... */ })
+//! }
+//!
+//! async fn feed(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|socket| async { /* ... */ })
+//! }
+//!
+//! let app = Router::new()
+//!     .route("/chat", get(chat))
+//!     .route("/feed", get(feed))
+//!     .layer(
+//!         OriginLayer::new(["https://example.com", "https://*.example.com"])
+//!             .allow_missing_origin(false),
+//!     );
+//! # let _: Router = app;
+//! ```
+
+use crate::{
+    handshake,
+    rejection::{OriginMissing, OriginNotAllowed, WebSocketUpgradeRejection},
+};
+use axum_core::{
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use bytes::Bytes;
+use futures_util::ready;
+use http::{header, Request};
+use pin_project_lite::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// [`Layer`] that rejects WebSocket upgrade requests from origins that aren't allowed.
+///
+/// Origins are matched like with
+/// [`WebSocketUpgrade::allowed_origins`](crate::WebSocketUpgrade::allowed_origins): a leading
+/// `*.` in the host matches any subdomain and `*` alone allows any origin. See the
+/// [module docs](self) for an example.
+#[derive(Debug, Clone)]
+pub struct OriginLayer {
+    origins: Arc<[String]>,
+    allow_missing: bool,
+}
+
+impl OriginLayer {
+    /// Create a new `OriginLayer` allowing `origins`.
+    pub fn new<I>(origins: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        Self {
+            origins: origins
+                .into_iter()
+                .map(|origin| origin.as_ref().to_owned())
+                .collect(),
+            allow_missing: true,
+        }
+    }
+
+    /// Whether to allow requests without an `Origin` header.
+    ///
+    /// Browsers always send the header, so such requests typically come from non-browser
+    /// clients. Disallowed requests are rejected with
+    /// [`OriginMissing`](crate::rejection::OriginMissing).
+    ///
+    /// Defaults to `true`.
+    pub fn allow_missing_origin(mut self, allow: bool) -> Self {
+        self.allow_missing = allow;
+        self
+    }
+
+    fn check<B>(&self, req: &Request<B>) -> Result<(), WebSocketUpgradeRejection> {
+        if !handshake::header_contains(req.headers(), header::UPGRADE, "websocket") {
+            return Ok(());
+        }
+
+        let Some(origin) = req.headers().get(header::ORIGIN) else {
+            return if self.allow_missing {
+                Ok(())
+            } else {
+                Err(OriginMissing.into())
+            };
+        };
+
+        let allowed = origin.to_str().is_ok_and(|origin| {
+            self.origins
+                .iter()
+                .any(|pattern| handshake::origin_matches(origin, pattern))
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(OriginNotAllowed {
+                origin: origin.clone(),
+            }
+            .into())
+        }
+    }
+}
+
+impl<S> Layer<S> for OriginLayer {
+    type Service = Origin<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Origin {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Middleware that rejects WebSocket upgrade requests from origins that aren't allowed.
+///
+/// Created with [`OriginLayer`].
+#[derive(Debug, Clone)]
+pub struct Origin<S> {
+    inner: S,
+    layer: OriginLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Origin<S>
+where
+    S: Service<Request<ReqBody>, Response = http::Response<ResBody>>,
+    ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = OriginFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let kind = match self.layer.check(&req) {
+            Ok(()) => Kind::Inner {
+                future: self.inner.call(req),
+            },
+            Err(rejection) => Kind::Rejected {
+                response: Some(rejection.into_response()),
+            },
+        };
+        OriginFuture { kind }
+    }
+}
+
+pin_project! {
+    /// Response future for [`Origin`].
+    pub struct OriginFuture<F> {
+        #[pin]
+        kind: Kind<F>,
+    }
+}
+
+pin_project! {
+    #[project = KindProj]
+    enum Kind<F> {
+        Inner {
+            #[pin]
+            future: F,
+        },
+        Rejected {
+            response: Option<Response>,
+        },
+    }
+}
+
+impl<F> std::fmt::Debug for OriginFuture<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OriginFuture").finish_non_exhaustive()
+    }
+}
+
+impl<F, ResBody, E> Future for OriginFuture<F>
+where
+    F: Future<Output = Result<http::Response<ResBody>, E>>,
+    ResBody: http_body::Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Output = Result<Response, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().kind.project() {
+            KindProj::Inner { future } => {
+                let res = ready!(future.poll(cx))?;
+                Poll::Ready(Ok(res.map(axum_core::body::boxed)))
+            }
+            KindProj::Rejected { response } => {
+                Poll::Ready(Ok(response.take().expect("future polled after completion")))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rejection::WebSocketUpgradeRejection;
+
+    fn upgrade_request(origin: Option<&'static str>) -> Request<()> {
+        let mut req = Request::builder()
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, "websocket");
+        if let Some(origin) = origin {
+            req = req.header(header::ORIGIN, origin);
+        }
+        req.body(()).unwrap()
+    }
+
+    #[test]
+    fn allows_matching_origins() {
+        let layer = OriginLayer::new(["https://example.com", "https://*.example.com"]);
+
+        assert!(layer
+            .check(&upgrade_request(Some("https://example.com")))
+            .is_ok());
+        assert!(layer
+            .check(&upgrade_request(Some("https://chat.example.com")))
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_other_origins() {
+        let layer = OriginLayer::new(["https://*.example.com"]);
+
+        for origin in [
+            "https://example.com",
+            "https://x.example.com:8080",
+            "https://a.example.com.evil.com",
+        ] {
+            assert!(
+                matches!(
+                    layer.check(&upgrade_request(Some(origin))),
+                    Err(WebSocketUpgradeRejection::OriginNotAllowed(_))
+                ),
+                "{origin} was allowed",
+            );
+        }
+    }
+
+    #[test]
+    fn missing_origin() {
+        let layer = OriginLayer::new(["https://example.com"]);
+        assert!(layer.check(&upgrade_request(None)).is_ok());
+
+        let layer = layer.allow_missing_origin(false);
+        assert!(matches!(
+            layer.check(&upgrade_request(None)),
+            Err(WebSocketUpgradeRejection::OriginMissing(_))
+        ));
+    }
+
+    #[test]
+    fn ignores_requests_that_are_not_upgrades() {
+        let layer = OriginLayer::new(["https://example.com"]).allow_missing_origin(false);
+        let req = Request::builder()
+            .header(header::ORIGIN, "https://evil.com")
+            .body(())
+            .unwrap();
+
+        assert!(layer.check(&req).is_ok());
+    }
+}
@@ -0,0 +1,26 @@
+use http::HeaderMap;
+use opentelemetry::{global, propagation::Extractor};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Make the trace context sent with the upgrade request, such as W3C `traceparent` and
+/// `baggage` headers, the parent of `span`.
+///
+/// Headers are read with the globally configured propagator, see
+/// [`global::set_text_map_propagator`].
+pub(crate) fn set_parent(span: &tracing::Span, headers: &HeaderMap) {
+    let context =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(context);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
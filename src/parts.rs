@@ -0,0 +1,186 @@
+use crate::{
+    close::OnClose, connection_info::ConnectionInfo, stats::Quotas, ConnectionId,
+    DefaultOnFailedUpdgrade, ErrorPolicy, Extension, SlowConsumerPolicy, WebSocketUpgrade,
+};
+#[cfg(feature = "console")]
+use axum::extract::MatchedPath;
+use http::{header, request::Parts, HeaderValue};
+use hyper::upgrade::OnUpgrade;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+
+#[cfg(feature = "testing")]
+use crate::testing;
+#[cfg(feature = "tracing")]
+use crate::MessageLog;
+
+/// The pieces of a [`WebSocketUpgrade`].
+///
+/// Created with [`WebSocketUpgrade::into_parts`] and turned back into a [`WebSocketUpgrade`]
+/// with [`WebSocketUpgrade::from_parts`]. This lets frameworks built on this crate, such as
+/// custom routers, manipulate the handshake directly. The response can be built from the parts
+/// with [`handshake::upgrade_response`](crate::handshake::upgrade_response).
+///
+/// The settings without a public field, such as timeouts and hooks, are kept as is. The
+/// request head isn't parsed again when rebuilding the upgrade, so changing its headers doesn't
+/// change the offered extensions, the `Origin`, or the cookies.
+#[derive(Debug)]
+pub struct UpgradeParts<C = DefaultOnFailedUpdgrade> {
+    /// The configuration of the socket.
+    pub config: WebSocketConfig,
+    /// The protocol sent in the `Sec-WebSocket-Protocol` header of the response.
+    pub protocol: Option<HeaderValue>,
+    /// The `Sec-WebSocket-Key` header sent by the client.
+    pub sec_websocket_key: HeaderValue,
+    /// Resolves to the upgraded connection once the response has been sent.
+    pub on_upgrade: OnUpgrade,
+    /// Called if upgrading the connection fails.
+    pub on_failed_upgrade: C,
+    /// The extensions sent in the `Sec-WebSocket-Extensions` header of the response.
+    pub accepted_extensions: Vec<Extension>,
+    /// The head of the upgrade request.
+    pub request: Parts,
+    settings: Settings,
+}
+
+impl<C> UpgradeParts<C> {
+    /// The protocols offered by the client in the `Sec-WebSocket-Protocol` header, the
+    /// candidates for [`protocol`](Self::protocol).
+    pub fn offered_protocols(&self) -> impl Iterator<Item = &str> {
+        self.request
+            .headers
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|protocol| !protocol.is_empty())
+    }
+
+    /// The extensions offered by the client in the `Sec-WebSocket-Extensions` header.
+    pub fn offered_extensions(&self) -> &[Extension] {
+        &self.settings.offered_extensions
+    }
+}
+
+#[derive(Debug)]
+struct Settings {
+    protocol_token: Option<(String, Option<HeaderValue>)>,
+    connection_id: ConnectionId,
+    liveness_timeout: Option<Duration>,
+    close_timeout: Option<Duration>,
+    close_linger: Option<Duration>,
+    slow_consumer: Option<(Duration, SlowConsumerPolicy)>,
+    error_policy: ErrorPolicy,
+    #[cfg(feature = "tracing")]
+    message_log: Option<MessageLog>,
+    #[cfg(feature = "console")]
+    matched_path: Option<MatchedPath>,
+    quotas: Quotas,
+    on_close: Option<OnClose>,
+    offered_extensions: Vec<Extension>,
+    origin: Option<HeaderValue>,
+    allowed_origins: Option<Arc<[String]>>,
+    connection_info: Option<ConnectionInfo>,
+    peer_addr: Option<SocketAddr>,
+    kept_request: Option<Parts>,
+    #[cfg(feature = "cookie")]
+    cookies: cookie::CookieJar,
+    #[cfg(feature = "testing")]
+    faults: Option<testing::Faults>,
+}
+
+impl<C> WebSocketUpgrade<C> {
+    /// Split the upgrade into its parts.
+    ///
+    /// See [`UpgradeParts`] for more details.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use axum::response::Response;
+    /// use axum_tungstenite::WebSocketUpgrade;
+    ///
+    /// async fn handler(ws: WebSocketUpgrade) -> Response {
+    ///     let mut parts = ws.into_parts();
+    ///     if parts.offered_protocols().any(|protocol| protocol == "v2") {
+    ///         parts.protocol = Some("v2".parse().unwrap());
+    ///     }
+    ///
+    ///     WebSocketUpgrade::from_parts(parts).on_upgrade(|socket| async { /* ... */ })
+    /// }
+    /// ```
+    pub fn into_parts(self) -> UpgradeParts<C> {
+        UpgradeParts {
+            config: self.config,
+            protocol: self.protocol,
+            sec_websocket_key: self.sec_websocket_key,
+            on_upgrade: self.on_upgrade,
+            on_failed_upgrade: self.on_failed_upgrade,
+            accepted_extensions: self.accepted_extensions,
+            request: self.request,
+            settings: Settings {
+                protocol_token: self.protocol_token,
+                connection_id: self.connection_id,
+                liveness_timeout: self.liveness_timeout,
+                close_timeout: self.close_timeout,
+                close_linger: self.close_linger,
+                slow_consumer: self.slow_consumer,
+                error_policy: self.error_policy,
+                #[cfg(feature = "tracing")]
+                message_log: self.message_log,
+                #[cfg(feature = "console")]
+                matched_path: self.matched_path,
+                quotas: self.quotas,
+                on_close: self.on_close,
+                offered_extensions: self.offered_extensions,
+                origin: self.origin,
+                allowed_origins: self.allowed_origins,
+                connection_info: self.connection_info,
+                peer_addr: self.peer_addr,
+                kept_request: self.kept_request,
+                #[cfg(feature = "cookie")]
+                cookies: self.cookies,
+                #[cfg(feature = "testing")]
+                faults: self.faults,
+            },
+        }
+    }
+
+    /// Rebuild an upgrade from parts created with [`into_parts`](Self::into_parts).
+    pub fn from_parts(parts: UpgradeParts<C>) -> Self {
+        let settings = parts.settings;
+        Self {
+            config: parts.config,
+            protocol: parts.protocol,
+            protocol_token: settings.protocol_token,
+            sec_websocket_key: parts.sec_websocket_key,
+            on_upgrade: parts.on_upgrade,
+            on_failed_upgrade: parts.on_failed_upgrade,
+            connection_id: settings.connection_id,
+            liveness_timeout: settings.liveness_timeout,
+            close_timeout: settings.close_timeout,
+            close_linger: settings.close_linger,
+            slow_consumer: settings.slow_consumer,
+            error_policy: settings.error_policy,
+            #[cfg(feature = "tracing")]
+            message_log: settings.message_log,
+            #[cfg(feature = "console")]
+            matched_path: settings.matched_path,
+            quotas: settings.quotas,
+            on_close: settings.on_close,
+            offered_extensions: settings.offered_extensions,
+            accepted_extensions: parts.accepted_extensions,
+            origin: settings.origin,
+            allowed_origins: settings.allowed_origins,
+            request: parts.request,
+            connection_info: settings.connection_info,
+            peer_addr: settings.peer_addr,
+            kept_request: settings.kept_request,
+            #[cfg(feature = "cookie")]
+            cookies: settings.cookies,
+            #[cfg(feature = "testing")]
+            faults: settings.faults,
+        }
+    }
+}
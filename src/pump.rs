@@ -0,0 +1,241 @@
+//! Forward messages between [`tokio::sync::broadcast`] channels and a [`WebSocket`].
+//!
+//! [`Pump`] runs the select loop that sends messages from a broadcast channel to the socket and
+//! optionally publishes the socket's messages to another channel, handling lagging receivers,
+//! closed channels, and disconnecting clients.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::{extract::State, response::Response};
+//! use axum_tungstenite::{pump::Pump, Message, WebSocketUpgrade};
+//! use tokio::sync::broadcast;
+//!
+//! async fn chat(ws: WebSocketUpgrade, State(tx): State<broadcast::Sender<Message>>) -> Response {
+//!     ws.on_upgrade(|mut socket| async move {
+//!         let _ = Pump::new(&mut socket)
+//!             .subscribe(tx.subscribe())
+//!             .publish(tx)
+//!             .run()
+//!             .await;
+//!     })
+//! }
+//! ```
+
+use crate::{CloseCode, CloseFrame, Error, Message, WebSocket};
+use std::future::pending;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Forwards messages between broadcast channels and a [`WebSocket`].
+///
+/// See the [module docs](self) for an example.
+#[derive(Debug)]
+pub struct Pump<'a> {
+    socket: &'a mut WebSocket,
+    subscription: Option<broadcast::Receiver<Message>>,
+    publish: Option<broadcast::Sender<Message>>,
+    on_lag: OnLag,
+}
+
+impl<'a> Pump<'a> {
+    /// Create a new `Pump` for `socket`.
+    pub fn new(socket: &'a mut WebSocket) -> Self {
+        Self {
+            socket,
+            subscription: None,
+            publish: None,
+            on_lag: OnLag::default(),
+        }
+    }
+
+    /// Send the messages received from `rx` to the socket.
+    ///
+    /// Once all senders of the channel have been dropped the socket is closed with
+    /// `1000 Normal` and the pump stops with [`PumpEnd::ChannelClosed`].
+    pub fn subscribe(mut self, rx: broadcast::Receiver<Message>) -> Self {
+        self.subscription = Some(rx);
+        self
+    }
+
+    /// Publish the `Text` and `Binary` messages received from the socket to `tx`.
+    ///
+    /// Messages sent while the channel has no receivers are dropped.
+    pub fn publish(mut self, tx: broadcast::Sender<Message>) -> Self {
+        self.publish = Some(tx);
+        self
+    }
+
+    /// Set what to do when the socket falls behind the subscribed channel.
+    ///
+    /// Defaults to [`OnLag::Skip`].
+    pub fn on_lag(mut self, on_lag: OnLag) -> Self {
+        self.on_lag = on_lag;
+        self
+    }
+
+    /// Run until the client disconnects, the subscribed channel closes, or an error occurs.
+    pub async fn run(self) -> Result<PumpEnd, Error> {
+        let Self {
+            socket,
+            mut subscription,
+            publish,
+            on_lag,
+        } = self;
+
+        loop {
+            tokio::select! {
+                msg = recv(&mut subscription) => match msg {
+                    Ok(msg) => socket.send(msg).await?,
+                    Err(RecvError::Lagged(skipped)) => match on_lag {
+                        OnLag::Skip => {}
+                        OnLag::Disconnect => {
+                            close(socket, CloseCode::Again, "Too slow").await?;
+                            return Ok(PumpEnd::Lagged(skipped));
+                        }
+                    },
+                    Err(RecvError::Closed) => {
+                        close(socket, CloseCode::Normal, "").await?;
+                        return Ok(PumpEnd::ChannelClosed);
+                    }
+                },
+                msg = socket.recv() => match msg {
+                    Some(Ok(msg @ (Message::Text(_) | Message::Binary(_)))) => {
+                        if let Some(tx) = &publish {
+                            let _ = tx.send(msg);
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => return Err(err),
+                    None => return Ok(PumpEnd::ClientClosed),
+                },
+            }
+        }
+    }
+}
+
+async fn recv(rx: &mut Option<broadcast::Receiver<Message>>) -> Result<Message, RecvError> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => pending().await,
+    }
+}
+
+async fn close(socket: &mut WebSocket, code: CloseCode, reason: &'static str) -> Result<(), Error> {
+    let frame = CloseFrame {
+        code,
+        reason: reason.into(),
+    };
+    socket.send(Message::Close(Some(frame))).await
+}
+
+/// What a [`Pump`] does when the socket falls behind the subscribed channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OnLag {
+    /// Skip the missed messages and continue with the oldest one still in the channel.
+    #[default]
+    Skip,
+    /// Close the socket with `1013 Try Again Later` and stop with [`PumpEnd::Lagged`].
+    Disconnect,
+}
+
+/// Why a [`Pump`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PumpEnd {
+    /// The client closed the connection.
+    ClientClosed,
+    /// All senders of the subscribed channel were dropped.
+    ChannelClosed,
+    /// The socket fell behind by the given number of messages and was disconnected.
+    Lagged(u64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::sync::oneshot;
+
+    fn close_frame(code: CloseCode, reason: &'static str) -> Message {
+        Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        }))
+    }
+
+    #[tokio::test]
+    async fn lagging_sockets_skip_missed_messages_by_default() {
+        let (tx, rx) = broadcast::channel(2);
+        for n in 0..5 {
+            tx.send(Message::Text(n.to_string())).unwrap();
+        }
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = test_helpers::serve(move |mut socket| async move {
+            done_tx
+                .send(Pump::new(&mut socket).subscribe(rx).run().await)
+                .unwrap();
+        })
+        .await;
+
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            Message::Text("3".into())
+        );
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            Message::Text("4".into())
+        );
+        drop(tx);
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            close_frame(CloseCode::Normal, "")
+        );
+        assert_eq!(done_rx.await.unwrap().unwrap(), PumpEnd::ChannelClosed);
+    }
+
+    #[tokio::test]
+    async fn lagging_sockets_can_be_disconnected() {
+        let (tx, rx) = broadcast::channel(2);
+        for n in 0..5 {
+            tx.send(Message::Text(n.to_string())).unwrap();
+        }
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = test_helpers::serve(move |mut socket| async move {
+            let pump = Pump::new(&mut socket).subscribe(rx);
+            done_tx
+                .send(pump.on_lag(OnLag::Disconnect).run().await)
+                .unwrap();
+        })
+        .await;
+
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            close_frame(CloseCode::Again, "Too slow")
+        );
+        assert_eq!(done_rx.await.unwrap().unwrap(), PumpEnd::Lagged(3));
+    }
+
+    #[tokio::test]
+    async fn client_messages_are_published() {
+        let (tx, mut published) = broadcast::channel(8);
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = test_helpers::serve(move |mut socket| async move {
+            done_tx
+                .send(Pump::new(&mut socket).publish(tx).run().await)
+                .unwrap();
+        })
+        .await;
+
+        client.send(Message::Text("hi".into())).await.unwrap();
+        client.send(Message::Ping(vec![1])).await.unwrap();
+        client.send(Message::Binary(vec![2])).await.unwrap();
+        client.close(None).await.unwrap();
+
+        assert_eq!(published.recv().await.unwrap(), Message::Text("hi".into()));
+        assert_eq!(published.recv().await.unwrap(), Message::Binary(vec![2]));
+        assert_eq!(done_rx.await.unwrap().unwrap(), PumpEnd::ClientClosed);
+        assert!(matches!(published.recv().await, Err(RecvError::Closed)));
+    }
+}
@@ -0,0 +1,576 @@
+//! Client connections that reconnect automatically.
+//!
+//! [`Reconnect::spawn`] runs a client connection in its own task and returns a
+//! [`ReconnectingWebSocket`] handle, like [`actor`](crate::actor) does for accepted
+//! connections. Whenever the connection drops, the task connects again, waiting between
+//! attempts according to a [`Backoff`]. Messages from [`Reconnect::on_connect`] are sent after
+//! every successful connect, which is where subscriptions to upstream feeds are renewed.
+//!
+//! Received messages and connection state changes are delivered as [`ReconnectEvent`]s.
+//!
+//! Requires the `client` feature.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use axum_tungstenite::{
+//!     reconnect::{Backoff, Reconnect, ReconnectEvent},
+//!     Message,
+//! };
+//! use http::HeaderMap;
+//! use std::time::Duration;
+//!
+//! # async fn run() {
+//! let feed = Reconnect::new("ws://upstream.internal/feed".parse().unwrap(), HeaderMap::new())
+//!     .backoff(Backoff::exponential(
+//!         Duration::from_millis(500),
+//!         Duration::from_secs(30),
+//!     ))
+//!     .on_connect(|| [Message::Text(r#"{"subscribe":"prices"}"#.into())])
+//!     .spawn();
+//!
+//! let mut events = feed.subscribe();
+//! while let Ok(event) = events.recv().await {
+//!     match event {
+//!         ReconnectEvent::Message(msg) => { /* ... */ }
+//!         ReconnectEvent::Disconnected(error) => { /* ... */ }
+//!         ReconnectEvent::Closed => break,
+//!         _ => {}
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::{client, Error, Message, WebSocket};
+use futures_util::future::{BoxFuture, FutureExt};
+use http::{HeaderMap, Uri};
+use rand::Rng;
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+const COMMAND_CAPACITY: usize = 32;
+const EVENT_CAPACITY: usize = 64;
+
+type Connect = Arc<dyn Fn() -> BoxFuture<'static, Result<WebSocket, Error>> + Send + Sync>;
+type OnConnect = Arc<dyn Fn() -> Vec<Message> + Send + Sync>;
+
+/// Configuration for a [`ReconnectingWebSocket`].
+///
+/// See the [module docs](self) for an example.
+pub struct Reconnect {
+    connect: Connect,
+    backoff: Backoff,
+    on_connect: Option<OnConnect>,
+}
+
+impl Reconnect {
+    /// Connect to `url` with [`client::connect`], sending `headers` with every upgrade request.
+    pub fn new(url: Uri, headers: HeaderMap) -> Self {
+        Self::with_connector(move || client::connect(url.clone(), headers.clone()))
+    }
+
+    /// Open connections by calling `connect`.
+    ///
+    /// Useful for transports [`client::connect`] doesn't support, such as TLS with
+    /// [`client::connect_with_io`].
+    pub fn with_connector<F, Fut>(connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<WebSocket, Error>> + Send + 'static,
+    {
+        Self {
+            connect: Arc::new(move || connect().boxed()),
+            backoff: Backoff::default(),
+            on_connect: None,
+        }
+    }
+
+    /// Set how long to wait between connection attempts.
+    ///
+    /// Defaults to [`Backoff::default`].
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Send the messages returned by `on_connect` after every successful connect.
+    ///
+    /// They are sent before [`ReconnectEvent::Connected`] is emitted and before any messages
+    /// sent through the handle. If sending fails the connection counts as a failed attempt.
+    pub fn on_connect<F, I>(mut self, on_connect: F) -> Self
+    where
+        F: Fn() -> I + Send + Sync + 'static,
+        I: IntoIterator<Item = Message>,
+    {
+        self.on_connect = Some(Arc::new(move || on_connect().into_iter().collect()));
+        self
+    }
+
+    /// Spawn a task that maintains the connection and return a handle to it.
+    ///
+    /// The task stops when [`ReconnectingWebSocket::close`] is called, when all handles have
+    /// been dropped, or when the [`Backoff`] runs out of attempts.
+    pub fn spawn(self) -> ReconnectingWebSocket {
+        let (commands_tx, commands_rx) = mpsc::channel(COMMAND_CAPACITY);
+        let (events_tx, _) = broadcast::channel(EVENT_CAPACITY);
+        let connected = Arc::new(AtomicBool::new(false));
+
+        tokio::spawn(run(self, commands_rx, events_tx.clone(), connected.clone()));
+
+        ReconnectingWebSocket {
+            commands: commands_tx,
+            events: events_tx,
+            connected,
+        }
+    }
+}
+
+impl fmt::Debug for Reconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reconnect")
+            .field("backoff", &self.backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Exponential backoff between connection attempts.
+///
+/// The first attempt after the connection drops waits the initial delay, which doubles with
+/// every failed attempt up to the maximum. The delay resets once a connection succeeds.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    jitter: bool,
+    max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    /// Start at `initial` and grow up to `max`.
+    pub fn exponential(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            multiplier: 2.0,
+            jitter: true,
+            max_attempts: None,
+        }
+    }
+
+    /// Set the factor the delay grows by after each failed attempt.
+    ///
+    /// Defaults to `2.0`.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Whether to randomize delays, so many clients don't reconnect at the same time.
+    ///
+    /// With jitter, each delay is picked at random between half and all of the computed delay.
+    /// Defaults to `true`.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Give up after `attempts` consecutive failed connection attempts.
+    ///
+    /// Defaults to retrying forever.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// The delay before the retry following `retries` earlier ones.
+    fn delay(&self, retries: u32) -> Duration {
+        let factor = self.multiplier.powi(retries.min(i32::MAX as u32) as i32);
+        let delay = Duration::try_from_secs_f64(self.initial.as_secs_f64() * factor.max(1.0))
+            .map_or(self.max, |delay| delay.min(self.max));
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+
+    fn gives_up_after(&self, failures: u32) -> bool {
+        self.max_attempts.is_some_and(|max| failures >= max)
+    }
+}
+
+impl Default for Backoff {
+    /// Start at 1 second and grow up to 30 seconds.
+    fn default() -> Self {
+        Self::exponential(Duration::from_secs(1), Duration::from_secs(30))
+    }
+}
+
+/// A handle to a connection maintained by [`Reconnect::spawn`].
+#[derive(Debug, Clone)]
+pub struct ReconnectingWebSocket {
+    commands: mpsc::Sender<Command>,
+    events: broadcast::Sender<ReconnectEvent>,
+    connected: Arc<AtomicBool>,
+}
+
+impl ReconnectingWebSocket {
+    /// Send a message and wait for it to be written.
+    ///
+    /// Messages aren't queued while disconnected, sending fails with [`Error::ConnectionClosed`]
+    /// instead. Fails with [`Error::AlreadyClosed`] if the task has stopped.
+    pub async fn send(&self, msg: Message) -> Result<(), Error> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.command(Command::Send(msg, reply_tx)).await?;
+        reply_rx.await.map_err(|_| Error::AlreadyClosed)?
+    }
+
+    /// Gracefully close the connection and stop reconnecting.
+    ///
+    /// Fails with [`Error::AlreadyClosed`] if the task has already stopped.
+    pub async fn close(&self) -> Result<(), Error> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.command(Command::Close(reply_tx)).await?;
+        reply_rx.await.map_err(|_| Error::AlreadyClosed)?
+    }
+
+    /// Returns `true` while connected.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` while the task is running, even if currently disconnected.
+    pub fn is_alive(&self) -> bool {
+        !self.commands.is_closed()
+    }
+
+    /// Subscribe to the events of the connection.
+    ///
+    /// Only events that happen after subscribing are received. Subscribers that fall more than 64
+    /// events behind miss the oldest ones, see [`broadcast::Receiver::recv`].
+    pub fn subscribe(&self) -> broadcast::Receiver<ReconnectEvent> {
+        self.events.subscribe()
+    }
+
+    async fn command(&self, command: Command) -> Result<(), Error> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| Error::AlreadyClosed)
+    }
+}
+
+/// An event emitted by a [`ReconnectingWebSocket`], received through
+/// [`ReconnectingWebSocket::subscribe`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ReconnectEvent {
+    /// A connection was established and the [`on_connect`](Reconnect::on_connect) messages
+    /// were sent.
+    Connected,
+    /// A message was received.
+    Message(Message),
+    /// The connection was lost, with the error that caused it or `None` if it was closed
+    /// without one. Reconnecting starts afterwards.
+    Disconnected(Option<Arc<Error>>),
+    /// A connection attempt failed. The next one is made after `retry_in`, or never if it's
+    /// `None`.
+    ConnectFailed {
+        /// Why the attempt failed.
+        error: Arc<Error>,
+        /// How long until the next attempt.
+        retry_in: Option<Duration>,
+    },
+    /// The task stopped. This is always the last event.
+    Closed,
+}
+
+#[derive(Debug)]
+enum Command {
+    Send(Message, oneshot::Sender<Result<(), Error>>),
+    Close(oneshot::Sender<Result<(), Error>>),
+}
+
+async fn run(
+    config: Reconnect,
+    mut commands: mpsc::Receiver<Command>,
+    events: broadcast::Sender<ReconnectEvent>,
+    connected: Arc<AtomicBool>,
+) {
+    let mut failures = 0;
+    let mut retries = 0;
+    let mut delay = None;
+
+    'reconnect: loop {
+        if let Some(delay) = delay.take() {
+            retries += 1;
+            if while_disconnected(tokio::time::sleep(delay), &mut commands)
+                .await
+                .is_none()
+            {
+                break;
+            }
+        }
+
+        let Some(result) = while_disconnected(connect(&config), &mut commands).await else {
+            break;
+        };
+        let mut socket = match result {
+            Ok(socket) => socket,
+            Err(err) => {
+                failures += 1;
+                let retry_in = (!config.backoff.gives_up_after(failures))
+                    .then(|| config.backoff.delay(retries));
+                let _ = events.send(ReconnectEvent::ConnectFailed {
+                    error: Arc::new(err),
+                    retry_in,
+                });
+                match retry_in {
+                    Some(retry_in) => {
+                        delay = Some(retry_in);
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+        };
+
+        failures = 0;
+        retries = 0;
+        connected.store(true, Ordering::Release);
+        let _ = events.send(ReconnectEvent::Connected);
+
+        let error = loop {
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(Command::Send(msg, reply)) => {
+                        let _ = reply.send(socket.send(msg).await);
+                    }
+                    Some(Command::Close(reply)) => {
+                        connected.store(false, Ordering::Release);
+                        let _ = reply.send(socket.close().await);
+                        break 'reconnect;
+                    }
+                    None => {
+                        connected.store(false, Ordering::Release);
+                        let _ = socket.close().await;
+                        break 'reconnect;
+                    }
+                },
+                msg = socket.recv() => match msg {
+                    Some(Ok(msg)) => {
+                        let _ = events.send(ReconnectEvent::Message(msg));
+                    }
+                    Some(Err(err)) => break Some(Arc::new(err)),
+                    None => break None,
+                },
+            }
+        };
+
+        connected.store(false, Ordering::Release);
+        let _ = events.send(ReconnectEvent::Disconnected(error));
+        delay = Some(config.backoff.delay(0));
+    }
+
+    let _ = events.send(ReconnectEvent::Closed);
+}
+
+/// Connect and send the `on_connect` messages.
+async fn connect(config: &Reconnect) -> Result<WebSocket, Error> {
+    let mut socket = (config.connect)().await?;
+    if let Some(on_connect) = &config.on_connect {
+        for msg in on_connect() {
+            socket.send(msg).await?;
+        }
+    }
+    Ok(socket)
+}
+
+/// Drive `future` while answering commands as disconnected. Returns `None` if the task should
+/// stop.
+async fn while_disconnected<F>(
+    future: F,
+    commands: &mut mpsc::Receiver<Command>,
+) -> Option<F::Output>
+where
+    F: Future,
+{
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            output = &mut future => return Some(output),
+            command = commands.recv() => match command {
+                Some(Command::Send(_, reply)) => {
+                    let _ = reply.send(Err(Error::ConnectionClosed));
+                }
+                Some(Command::Close(reply)) => {
+                    let _ = reply.send(Ok(()));
+                    return None;
+                }
+                None => return None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WebSocketUpgrade;
+    use axum::{routing::get, Router};
+    use hyper::server::conn::Http;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Connect to an in-memory server that echoes the first message of every connection and
+    /// then closes it.
+    async fn connect_to_echo_once() -> Result<WebSocket, Error> {
+        let app = Router::new().route(
+            "/",
+            get(|ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|mut socket| async move {
+                    if let Some(Ok(msg)) = socket.recv().await {
+                        let _ = socket.send(msg).await;
+                        let _ = socket.send(Message::Close(None)).await;
+                        while socket.recv().await.is_some() {}
+                    }
+                })
+            }),
+        );
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(Http::new().serve_connection(server, app).with_upgrades());
+        client::connect_with_io(client, "ws://localhost/", HeaderMap::new()).await
+    }
+
+    /// The next event that isn't a received `Close` frame.
+    async fn next_event(events: &mut broadcast::Receiver<ReconnectEvent>) -> ReconnectEvent {
+        loop {
+            match events.recv().await.unwrap() {
+                ReconnectEvent::Message(Message::Close(_)) => {}
+                event => return event,
+            }
+        }
+    }
+
+    fn no_delay() -> Backoff {
+        Backoff::exponential(Duration::from_millis(1), Duration::from_millis(1)).jitter(false)
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_maximum() {
+        let backoff = Backoff::exponential(Duration::from_secs(1), Duration::from_secs(10))
+            .multiplier(3.0)
+            .jitter(false);
+        let delays = (0..4).map(|retries| backoff.delay(retries).as_secs());
+        assert_eq!(delays.collect::<Vec<_>>(), [1, 3, 9, 10]);
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(10));
+
+        let backoff = backoff.jitter(true);
+        for _ in 0..100 {
+            let delay = backoff.delay(1);
+            assert!(delay >= Duration::from_millis(1500) && delay <= Duration::from_secs(3));
+        }
+
+        assert!(!backoff.gives_up_after(100));
+        assert!(!backoff.max_attempts(2).gives_up_after(1));
+        assert!(backoff.max_attempts(2).gives_up_after(2));
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_resends_the_on_connect_messages() {
+        let socket = Reconnect::with_connector(connect_to_echo_once)
+            .backoff(no_delay())
+            .on_connect(|| [Message::Text("subscribe".into())])
+            .spawn();
+        let mut events = socket.subscribe();
+
+        for _ in 0..2 {
+            assert!(matches!(
+                next_event(&mut events).await,
+                ReconnectEvent::Connected
+            ));
+            assert!(matches!(
+                next_event(&mut events).await,
+                ReconnectEvent::Message(Message::Text(text)) if text == "subscribe"
+            ));
+            assert!(matches!(
+                next_event(&mut events).await,
+                ReconnectEvent::Disconnected(None)
+            ));
+        }
+
+        socket.close().await.unwrap();
+        while !matches!(next_event(&mut events).await, ReconnectEvent::Closed) {}
+        assert!(!socket.is_connected());
+        assert!(matches!(
+            socket.send(Message::Text("late".into())).await,
+            Err(Error::AlreadyClosed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_maximum_attempts() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let socket = Reconnect::with_connector({
+            let attempts = attempts.clone();
+            move || {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                async { Err(Error::ConnectionClosed) }
+            }
+        })
+        .backoff(no_delay().max_attempts(3))
+        .spawn();
+        let mut events = socket.subscribe();
+
+        for retry_in in [
+            Some(Duration::from_millis(1)),
+            Some(Duration::from_millis(1)),
+            None,
+        ] {
+            match next_event(&mut events).await {
+                ReconnectEvent::ConnectFailed {
+                    error,
+                    retry_in: actual,
+                } => {
+                    assert!(matches!(*error, Error::ConnectionClosed));
+                    assert_eq!(actual, retry_in);
+                }
+                event => panic!("unexpected event {event:?}"),
+            }
+        }
+        assert!(matches!(
+            next_event(&mut events).await,
+            ReconnectEvent::Closed
+        ));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(!socket.is_alive());
+    }
+
+    #[tokio::test]
+    async fn sending_while_disconnected_fails() {
+        let socket = Reconnect::with_connector(std::future::pending).spawn();
+        let mut events = socket.subscribe();
+
+        assert!(socket.is_alive());
+        assert!(!socket.is_connected());
+        assert!(matches!(
+            socket.send(Message::Text("hi".into())).await,
+            Err(Error::ConnectionClosed)
+        ));
+
+        socket.close().await.unwrap();
+        assert!(matches!(
+            next_event(&mut events).await,
+            ReconnectEvent::Closed
+        ));
+        assert!(matches!(socket.close().await, Err(Error::AlreadyClosed)));
+    }
+}
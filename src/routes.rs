@@ -0,0 +1,69 @@
+//! Ready-made WebSocket routes.
+//!
+//! These can be mounted into any [`Router`](axum::Router) to verify the WebSocket path end to
+//! end, through proxies and load balancers, without writing custom handlers.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::Router;
+//! use axum_tungstenite::routes;
+//!
+//! let app = Router::new()
+//!     .route("/ws/echo", routes::echo())
+//!     .route("/ws/health", routes::health());
+//! # let _: Router = app;
+//! ```
+
+use crate::{CloseCode, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::routing::{get, MethodRouter};
+use std::time::Duration;
+
+/// How long [`health`] waits for the client to complete the closing handshake.
+const HEALTH_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A route that sends every `Text` and `Binary` message back to the client.
+///
+/// The connection stays open until the client closes it.
+pub fn echo<S>() -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    get(|ws: WebSocketUpgrade| async move { ws.on_upgrade(echo_socket) })
+}
+
+async fn echo_socket(mut socket: WebSocket) {
+    while let Some(Ok(msg)) = socket.recv().await {
+        if (msg.is_text() || msg.is_binary()) && socket.send(msg).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// A liveness route that completes a full upgrade round trip.
+///
+/// After upgrading, the server sends a `Text` message containing `ok` and closes the connection
+/// with [`CloseCode::Normal`]. A check passes if it receives `ok` followed by the close frame.
+/// Requests that aren't valid WebSocket upgrades are rejected like with [`WebSocketUpgrade`].
+pub fn health<S>() -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    get(|ws: WebSocketUpgrade| async move {
+        ws.close_timeout(HEALTH_CLOSE_TIMEOUT)
+            .on_upgrade(health_socket)
+    })
+}
+
+async fn health_socket(mut socket: WebSocket) {
+    if socket.send(Message::Text("ok".to_owned())).await.is_err() {
+        return;
+    }
+    let frame = CloseFrame {
+        code: CloseCode::Normal,
+        reason: "".into(),
+    };
+    if socket.send(Message::Close(Some(frame))).await.is_ok() {
+        let _ = socket.await_close().await;
+    }
+}
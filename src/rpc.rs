@@ -0,0 +1,547 @@
+//! Request/response correlation over a single WebSocket.
+//!
+//! [`Rpc`] takes ownership of a [`WebSocket`] and allows both peers to call methods on each other.
+//! Every request is tagged with a correlation ID and [`Rpc::call`] resolves once the reply with
+//! the same ID arrives, or fails if none arrives in time. Incoming requests are routed to the
+//! handlers registered on a [`Dispatcher`].
+//!
+//! # Wire format
+//!
+//! Every frame is sent as a binary WebSocket message that starts with a 1 byte frame kind
+//! followed by an 8 byte big endian correlation ID:
+//!
+//! - `0`, request: followed by a 2 byte big endian method name length, the UTF-8 method name, and
+//!   the payload.
+//! - `1`, success response: followed by the payload.
+//! - `2`, error response: followed by a UTF-8 error message.
+//!
+//! # Concurrency
+//!
+//! At most [`max_concurrent_requests`](Dispatcher::max_concurrent_requests) incoming requests
+//! are handled at once. Requests that arrive while that many are in progress are queued until
+//! one of the handlers finishes, and once as many requests are queued as may be handled at once
+//! further requests are answered with an error response. Replies to calls are read regardless,
+//! so handlers may call back into the peer.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::response::Response;
+//! use axum_tungstenite::{
+//!     rpc::{Dispatcher, Rpc},
+//!     WebSocketUpgrade,
+//! };
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|socket| async move {
+//!         let dispatcher = Dispatcher::new()
+//!             .handle("echo", |payload| async move { Ok(payload) })
+//!             .handle("fail", |_| async move { Err("nope".to_owned()) });
+//!
+//!         let rpc = Rpc::new(socket, dispatcher);
+//!
+//!         // the client can also handle requests from the server
+//!         match rpc.call("ping", "hello".into()).await {
+//!             Ok(reply) => println!("client replied with {:?}", reply),
+//!             Err(err) => println!("call failed: {}", err),
+//!         }
+//!     })
+//! }
+//! ```
+
+use crate::{Message, WebSocket};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::future::BoxFuture;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+
+const REQUEST: u8 = 0;
+const RESPONSE: u8 = 1;
+const ERROR: u8 = 2;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 64;
+
+type Handler = Arc<dyn Fn(Bytes) -> BoxFuture<'static, Result<Bytes, String>> + Send + Sync>;
+
+/// Handlers for incoming requests, keyed by method name.
+///
+/// Requests for methods without a handler are answered with an error response.
+#[derive(Clone)]
+pub struct Dispatcher {
+    handlers: HashMap<String, Handler>,
+    max_concurrent_requests: usize,
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+        }
+    }
+}
+
+impl Dispatcher {
+    /// Create a new `Dispatcher` without any handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a handler for `method`.
+    ///
+    /// Handlers run concurrently on their own tasks, up to
+    /// [`max_concurrent_requests`](Self::max_concurrent_requests) at once. Returning `Err` sends
+    /// an error response with the given message, which the caller receives as
+    /// [`CallError::Remote`]. If the handler panics an error response is sent as well.
+    pub fn handle<F, Fut>(mut self, method: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Bytes) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Bytes, String>> + Send + 'static,
+    {
+        self.handlers.insert(
+            method.into(),
+            Arc::new(move |payload| Box::pin(handler(payload))),
+        );
+        self
+    }
+
+    /// Set how many incoming requests may be handled at once.
+    ///
+    /// See the [module docs](self#concurrency) for what happens at the limit. Defaults to 64.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn max_concurrent_requests(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_concurrent_requests must be greater than zero");
+        self.max_concurrent_requests = max;
+        self
+    }
+}
+
+impl fmt::Debug for Dispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("methods", &self.handlers.keys().collect::<Vec<_>>())
+            .field("max_concurrent_requests", &self.max_concurrent_requests)
+            .finish()
+    }
+}
+
+/// Correlates requests and responses over a [`WebSocket`].
+///
+/// `Rpc` is cheap to clone so calls can be made from several tasks at once.
+///
+/// See the [module docs](self) for more details.
+#[derive(Debug, Clone)]
+pub struct Rpc {
+    shared: Arc<Shared>,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Shared {
+    next_id: AtomicU64,
+    frames_tx: mpsc::UnboundedSender<Bytes>,
+    /// `None` once the socket has closed.
+    pending: Mutex<Option<HashMap<u64, oneshot::Sender<Result<Bytes, String>>>>>,
+}
+
+impl Rpc {
+    /// Create a new `Rpc` that answers incoming requests using `dispatcher`.
+    ///
+    /// This spawns a task that drives the socket. The task ends when the socket closes, after
+    /// which all outstanding and future calls fail with [`CallError::Closed`].
+    pub fn new(socket: WebSocket, dispatcher: Dispatcher) -> Self {
+        let (frames_tx, frames_rx) = mpsc::unbounded_channel();
+
+        let shared = Arc::new(Shared {
+            next_id: AtomicU64::new(0),
+            frames_tx,
+            pending: Mutex::new(Some(HashMap::new())),
+        });
+
+        tokio::spawn(drive(socket, shared.clone(), dispatcher, frames_rx));
+
+        Self {
+            shared,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Set how long [`call`](Self::call) waits for a reply.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Call `method` on the peer and wait for its reply.
+    pub async fn call(&self, method: &str, payload: Bytes) -> Result<Bytes, CallError> {
+        let method_len = u16::try_from(method.len()).map_err(|_| CallError::MethodTooLong)?;
+
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        self.shared
+            .pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or(CallError::Closed)?
+            .insert(id, reply_tx);
+
+        let mut frame = BytesMut::with_capacity(11 + method.len() + payload.len());
+        frame.put_u8(REQUEST);
+        frame.put_u64(id);
+        frame.put_u16(method_len);
+        frame.put_slice(method.as_bytes());
+        frame.put_slice(&payload);
+
+        if self.shared.frames_tx.send(frame.freeze()).is_err() {
+            self.forget(id);
+            return Err(CallError::Closed);
+        }
+
+        match tokio::time::timeout(self.timeout, reply_rx).await {
+            Ok(Ok(Ok(reply))) => Ok(reply),
+            Ok(Ok(Err(message))) => Err(CallError::Remote(message)),
+            Ok(Err(_)) => Err(CallError::Closed),
+            Err(_) => {
+                self.forget(id);
+                Err(CallError::Timeout)
+            }
+        }
+    }
+
+    fn forget(&self, id: u64) {
+        if let Some(pending) = self.shared.pending.lock().unwrap().as_mut() {
+            pending.remove(&id);
+        }
+    }
+}
+
+async fn drive(
+    mut socket: WebSocket,
+    shared: Arc<Shared>,
+    dispatcher: Dispatcher,
+    mut frames_rx: mpsc::UnboundedReceiver<Bytes>,
+) {
+    let max = dispatcher.max_concurrent_requests;
+    let semaphore = Arc::new(Semaphore::new(max));
+    // requests waiting for one of the handlers to finish
+    let mut queued = VecDeque::new();
+
+    loop {
+        tokio::select! {
+            acquired = semaphore.clone().acquire_owned(), if !queued.is_empty() => {
+                let permit = match acquired {
+                    Ok(permit) => permit,
+                    Err(_) => break,
+                };
+                if let Some(request) = queued.pop_front() {
+                    spawn_request(&shared, &dispatcher, request, permit);
+                }
+            }
+            msg = socket.recv() => {
+                let frame = match msg {
+                    Some(Ok(Message::Binary(frame))) => frame,
+                    Some(Ok(_)) => continue,
+                    Some(Err(_)) | None => break,
+                };
+                let request = match handle_frame(&shared, Bytes::from(frame)) {
+                    Some(request) => request,
+                    None => continue,
+                };
+                if queued.is_empty() {
+                    if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+                        spawn_request(&shared, &dispatcher, request, permit);
+                        continue;
+                    }
+                }
+                if queued.len() < max {
+                    queued.push_back(request);
+                } else {
+                    let body = Bytes::from_static(b"too many concurrent requests");
+                    let _ = shared.frames_tx.send(encode_reply(ERROR, request.id, &body));
+                }
+            }
+            Some(frame) = frames_rx.recv() => {
+                if socket.send(Message::Binary(frame.to_vec())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // dropping the senders fails all outstanding calls
+    shared.pending.lock().unwrap().take();
+}
+
+/// An incoming request.
+struct Request {
+    id: u64,
+    method: Bytes,
+    payload: Bytes,
+}
+
+/// Handle a frame from the peer, returning it if it's a request.
+fn handle_frame(shared: &Shared, mut frame: Bytes) -> Option<Request> {
+    if frame.len() < 9 {
+        return None;
+    }
+    let kind = frame.get_u8();
+    let id = frame.get_u64();
+
+    match kind {
+        REQUEST => {
+            if frame.len() < 2 {
+                return None;
+            }
+            let method_len = frame.get_u16() as usize;
+            if frame.len() < method_len {
+                return None;
+            }
+            let method = frame.split_to(method_len);
+            return Some(Request {
+                id,
+                method,
+                payload: frame,
+            });
+        }
+        RESPONSE | ERROR => {
+            let reply_tx = shared
+                .pending
+                .lock()
+                .unwrap()
+                .as_mut()
+                .and_then(|pending| pending.remove(&id));
+
+            if let Some(reply_tx) = reply_tx {
+                let reply = if kind == RESPONSE {
+                    Ok(frame)
+                } else {
+                    Err(String::from_utf8_lossy(&frame).into_owned())
+                };
+                let _ = reply_tx.send(reply);
+            }
+        }
+        _ => {}
+    }
+
+    None
+}
+
+fn spawn_request(
+    shared: &Shared,
+    dispatcher: &Dispatcher,
+    request: Request,
+    permit: OwnedSemaphorePermit,
+) {
+    let Request {
+        id,
+        method,
+        payload,
+    } = request;
+    let handler = std::str::from_utf8(&method)
+        .ok()
+        .and_then(|method| dispatcher.handlers.get(method))
+        .cloned();
+    let frames_tx = shared.frames_tx.clone();
+
+    tokio::spawn(async move {
+        let result = match handler {
+            // run the handler on its own task so a panic can be answered
+            Some(handler) => match tokio::spawn(handler(payload)).await {
+                Ok(result) => result,
+                Err(_) => Err("handler failed".to_owned()),
+            },
+            None => Err(format!(
+                "unknown method `{}`",
+                String::from_utf8_lossy(&method)
+            )),
+        };
+
+        let (kind, body) = match result {
+            Ok(reply) => (RESPONSE, reply),
+            Err(message) => (ERROR, Bytes::from(message)),
+        };
+
+        let _ = frames_tx.send(encode_reply(kind, id, &body));
+        drop(permit);
+    });
+}
+
+fn encode_reply(kind: u8, id: u64, body: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(9 + body.len());
+    frame.put_u8(kind);
+    frame.put_u64(id);
+    frame.put_slice(body);
+    frame.freeze()
+}
+
+/// Error returned by [`Rpc::call`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CallError {
+    /// No reply arrived within the [timeout](Rpc::timeout).
+    Timeout,
+    /// The socket closed before a reply arrived.
+    Closed,
+    /// The peer's handler failed or the peer has no handler for the method.
+    Remote(String),
+    /// The method name is longer than 65535 bytes.
+    MethodTooLong,
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "Timed out waiting for a reply"),
+            Self::Closed => write!(f, "Connection closed before a reply arrived"),
+            Self::Remote(message) => write!(f, "Remote error: {}", message),
+            Self::MethodTooLong => write!(f, "Method name is longer than 65535 bytes"),
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_helpers, WebSocketUpgrade};
+    use axum::{routing::get, Router};
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::time::timeout;
+
+    fn request(id: u64, method: &str) -> Message {
+        let mut frame = BytesMut::new();
+        frame.put_u8(REQUEST);
+        frame.put_u64(id);
+        frame.put_u16(method.len() as u16);
+        frame.put_slice(method.as_bytes());
+        Message::Binary(frame.to_vec())
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_limit_wait() {
+        let gate = Arc::new(Semaphore::new(0));
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let dispatcher = Dispatcher::new()
+            .handle("wait", {
+                let gate = gate.clone();
+                move |_| {
+                    let gate = gate.clone();
+                    let started_tx = started_tx.clone();
+                    async move {
+                        started_tx.send(()).unwrap();
+                        gate.acquire().await.unwrap().forget();
+                        Ok(Bytes::new())
+                    }
+                }
+            })
+            .max_concurrent_requests(1);
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|socket| async move {
+                    let _rpc = Rpc::new(socket, dispatcher);
+                    std::future::pending::<()>().await
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 1024).await;
+
+        client.send(request(1, "wait")).await.unwrap();
+        client.send(request(2, "wait")).await.unwrap();
+
+        started_rx.recv().await.unwrap();
+        assert!(timeout(Duration::from_millis(100), started_rx.recv())
+            .await
+            .is_err());
+
+        gate.add_permits(1);
+        let reply = client.next().await.unwrap().unwrap().into_data();
+        assert_eq!(reply[0], RESPONSE);
+        assert_eq!(reply[1..9], 1u64.to_be_bytes());
+        started_rx.recv().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn handlers_can_call_back_into_the_peer_at_the_limit() {
+        let rpc = Arc::new(Mutex::new(None::<Rpc>));
+        let dispatcher = Dispatcher::new()
+            .handle("ask", {
+                let rpc = rpc.clone();
+                move |_| {
+                    let rpc = rpc.lock().unwrap().clone().unwrap();
+                    async move {
+                        rpc.call("name", Bytes::new())
+                            .await
+                            .map_err(|err| err.to_string())
+                    }
+                }
+            })
+            .max_concurrent_requests(1);
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(move |socket| async move {
+                    *rpc.lock().unwrap() = Some(Rpc::new(socket, dispatcher));
+                    std::future::pending::<()>().await
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 1024).await;
+
+        client.send(request(1, "ask")).await.unwrap();
+
+        let call = client.next().await.unwrap().unwrap().into_data();
+        assert_eq!(call[0], REQUEST);
+        let mut reply = BytesMut::new();
+        reply.put_u8(RESPONSE);
+        reply.put_slice(&call[1..9]);
+        reply.put_slice(b"client");
+        client.send(Message::Binary(reply.to_vec())).await.unwrap();
+
+        let reply = client.next().await.unwrap().unwrap().into_data();
+        assert_eq!(reply[0], RESPONSE);
+        assert_eq!(reply[1..9], 1u64.to_be_bytes());
+        assert_eq!(&reply[9..], b"client");
+    }
+
+    #[tokio::test]
+    async fn panicking_handlers_send_an_error() {
+        let dispatcher = Dispatcher::new().handle("panic", |_| async move {
+            panic!("handler panicked");
+        });
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|socket| async move {
+                    let _rpc = Rpc::new(socket, dispatcher);
+                    std::future::pending::<()>().await
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 1024).await;
+
+        client.send(request(1, "panic")).await.unwrap();
+
+        let reply = client.next().await.unwrap().unwrap().into_data();
+        assert_eq!(reply[0], ERROR);
+        assert_eq!(reply[1..9], 1u64.to_be_bytes());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::Message;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Identifies `Ping`s sent by [`WebSocket::send_rtt_ping`](crate::WebSocket::send_rtt_ping) so
+/// they aren't confused with `Ping`s sent by the application.
+const PAYLOAD_PREFIX: &[u8] = b"rtt:";
+
+/// Round trip time measurements of a [`WebSocket`](crate::WebSocket).
+///
+/// Returned by [`WebSocket::rtt`](crate::WebSocket::rtt).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rtt {
+    latest: Duration,
+    smoothed: Duration,
+    samples: u64,
+}
+
+impl Rtt {
+    /// The most recent measurement.
+    pub fn latest(&self) -> Duration {
+        self.latest
+    }
+
+    /// An exponentially weighted moving average of the measurements, weighing each new
+    /// measurement by 1/8 like TCP's smoothed round trip time.
+    pub fn smoothed(&self) -> Duration {
+        self.smoothed
+    }
+
+    /// The number of measurements taken.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RttTracker {
+    next_seq: u64,
+    /// The sequence number of the outstanding `Ping` and when it was sent.
+    pending: Option<(u64, Instant)>,
+    rtt: Option<Rtt>,
+}
+
+impl RttTracker {
+    /// Create the next `Ping` to send, replacing any outstanding one.
+    pub(crate) fn ping(&mut self) -> Message {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.pending = Some((seq, Instant::now()));
+        Message::Ping(payload(seq))
+    }
+
+    pub(crate) fn record_pong(&mut self, msg: &Message) {
+        let sent = match (msg, self.pending) {
+            (Message::Pong(data), Some((seq, sent))) if *data == payload(seq) => sent,
+            _ => return,
+        };
+        self.pending = None;
+
+        let sample = sent.elapsed();
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => Rtt {
+                latest: sample,
+                smoothed: (rtt.smoothed * 7 + sample) / 8,
+                samples: rtt.samples + 1,
+            },
+            None => Rtt {
+                latest: sample,
+                smoothed: sample,
+                samples: 1,
+            },
+        });
+    }
+
+    pub(crate) fn rtt(&self) -> Option<Rtt> {
+        self.rtt
+    }
+}
+
+fn payload(seq: u64) -> Vec<u8> {
+    let mut payload = PAYLOAD_PREFIX.to_vec();
+    payload.extend_from_slice(&seq.to_be_bytes());
+    payload
+}
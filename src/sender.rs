@@ -0,0 +1,220 @@
+use crate::{close::Telemetry, Error, Message, WebSocket};
+use futures_util::sink::{Sink, SinkExt};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+
+const CAPACITY: usize = 32;
+
+/// A cloneable handle for queueing messages on a [`WebSocket`](crate::WebSocket) from other
+/// tasks.
+///
+/// Messages go into a queue of up to 32 messages that the socket drains whenever it's polled,
+/// for example by [`WebSocket::recv`](crate::WebSocket::recv) or
+/// [`WebSocket::send`](crate::WebSocket::send). There is no separate writer task, so the task
+/// owning the socket must keep receiving from it for queued messages to be written.
+///
+/// Created with [`WebSocket::queued_sender`](crate::WebSocket::queued_sender).
+#[derive(Debug, Clone)]
+pub struct QueuedSender {
+    tx: mpsc::Sender<Message>,
+}
+
+impl QueuedSender {
+    /// Queue a message to be sent.
+    ///
+    /// Waits while the queue is full, until the socket is polled again. Fails with
+    /// [`Error::AlreadyClosed`] once the socket has been dropped or failed to write.
+    pub async fn send(&self, msg: Message) -> Result<(), Error> {
+        self.tx.send(msg).await.map_err(|_| Error::AlreadyClosed)
+    }
+
+    /// Returns `true` if the socket has been dropped or failed to write.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+/// Permission to send one message on a [`WebSocket`].
+///
+/// Created with [`WebSocket::reserve`].
+#[derive(Debug)]
+#[must_use = "the reserved capacity is wasted unless a message is sent"]
+pub struct SendPermit<'a> {
+    socket: &'a mut WebSocket,
+}
+
+impl<'a> SendPermit<'a> {
+    pub(crate) fn new(socket: &'a mut WebSocket) -> Self {
+        Self { socket }
+    }
+
+    /// Queue `msg` for sending.
+    ///
+    /// The message is written once the socket is flushed, for example by
+    /// [`WebSocket::flush`] or the next [`WebSocket::send`].
+    #[allow(clippy::result_large_err)]
+    pub fn send(self, msg: Message) -> Result<(), Error> {
+        self.socket.start_send_unpin(msg)
+    }
+}
+
+/// The receiving end of [`QueuedSender`]s, drained by the socket while it is polled.
+#[derive(Debug)]
+pub(crate) struct Outgoing {
+    tx: mpsc::Sender<Message>,
+    rx: mpsc::Receiver<Message>,
+    pending: Option<Message>,
+    needs_flush: bool,
+}
+
+impl Outgoing {
+    pub(crate) fn new() -> Self {
+        let (tx, rx) = mpsc::channel(CAPACITY);
+        Self {
+            tx,
+            rx,
+            pending: None,
+            needs_flush: false,
+        }
+    }
+
+    pub(crate) fn sender(&self) -> QueuedSender {
+        QueuedSender {
+            tx: self.tx.clone(),
+        }
+    }
+
+    /// Write as many queued messages to `sink` as possible without waiting.
+    pub(crate) fn poll_write<S>(
+        &mut self,
+        mut sink: Pin<&mut S>,
+        telemetry: &mut Telemetry,
+        cx: &mut Context<'_>,
+    ) where
+        S: Sink<Message, Error = Error>,
+    {
+        if self.rx.is_closed() {
+            return;
+        }
+
+        if self.try_poll_write(sink.as_mut(), telemetry, cx).is_err() {
+            // the error surfaces through the socket itself, senders just see it as closed
+            self.rx.close();
+            self.pending = None;
+            self.needs_flush = false;
+        }
+    }
+
+    /// Returns `true` if the last [`poll_write`](Self::poll_write) wrote and flushed everything
+    /// that was queued.
+    pub(crate) fn is_drained(&self) -> bool {
+        self.pending.is_none() && !self.needs_flush
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn try_poll_write<S>(
+        &mut self,
+        mut sink: Pin<&mut S>,
+        telemetry: &mut Telemetry,
+        cx: &mut Context<'_>,
+    ) -> Result<(), Error>
+    where
+        S: Sink<Message, Error = Error>,
+    {
+        loop {
+            if self.pending.is_none() {
+                match self.rx.poll_recv(cx) {
+                    Poll::Ready(Some(msg)) => self.pending = Some(msg),
+                    Poll::Ready(None) | Poll::Pending => break,
+                }
+            }
+
+            match sink.as_mut().poll_ready(cx)? {
+                Poll::Ready(()) => {
+                    let msg = self.pending.take().expect("pending message");
+                    telemetry.record_sent(&msg);
+                    sink.as_mut().start_send(msg)?;
+                    self.needs_flush = true;
+                }
+                Poll::Pending => return Ok(()),
+            }
+        }
+
+        if self.needs_flush && sink.poll_flush(cx)?.is_ready() {
+            self.needs_flush = false;
+            telemetry.record_flushed();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_helpers, WebSocketUpgrade};
+    use axum::{routing::get, Router};
+    use futures_util::StreamExt;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn queued_messages_are_written_while_receiving() {
+        let app = Router::new().route(
+            "/",
+            get(|ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|mut socket| async move {
+                    let sender = socket.queued_sender();
+                    tokio::spawn(async move {
+                        sender
+                            .send(Message::Text("queued".to_owned()))
+                            .await
+                            .unwrap();
+                    });
+                    while let Some(Ok(_)) = socket.recv().await {}
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 1024).await;
+
+        let msg = client.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Text("queued".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn queue_fills_up_while_the_socket_isnt_polled() {
+        let (full_tx, mut full_rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(move |mut socket| async move {
+                    let sender = socket.queued_sender();
+                    for _ in 0..CAPACITY {
+                        sender
+                            .send(Message::Text("queued".to_owned()))
+                            .await
+                            .unwrap();
+                    }
+                    let blocked = timeout(
+                        Duration::from_millis(50),
+                        sender.send(Message::Text("queued".to_owned())),
+                    )
+                    .await
+                    .is_err();
+                    full_tx.send(blocked).unwrap();
+                    while let Some(Ok(_)) = socket.recv().await {}
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 64 * 1024).await;
+
+        assert!(full_rx.recv().await.unwrap());
+        for _ in 0..CAPACITY {
+            let msg = client.next().await.unwrap().unwrap();
+            assert_eq!(msg, Message::Text("queued".to_owned()));
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Tower services that handle WebSocket connections.
+//!
+//! [`WsService`] turns a [`Service`] that takes a [`WebSocket`] into a service that takes HTTP
+//! requests, upgrades them, and passes the sockets on. This allows composing the handling of
+//! sockets with standard tower middleware, such as timeouts or concurrency limits, and serving
+//! it with [`Router::route_service`](axum::Router::route_service).
+//!
+//! # Example
+//!
+//! ```
+//! use axum::Router;
+//! use axum_tungstenite::{service::WsService, WebSocket};
+//!
+//! async fn handle_socket(mut socket: WebSocket) {
+//!     while let Some(Ok(msg)) = socket.recv().await {
+//!         if socket.send(msg).await.is_err() {
+//!             break;
+//!         }
+//!     }
+//! }
+//!
+//! let app = Router::new().route_service("/ws", WsService::from_fn(handle_socket));
+//! # let _: Router = app;
+//! ```
+
+use crate::{WebSocket, WebSocketUpgrade};
+use axum_core::response::{IntoResponse, Response};
+use futures_util::future::{poll_fn, FutureExt, Map};
+use http::Request;
+use std::{
+    convert::Infallible,
+    future::{ready, Future, Ready},
+    task::{Context, Poll},
+};
+use tower_service::Service;
+
+/// A [`Service`] that upgrades HTTP requests and passes the sockets to an inner service.
+///
+/// Requests that aren't valid WebSocket upgrade requests get the response of the
+/// [`WebSocketUpgradeRejection`](crate::rejection::WebSocketUpgradeRejection). The inner service
+/// is cloned for each connection and called from the connection's task once the upgrade
+/// completes. Errors returned by the inner service are ignored, so handle them inside it if they
+/// matter.
+///
+/// See the [module docs](self) for an example.
+#[derive(Debug, Clone)]
+pub struct WsService<S> {
+    service: S,
+}
+
+impl<S> WsService<S> {
+    /// Create a new `WsService` wrapping `service`.
+    pub fn new(service: S) -> Self {
+        Self { service }
+    }
+}
+
+impl<F> WsService<ServiceFn<F>> {
+    /// Create a new `WsService` that calls `f` with each socket.
+    pub fn from_fn(f: F) -> Self {
+        Self::new(ServiceFn { f })
+    }
+}
+
+impl<S, B> Service<Request<B>> for WsService<S>
+where
+    S: Service<WebSocket, Response = ()> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Ready<Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let (mut parts, _body) = req.into_parts();
+
+        let response = match WebSocketUpgrade::from_request_head(&mut parts) {
+            Ok(ws) => {
+                let mut service = self.service.clone();
+                ws.on_upgrade(move |socket| async move {
+                    if poll_fn(|cx| service.poll_ready(cx)).await.is_ok() {
+                        let _ = service.call(socket).await;
+                    }
+                })
+            }
+            Err(rejection) => rejection.into_response(),
+        };
+
+        ready(Ok(response))
+    }
+}
+
+/// A [`Service`] that calls a function with each socket.
+///
+/// Created with [`WsService::from_fn`].
+#[derive(Debug, Clone, Copy)]
+pub struct ServiceFn<F> {
+    f: F,
+}
+
+impl<F, Fut> Service<WebSocket> for ServiceFn<F>
+where
+    F: FnMut(WebSocket) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    type Response = ();
+    type Error = Infallible;
+    type Future = Map<Fut, fn(()) -> Result<(), Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, socket: WebSocket) -> Self::Future {
+        (self.f)(socket).map(Ok)
+    }
+}
@@ -0,0 +1,735 @@
+//! Resumable sessions.
+//!
+//! A session is application state that outlives a single connection. When a connection is
+//! upgraded through [`Sessions::on_upgrade`] the client is attached to a session. If the
+//! connection drops the session is kept in a [`SessionStore`] for a while, and a client that
+//! reconnects with the session's ID before it expires is attached to the same session again.
+//!
+//! Clients send the ID of the session they want to resume in a query parameter, `session_id` by
+//! default. How clients learn the ID is up to the application, typically it's sent as the first
+//! message after connecting.
+//!
+//! A session is attached to one connection at a time. A client that resumes a session that's
+//! still attached to another connection waits until that connection gives it up, see
+//! [`Session::superseded`].
+//!
+//! Sessions can also buffer outgoing messages so they can be replayed to a client that missed
+//! them, see [`Sessions::replay_buffer`].
+//!
+//! # Example
+//!
+//! ```
+//! use axum::{extract::State, response::Response, routing::get, Router};
+//! use axum_tungstenite::{
+//!     session::{MemoryStore, Session, Sessions},
+//!     Message, WebSocket, WebSocketUpgrade,
+//! };
+//! use std::time::Duration;
+//!
+//! #[derive(Default)]
+//! struct Chat {
+//!     nickname: Option<String>,
+//! }
+//!
+//! let sessions = Sessions::<Chat, _>::new(MemoryStore::new()).ttl(Duration::from_secs(60));
+//!
+//! let app = Router::new()
+//!     .route("/ws", get(handler))
+//!     .with_state(sessions);
+//!
+//! async fn handler(
+//!     ws: WebSocketUpgrade,
+//!     State(sessions): State<Sessions<Chat, MemoryStore>>,
+//! ) -> Response {
+//!     sessions.on_upgrade(ws, handle_socket)
+//! }
+//!
+//! async fn handle_socket(mut socket: WebSocket, mut session: Session<Chat>) {
+//!     if !session.is_resumed() {
+//!         let id = session.id().to_string();
+//!         if socket.send(Message::Text(id)).await.is_err() {
+//!             return;
+//!         }
+//!     }
+//!
+//!     while let Some(Ok(msg)) = socket.recv().await {
+//!         if let Message::Text(nickname) = msg {
+//!             session.nickname = Some(nickname);
+//!         }
+//!     }
+//!
+//!     // `session` is stored once this returns so the client can resume it
+//! }
+//! # let _: Router = app;
+//! ```
+
+use crate::{CallbackResult, Error, Message, OnFailedUpdgrade, WebSocket, WebSocketUpgrade};
+use async_trait::async_trait;
+use axum_core::response::Response;
+use rand::Rng;
+use std::{
+    any::Any,
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{oneshot, watch, Notify},
+    time::Instant,
+};
+
+/// The ID of a [`Session`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionId(String);
+
+impl SessionId {
+    /// Generate a new random session ID.
+    pub fn random() -> Self {
+        let bytes: [u8; 16] = rand::thread_rng().gen();
+        Self(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// Get the ID as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for SessionId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+/// Storage for sessions that aren't attached to a connection.
+///
+/// [`Sessions`] stores [`SessionData`] so stores used with it must implement
+/// `SessionStore<SessionData<S>>`.
+#[async_trait]
+pub trait SessionStore<S>: Send + Sync + 'static {
+    /// Remove the session with the given ID from the store and return it.
+    ///
+    /// Returns `None` if there is no such session or it has expired.
+    async fn take(&self, id: &SessionId) -> Option<S>;
+
+    /// Store a session that should expire after `ttl`.
+    async fn put(&self, id: SessionId, session: S, ttl: Duration);
+}
+
+/// An in-memory [`SessionStore`].
+///
+/// Expired sessions are removed whenever the store is accessed.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<Mutex<HashMap<SessionId, (Box<dyn Any + Send>, Instant)>>>,
+}
+
+impl MemoryStore {
+    /// Create a new empty `MemoryStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for MemoryStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self.sessions.lock().unwrap().len();
+        f.debug_struct("MemoryStore").field("len", &len).finish()
+    }
+}
+
+#[async_trait]
+impl<S> SessionStore<S> for MemoryStore
+where
+    S: Send + 'static,
+{
+    async fn take(&self, id: &SessionId) -> Option<S> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        let (session, _) = sessions.remove(id)?;
+        session.downcast().ok().map(|session| *session)
+    }
+
+    async fn put(&self, id: SessionId, session: S, ttl: Duration) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        sessions.insert(id, (Box::new(session), now + ttl));
+    }
+}
+
+/// What [`Sessions`] puts in its [`SessionStore`].
+#[derive(Debug)]
+pub struct SessionData<S> {
+    /// The application state.
+    pub state: S,
+    /// Outgoing messages that may have to be replayed.
+    pub replay_buffer: ReplayBuffer,
+}
+
+/// Buffer of the most recent outgoing messages of a session.
+///
+/// See [`Sessions::replay_buffer`].
+#[derive(Debug, Clone)]
+pub struct ReplayBuffer {
+    capacity: usize,
+    next_seq: u64,
+    messages: VecDeque<(u64, Message)>,
+}
+
+impl ReplayBuffer {
+    /// Create an empty buffer that keeps at most `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next_seq: 1,
+            messages: VecDeque::new(),
+        }
+    }
+
+    /// The sequence number the next message will get.
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Add a message to the buffer and return its sequence number.
+    ///
+    /// If the buffer is full the oldest message is dropped.
+    pub fn push(&mut self, msg: Message) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if self.capacity > 0 {
+            if self.messages.len() == self.capacity {
+                self.messages.pop_front();
+            }
+            self.messages.push_back((seq, msg));
+        }
+        seq
+    }
+
+    /// Remove messages with sequence numbers up to and including `seq`.
+    pub fn ack(&mut self, seq: u64) {
+        while self
+            .messages
+            .front()
+            .is_some_and(|(buffered, _)| *buffered <= seq)
+        {
+            self.messages.pop_front();
+        }
+    }
+
+    /// The buffered messages with sequence numbers greater than `seq`, oldest first.
+    pub fn after(&self, seq: u64) -> impl Iterator<Item = (u64, &Message)> {
+        self.messages
+            .iter()
+            .filter(move |(buffered, _)| *buffered > seq)
+            .map(|(seq, msg)| (*seq, msg))
+    }
+}
+
+/// Attaches connections to resumable [`Session`]s.
+///
+/// See the [module docs](self) for an example.
+pub struct Sessions<S, T> {
+    store: Arc<T>,
+    ttl: Duration,
+    query_param: Arc<str>,
+    replay_capacity: usize,
+    live: Arc<LiveSessions>,
+    _marker: std::marker::PhantomData<fn() -> S>,
+}
+
+impl<S, T> Sessions<S, T>
+where
+    T: SessionStore<SessionData<S>>,
+{
+    /// Create a new `Sessions` backed by `store`.
+    ///
+    /// Sessions are kept for 30 seconds after their connection ends by default.
+    pub fn new(store: T) -> Self {
+        Self {
+            store: Arc::new(store),
+            ttl: Duration::from_secs(30),
+            query_param: "session_id".into(),
+            replay_capacity: 0,
+            live: Default::default(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Set how long sessions are kept after their connection ends.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the name of the query parameter clients use to send the ID of the session to resume.
+    ///
+    /// Defaults to `session_id`.
+    pub fn query_param(mut self, name: &str) -> Self {
+        self.query_param = name.into();
+        self
+    }
+
+    /// Buffer the last `capacity` messages sent with [`Session::send`].
+    ///
+    /// Messages are numbered with increasing sequence numbers. When a client resumes a session
+    /// it can tell the application the last sequence number it received, and the application
+    /// can send the missed messages again with [`Session::replay`]. How the sequence numbers are
+    /// communicated is up to the application.
+    ///
+    /// Defaults to `0`, meaning no messages are buffered.
+    pub fn replay_buffer(mut self, capacity: usize) -> Self {
+        self.replay_capacity = capacity;
+        self
+    }
+
+    /// Finalize upgrading the connection and call the provided callback with the stream and the
+    /// session.
+    ///
+    /// If the client asked to resume a session that is still stored it's attached to that
+    /// session, otherwise a new session is created with `S::default()`.
+    ///
+    /// The session is stored after the callback returns and the [`Session`] has been dropped.
+    /// A client that asks to resume a session while it's still attached to a connection, or
+    /// while it's being stored, waits until it has been stored and is then attached to it. The
+    /// connection holding the session is told through [`Session::superseded`], if it doesn't
+    /// give the session up the resuming client waits until that connection ends.
+    pub fn on_upgrade<C, F, Fut>(&self, ws: WebSocketUpgrade<C>, callback: F) -> Response
+    where
+        S: Default + Send + 'static,
+        C: OnFailedUpdgrade,
+        F: FnOnce(WebSocket, Session<S>) -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: CallbackResult,
+    {
+        let requested_id = ws
+            .request
+            .uri
+            .query()
+            .and_then(|query| query_value(query, &self.query_param))
+            .map(SessionId);
+        let store = self.store.clone();
+        let ttl = self.ttl;
+        let replay_capacity = self.replay_capacity;
+        let live = self.live.clone();
+
+        ws.on_upgrade(move |socket| async move {
+            let resumed = match requested_id {
+                Some(id) => {
+                    live.supersede(&id).await;
+                    store.take(&id).await.map(|data| (id, data))
+                }
+                None => None,
+            };
+
+            let (save_tx, save_rx) = oneshot::channel();
+            let session = match resumed {
+                Some((id, data)) => {
+                    let reservation = live.reserve(id.clone());
+                    Session::new(id, data, true, save_tx, reservation)
+                }
+                None => {
+                    let data = SessionData {
+                        state: S::default(),
+                        replay_buffer: ReplayBuffer::new(replay_capacity),
+                    };
+                    let id = SessionId::random();
+                    let reservation = live.reserve(id.clone());
+                    Session::new(id, data, false, save_tx, reservation)
+                }
+            };
+
+            let output = callback(socket, session).await;
+
+            // waits for the session to be dropped if the callback passed it on
+            if let Ok((id, data, _reservation)) = save_rx.await {
+                store.put(id, data, ttl).await;
+            }
+
+            output
+        })
+    }
+}
+
+impl<S, T> Clone for Sessions<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            ttl: self.ttl,
+            query_param: self.query_param.clone(),
+            replay_capacity: self.replay_capacity,
+            live: self.live.clone(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<S, T> fmt::Debug for Sessions<S, T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sessions")
+            .field("store", &self.store)
+            .field("ttl", &self.ttl)
+            .field("query_param", &self.query_param)
+            .field("replay_capacity", &self.replay_capacity)
+            .finish()
+    }
+}
+
+/// Sessions that are attached to a connection or being stored.
+///
+/// Clients resuming one of them wait until it's stored.
+#[derive(Debug, Default)]
+struct LiveSessions {
+    ids: Mutex<HashMap<SessionId, LiveSession>>,
+}
+
+#[derive(Debug, Clone)]
+struct LiveSession {
+    done: watch::Receiver<()>,
+    superseded: Arc<Notify>,
+}
+
+impl LiveSessions {
+    fn reserve(self: &Arc<Self>, id: SessionId) -> Reservation {
+        let (done_tx, done_rx) = watch::channel(());
+        let superseded = Arc::new(Notify::new());
+        let live = LiveSession {
+            done: done_rx,
+            superseded: superseded.clone(),
+        };
+        self.ids.lock().unwrap().insert(id.clone(), live);
+        Reservation {
+            live: self.clone(),
+            id,
+            superseded,
+            _done: done_tx,
+        }
+    }
+
+    /// Tell the connection holding the session to give it up and wait until it's stored.
+    async fn supersede(&self, id: &SessionId) {
+        let live = self.ids.lock().unwrap().get(id).cloned();
+        if let Some(LiveSession {
+            mut done,
+            superseded,
+        }) = live
+        {
+            superseded.notify_one();
+            // resolves once the reservation is dropped
+            let _ = done.changed().await;
+        }
+    }
+}
+
+/// Keeps clients from resuming a session until it has been stored.
+struct Reservation {
+    live: Arc<LiveSessions>,
+    id: SessionId,
+    superseded: Arc<Notify>,
+    _done: watch::Sender<()>,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.live.ids.lock().unwrap().remove(&self.id);
+    }
+}
+
+type SaveTx<S> = oneshot::Sender<(SessionId, SessionData<S>, Reservation)>;
+
+/// Application state attached to a connection that can be resumed after reconnecting.
+///
+/// Dereferences to the state. When dropped the session is put back into the store so a
+/// reconnecting client can resume it. Use [`Session::end`] to discard it instead.
+pub struct Session<S>
+where
+    S: Send + 'static,
+{
+    id: SessionId,
+    data: Option<SessionData<S>>,
+    resumed: bool,
+    save_tx: Option<SaveTx<S>>,
+    reservation: Option<Reservation>,
+}
+
+impl<S> Session<S>
+where
+    S: Send + 'static,
+{
+    fn new(
+        id: SessionId,
+        data: SessionData<S>,
+        resumed: bool,
+        save_tx: SaveTx<S>,
+        reservation: Reservation,
+    ) -> Self {
+        Self {
+            id,
+            data: Some(data),
+            resumed,
+            save_tx: Some(save_tx),
+            reservation: Some(reservation),
+        }
+    }
+
+    /// The ID of the session.
+    pub fn id(&self) -> &SessionId {
+        &self.id
+    }
+
+    /// Whether the session was resumed from a previous connection.
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Resolves when another client asks to resume this session.
+    ///
+    /// The resuming client waits until the session has been stored, so return from the
+    /// callback to hand the session over.
+    pub fn superseded(&self) -> impl Future<Output = ()> + Send + 'static {
+        let superseded = self
+            .reservation
+            .as_ref()
+            .expect("session reservation missing")
+            .superseded
+            .clone();
+        async move { superseded.notified().await }
+    }
+
+    /// Send a message and add it to the session's replay buffer.
+    ///
+    /// Returns the message's sequence number. The message is buffered even if sending fails.
+    pub async fn send(&mut self, socket: &mut WebSocket, msg: Message) -> Result<u64, Error> {
+        let seq = self.data_mut().replay_buffer.push(msg.clone());
+        socket.send(msg).await?;
+        Ok(seq)
+    }
+
+    /// Send the buffered messages with sequence numbers greater than `last_seq` again.
+    pub async fn replay(&mut self, socket: &mut WebSocket, last_seq: u64) -> Result<(), Error> {
+        let missed = self
+            .replay_buffer()
+            .after(last_seq)
+            .map(|(_, msg)| msg.clone())
+            .collect::<Vec<_>>();
+        for msg in missed {
+            socket.send(msg).await?;
+        }
+        Ok(())
+    }
+
+    /// The session's replay buffer.
+    pub fn replay_buffer(&self) -> &ReplayBuffer {
+        &self.data().replay_buffer
+    }
+
+    /// Mutable reference to the session's replay buffer.
+    ///
+    /// Use [`ReplayBuffer::ack`] to drop messages the client has confirmed receiving.
+    pub fn replay_buffer_mut(&mut self) -> &mut ReplayBuffer {
+        &mut self.data_mut().replay_buffer
+    }
+
+    /// End the session without storing it, so it cannot be resumed.
+    pub fn end(mut self) -> S {
+        self.data.take().expect("session data missing").state
+    }
+
+    fn data(&self) -> &SessionData<S> {
+        self.data.as_ref().expect("session data missing")
+    }
+
+    fn data_mut(&mut self) -> &mut SessionData<S> {
+        self.data.as_mut().expect("session data missing")
+    }
+}
+
+impl<S> Deref for Session<S>
+where
+    S: Send + 'static,
+{
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.data().state
+    }
+}
+
+impl<S> DerefMut for Session<S>
+where
+    S: Send + 'static,
+{
+    fn deref_mut(&mut self) -> &mut S {
+        &mut self.data_mut().state
+    }
+}
+
+impl<S> Drop for Session<S>
+where
+    S: Send + 'static,
+{
+    fn drop(&mut self) {
+        if let (Some(data), Some(save_tx), Some(reservation)) = (
+            self.data.take(),
+            self.save_tx.take(),
+            self.reservation.take(),
+        ) {
+            // the reservation is kept until the session is stored
+            let _ = save_tx.send((self.id.clone(), data, reservation));
+        }
+    }
+}
+
+impl<S> fmt::Debug for Session<S>
+where
+    S: fmt::Debug + Send + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("id", &self.id)
+            .field("data", &self.data)
+            .field("resumed", &self.resumed)
+            .finish()
+    }
+}
+
+fn query_value(query: &str, name: &str) -> Option<String> {
+    form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+    use axum::{extract::State, routing::get, Router};
+    use futures_util::StreamExt;
+
+    /// A [`MemoryStore`] that takes a while to store sessions.
+    #[derive(Clone, Default)]
+    struct SlowStore(MemoryStore);
+
+    #[async_trait]
+    impl<S> SessionStore<S> for SlowStore
+    where
+        S: Send + 'static,
+    {
+        async fn take(&self, id: &SessionId) -> Option<S> {
+            self.0.take(id).await
+        }
+
+        async fn put(&self, id: SessionId, session: S, ttl: Duration) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            self.0.put(id, session, ttl).await
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnecting_while_the_session_is_stored_resumes_it() {
+        async fn handler(
+            ws: WebSocketUpgrade,
+            State(sessions): State<Sessions<u32, SlowStore>>,
+        ) -> Response {
+            sessions.on_upgrade(ws, |mut socket, mut session| async move {
+                *session += 1;
+                let reply = format!("{} {}", session.id(), *session);
+                socket.send(Message::Text(reply)).await.unwrap();
+                while let Some(Ok(_)) = socket.recv().await {}
+            })
+        }
+
+        let app = Router::new()
+            .route("/", get(handler))
+            .with_state(Sessions::new(SlowStore::default()));
+
+        let mut client = test_helpers::connect(app.clone(), 1024).await;
+        let reply = client.next().await.unwrap().unwrap().into_text().unwrap();
+        let (id, count) = reply.split_once(' ').unwrap();
+        assert_eq!(count, "1");
+        // reconnect as soon as the close handshake is done
+        client.close(None).await.unwrap();
+        while !client.next().await.unwrap().unwrap().is_close() {}
+
+        let uri = format!("ws://localhost/?session_id={}", id);
+        let mut client = test_helpers::connect_to(app, &uri, 1024).await;
+        let reply = client.next().await.unwrap().unwrap().into_text().unwrap();
+        assert_eq!(reply, format!("{} 2", id));
+    }
+
+    #[tokio::test]
+    async fn resuming_a_session_that_is_still_attached_supersedes_it() {
+        async fn handler(
+            ws: WebSocketUpgrade,
+            State(sessions): State<Sessions<u32, MemoryStore>>,
+        ) -> Response {
+            sessions.on_upgrade(ws, |mut socket, mut session| async move {
+                *session += 1;
+                let reply = format!("{} {}", session.id(), *session);
+                socket.send(Message::Text(reply)).await.unwrap();
+                let superseded = session.superseded();
+                tokio::select! {
+                    _ = superseded => {}
+                    _ = async { while let Some(Ok(_)) = socket.recv().await {} } => {}
+                }
+            })
+        }
+
+        let app = Router::new()
+            .route("/", get(handler))
+            .with_state(Sessions::new(MemoryStore::new()));
+
+        let mut first = test_helpers::connect(app.clone(), 1024).await;
+        let reply = first.next().await.unwrap().unwrap().into_text().unwrap();
+        let (id, count) = reply.split_once(' ').unwrap();
+        assert_eq!(count, "1");
+
+        // the first client is still connected
+        let uri = format!("ws://localhost/?session_id={}", id);
+        let mut second = test_helpers::connect_to(app, &uri, 1024).await;
+        let reply = second.next().await.unwrap().unwrap().into_text().unwrap();
+        assert_eq!(reply, format!("{} 2", id));
+    }
+
+    #[test]
+    fn dropping_a_session_outside_a_runtime_hands_it_back() {
+        let (save_tx, mut save_rx) = oneshot::channel();
+        let data = SessionData {
+            state: 1,
+            replay_buffer: ReplayBuffer::new(0),
+        };
+        let id = SessionId::from("a".to_owned());
+        let live = Arc::new(LiveSessions::default());
+        let reservation = live.reserve(id.clone());
+        let session = Session::new(id, data, false, save_tx, reservation);
+
+        std::thread::spawn(move || drop(session)).join().unwrap();
+
+        let (id, data, _reservation) = save_rx.try_recv().unwrap();
+        assert_eq!(id.as_str(), "a");
+        assert_eq!(data.state, 1);
+    }
+
+    #[test]
+    fn session_ids_are_percent_decoded() {
+        assert_eq!(
+            query_value("other=1&session_id=a%2Bb+c", "session_id").as_deref(),
+            Some("a+b c"),
+        );
+        assert_eq!(query_value("other=1", "session_id"), None);
+    }
+}
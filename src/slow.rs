@@ -0,0 +1,198 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::time::Sleep;
+
+/// What to do when a client stops reading.
+///
+/// See [`WebSocketUpgrade::slow_consumer`](crate::WebSocketUpgrade::slow_consumer).
+#[derive(Clone)]
+pub struct SlowConsumerPolicy(Policy);
+
+#[derive(Clone)]
+enum Policy {
+    Warn(Arc<dyn Fn(Duration) + Send + Sync>),
+    DropMessages,
+    Terminate,
+}
+
+impl SlowConsumerPolicy {
+    /// Call `callback` with the threshold once per stall and keep waiting for the client.
+    pub fn warn<F>(callback: F) -> Self
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        Self(Policy::Warn(Arc::new(callback)))
+    }
+
+    /// Drop outgoing `Text` and `Binary` messages until the client reads again.
+    ///
+    /// Once the client has been stalled for longer than the threshold,
+    /// [`WebSocket::send`](crate::WebSocket::send) returns without waiting for it, so producers
+    /// aren't held up by a single slow client, and drops these messages instead of buffering
+    /// them. Control frames are still queued. Messages buffered before the stall are kept and
+    /// written once the client reads again. Flushing, including through
+    /// [`SinkExt`](futures_util::SinkExt), only completes once they have been written and
+    /// [`WebSocket::pending`](crate::WebSocket::pending) reports what is still buffered.
+    pub fn drop_messages() -> Self {
+        Self(Policy::DropMessages)
+    }
+
+    /// Fail with [`io::ErrorKind::TimedOut`](std::io::ErrorKind::TimedOut) wrapping
+    /// [`Timeout::SlowConsumer`](crate::Timeout::SlowConsumer) and end the connection.
+    pub fn terminate() -> Self {
+        Self(Policy::Terminate)
+    }
+}
+
+impl fmt::Debug for SlowConsumerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let policy = match self.0 {
+            Policy::Warn(_) => "Warn",
+            Policy::DropMessages => "DropMessages",
+            Policy::Terminate => "Terminate",
+        };
+        f.debug_tuple("SlowConsumerPolicy").field(&policy).finish()
+    }
+}
+
+/// Tracks how long writes to the client have been stuck.
+#[derive(Debug)]
+pub(crate) struct SlowConsumer {
+    threshold: Duration,
+    policy: SlowConsumerPolicy,
+    /// Armed once a write couldn't make progress.
+    deadline: Option<Pin<Box<Sleep>>>,
+    warned: bool,
+    /// Set while outgoing data messages are dropped.
+    pub(crate) dropping: bool,
+}
+
+pub(crate) enum Stalled {
+    /// Pretend the write completed.
+    Skip,
+    Terminate,
+}
+
+impl SlowConsumer {
+    pub(crate) fn new(threshold: Duration, policy: SlowConsumerPolicy) -> Self {
+        Self {
+            threshold,
+            policy,
+            deadline: None,
+            warned: false,
+            dropping: false,
+        }
+    }
+
+    /// The client accepted writes again.
+    pub(crate) fn progress(&mut self) {
+        self.deadline = None;
+        self.warned = false;
+        self.dropping = false;
+    }
+
+    /// A write couldn't make progress. Resolves once it has been stuck for longer than the
+    /// threshold and the policy says to stop waiting.
+    pub(crate) fn poll_stalled(&mut self, cx: &mut Context<'_>) -> Poll<Stalled> {
+        let threshold = self.threshold;
+        let deadline = self
+            .deadline
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(threshold)));
+        ready!(deadline.as_mut().poll(cx));
+
+        match &self.policy.0 {
+            Policy::Warn(callback) => {
+                if !self.warned {
+                    self.warned = true;
+                    callback(threshold);
+                }
+                Poll::Pending
+            }
+            Policy::DropMessages => {
+                self.dropping = true;
+                Poll::Ready(Stalled::Skip)
+            }
+            Policy::Terminate => Poll::Ready(Stalled::Terminate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_helpers, Message, SlowConsumerPolicy, WebSocket, WebSocketUpgrade};
+    use axum::{routing::get, Router};
+    use futures_util::StreamExt;
+    use std::time::Duration;
+    use tokio::{
+        sync::{mpsc, oneshot},
+        time::timeout,
+    };
+
+    #[tokio::test]
+    async fn drop_messages_keeps_flush_pending_while_stalled() {
+        let (stalled_tx, mut stalled_rx) = mpsc::channel(1);
+        let (done_tx, done_rx) = oneshot::channel();
+        let app = Router::new().route(
+            "/",
+            get(|ws: WebSocketUpgrade| async move {
+                ws.slow_consumer(
+                    Duration::from_millis(50),
+                    SlowConsumerPolicy::drop_messages(),
+                )
+                .on_upgrade(|socket| async move {
+                    stalled_tx.send(stall(socket).await).await.unwrap();
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 1024).await;
+
+        let mut socket = stalled_rx.recv().await.unwrap();
+        let sent = socket.stats().messages_sent();
+        tokio::spawn(async move {
+            socket.flush().await.unwrap();
+            done_tx.send(socket).unwrap();
+        });
+
+        let mut received = 0;
+        while received < sent {
+            let msg = client.next().await.unwrap().unwrap();
+            assert!(msg.is_binary());
+            received += 1;
+        }
+        let socket = timeout(Duration::from_secs(1), done_rx)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(socket.pending().bytes(), 0);
+    }
+
+    /// Send until messages get dropped, then check the buffered ones are still reported.
+    async fn stall(mut socket: WebSocket) -> WebSocket {
+        let msg = Message::Binary(vec![0; 4096]);
+        loop {
+            let sent = socket.stats().messages_sent();
+            timeout(Duration::from_secs(1), socket.send(msg.clone()))
+                .await
+                .expect("send waits for a stalled client")
+                .unwrap();
+            if socket.stats().messages_sent() == sent {
+                break;
+            }
+        }
+
+        assert!(socket.pending().bytes() > 0);
+        assert!(
+            timeout(Duration::from_millis(100), socket.flush())
+                .await
+                .is_err(),
+            "flush completed while data was still buffered",
+        );
+        socket
+    }
+}
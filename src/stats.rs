@@ -0,0 +1,306 @@
+use crate::{CloseCode, Message};
+use std::fmt;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::OpCode;
+
+/// Counters of the traffic on a [`WebSocket`](crate::WebSocket).
+///
+/// Returned by [`WebSocket::stats`](crate::WebSocket::stats).
+///
+/// tungstenite reassembles fragmented messages before they reach the socket, so each data
+/// message counts once regardless of how many frames it was split into. Byte counts are payload
+/// sizes and don't include framing overhead. `Pong`s sent automatically in reply to `Ping`s
+/// aren't counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketStats {
+    messages_received: u64,
+    messages_sent: u64,
+    bytes_received: u64,
+    bytes_sent: u64,
+    control_frames_received: u64,
+    control_frames_sent: u64,
+}
+
+impl SocketStats {
+    /// Number of `Text` and `Binary` messages received.
+    pub fn messages_received(&self) -> u64 {
+        self.messages_received
+    }
+
+    /// Number of `Text` and `Binary` messages sent.
+    pub fn messages_sent(&self) -> u64 {
+        self.messages_sent
+    }
+
+    /// Number of payload bytes received, including control frames.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Number of payload bytes sent, including control frames.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Number of `Ping`, `Pong`, and `Close` frames received.
+    pub fn control_frames_received(&self) -> u64 {
+        self.control_frames_received
+    }
+
+    /// Number of `Ping`, `Pong`, and `Close` frames sent.
+    pub fn control_frames_sent(&self) -> u64 {
+        self.control_frames_sent
+    }
+
+    pub(crate) fn record_received(&mut self, msg: &Message) {
+        self.bytes_received += msg.len() as u64;
+        if is_control(msg) {
+            self.control_frames_received += 1;
+        } else {
+            self.messages_received += 1;
+        }
+    }
+
+    pub(crate) fn record_sent(&mut self, msg: &Message) {
+        self.bytes_sent += msg.len() as u64;
+        if is_control(msg) {
+            self.control_frames_sent += 1;
+        } else {
+            self.messages_sent += 1;
+        }
+    }
+}
+
+/// Messages written to a [`WebSocket`](crate::WebSocket) that haven't been flushed to the
+/// client yet.
+///
+/// Returned by [`WebSocket::pending`](crate::WebSocket::pending).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PendingWrites {
+    messages: usize,
+    bytes: usize,
+}
+
+impl PendingWrites {
+    /// Number of messages, including control frames, waiting to be flushed.
+    pub fn messages(&self) -> usize {
+        self.messages
+    }
+
+    /// Number of payload bytes waiting to be flushed.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns `true` if everything written has been flushed.
+    pub fn is_empty(&self) -> bool {
+        self.messages == 0
+    }
+
+    pub(crate) fn record_sent(&mut self, msg: &Message) {
+        self.messages += 1;
+        self.bytes += msg.len();
+    }
+}
+
+fn is_control(msg: &Message) -> bool {
+    match msg {
+        Message::Ping(_) | Message::Pong(_) | Message::Close(_) => true,
+        Message::Text(_) | Message::Binary(_) => false,
+        Message::Frame(frame) => matches!(frame.header().opcode, OpCode::Control(_)),
+    }
+}
+
+/// Limits on the traffic a client may send.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Quotas {
+    pub(crate) max_messages: Option<u64>,
+    pub(crate) max_bytes: Option<u64>,
+    pub(crate) max_text_size: Option<usize>,
+    pub(crate) max_binary_size: Option<usize>,
+}
+
+impl Quotas {
+    /// Check if receiving `msg` would exceed a quota.
+    pub(crate) fn check(&self, stats: &SocketStats, msg: &Message) -> Option<QuotaExceeded> {
+        let max_size = match msg {
+            Message::Text(_) => self.max_text_size,
+            Message::Binary(_) => self.max_binary_size,
+            _ => None,
+        };
+        if max_size.is_some_and(|max| msg.len() > max) {
+            return Some(QuotaExceeded::MessageSize);
+        }
+        if let Some(max) = self.max_messages {
+            if !is_control(msg) && stats.messages_received >= max {
+                return Some(QuotaExceeded::Messages);
+            }
+        }
+        if let Some(max) = self.max_bytes {
+            if stats.bytes_received.saturating_add(msg.len() as u64) > max {
+                return Some(QuotaExceeded::Bytes);
+            }
+        }
+        None
+    }
+}
+
+/// Error returned when a client exceeds a quota set with
+/// [`WebSocketUpgrade::max_messages`](crate::WebSocketUpgrade::max_messages),
+/// [`WebSocketUpgrade::max_total_bytes`](crate::WebSocketUpgrade::max_total_bytes),
+/// [`WebSocketUpgrade::max_text_message_size`](crate::WebSocketUpgrade::max_text_message_size),
+/// or
+/// [`WebSocketUpgrade::max_binary_message_size`](crate::WebSocketUpgrade::max_binary_message_size).
+///
+/// It is returned from [`WebSocket::recv`](crate::WebSocket::recv) wrapped in an
+/// [`Error::Io`](crate::Error::Io) and can be recovered by downcasting the result of
+/// [`std::io::Error::get_ref`].
+///
+/// The `Close` frame with the code documented on each variant is sent on a best-effort basis:
+/// only if the socket can accept it without waiting. Clients that aren't reading may see the
+/// connection end without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum QuotaExceeded {
+    /// The client sent too many messages. The connection is closed with `1008 Policy`.
+    Messages,
+    /// The client sent too many bytes. The connection is closed with `1009 Size`.
+    Bytes,
+    /// The client sent a `Text` or `Binary` message that is too large. The connection is closed
+    /// with `1009 Size`.
+    MessageSize,
+}
+
+impl QuotaExceeded {
+    /// The close code sent to the client when closing the connection.
+    pub fn close_code(&self) -> CloseCode {
+        match self {
+            Self::Messages => CloseCode::Policy,
+            Self::Bytes | Self::MessageSize => CloseCode::Size,
+        }
+    }
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Messages => write!(f, "Message quota exceeded"),
+            Self::Bytes => write!(f, "Byte quota exceeded"),
+            Self::MessageSize => write!(f, "Message size limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_helpers, Error, WebSocketUpgrade};
+    use axum::{routing::get, Router};
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::sync::mpsc;
+
+    /// Connect with `quotas`, send `messages`, and return what the server received and the
+    /// client's close frame.
+    async fn exceed(
+        quotas: fn(WebSocketUpgrade) -> WebSocketUpgrade,
+        messages: Vec<Message>,
+    ) -> (Vec<Result<Message, QuotaExceeded>>, Option<CloseCode>) {
+        let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/",
+            get(move |ws: WebSocketUpgrade| async move {
+                quotas(ws).on_upgrade(move |mut socket| async move {
+                    while let Some(result) = socket.recv().await {
+                        let result = result.map_err(|err| match err {
+                            Error::Io(err) => *err.get_ref().unwrap().downcast_ref().unwrap(),
+                            err => panic!("unexpected error: {}", err),
+                        });
+                        received_tx.send(result).unwrap();
+                    }
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 1024).await;
+
+        for msg in messages {
+            client.send(msg).await.unwrap();
+        }
+        let close_code = loop {
+            match client.next().await {
+                Some(Ok(Message::Close(frame))) => break frame.map(|frame| frame.code),
+                Some(Ok(Message::Pong(_))) => {}
+                other => panic!("expected a close frame, got {:?}", other),
+            }
+        };
+
+        let mut received = Vec::new();
+        while let Some(result) = received_rx.recv().await {
+            received.push(result);
+        }
+        (received, close_code)
+    }
+
+    #[tokio::test]
+    async fn message_quota() {
+        let (received, close_code) = exceed(
+            |ws| ws.max_messages(2),
+            vec![
+                Message::Text("a".to_owned()),
+                Message::Ping(Vec::new()),
+                Message::Text("b".to_owned()),
+                Message::Text("c".to_owned()),
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            received,
+            [
+                Ok(Message::Text("a".to_owned())),
+                Ok(Message::Ping(Vec::new())),
+                Ok(Message::Text("b".to_owned())),
+                Err(QuotaExceeded::Messages),
+            ]
+        );
+        assert_eq!(close_code, Some(CloseCode::Policy));
+    }
+
+    #[tokio::test]
+    async fn byte_quota() {
+        let (received, close_code) = exceed(
+            |ws| ws.max_total_bytes(4),
+            vec![Message::Binary(vec![0; 3]), Message::Binary(vec![0; 2])],
+        )
+        .await;
+
+        assert_eq!(
+            received,
+            [Ok(Message::Binary(vec![0; 3])), Err(QuotaExceeded::Bytes)]
+        );
+        assert_eq!(close_code, Some(CloseCode::Size));
+    }
+
+    #[tokio::test]
+    async fn message_size_limits() {
+        let (received, close_code) = exceed(
+            |ws| ws.max_text_message_size(2).max_binary_message_size(4),
+            vec![
+                Message::Binary(vec![0; 4]),
+                Message::Text("ab".to_owned()),
+                Message::Text("abc".to_owned()),
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            received,
+            [
+                Ok(Message::Binary(vec![0; 4])),
+                Ok(Message::Text("ab".to_owned())),
+                Err(QuotaExceeded::MessageSize),
+            ]
+        );
+        assert_eq!(close_code, Some(CloseCode::Size));
+    }
+}
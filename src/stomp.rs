@@ -0,0 +1,973 @@
+//! [STOMP 1.2] over a WebSocket.
+//!
+//! This module contains a [`Frame`] codec and a server side [`StompSession`] that performs the
+//! `CONNECT` handshake, tracks subscriptions, answers receipts, and surfaces client frames as
+//! [`Event`]s. Routing messages between subscriptions is left to the application.
+//!
+//! Clients select STOMP through the `Sec-WebSocket-Protocol` header, so the protocol should be
+//! accepted with [`WebSocketUpgrade::protocols`] using [`PROTOCOL`].
+//!
+//! # Example
+//!
+//! ```
+//! use axum::response::Response;
+//! use axum_tungstenite::{
+//!     stomp::{Event, StompSession, PROTOCOL},
+//!     WebSocketUpgrade,
+//! };
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     ws.protocols([PROTOCOL]).on_upgrade(|socket| async move {
+//!         let mut session = match StompSession::accept(socket).await {
+//!             Ok(session) => session,
+//!             Err(_) => return,
+//!         };
+//!
+//!         // echo everything sent to a destination back to its subscribers
+//!         while let Some(Ok(event)) = session.next_event().await {
+//!             if let Event::Send { destination, frame } = event {
+//!                 let subscriptions = session
+//!                     .subscriptions()
+//!                     .filter(|sub| sub.destination() == destination)
+//!                     .map(|sub| sub.id().to_owned())
+//!                     .collect::<Vec<_>>();
+//!
+//!                 for id in subscriptions {
+//!                     let _ = session.message(&id, frame.body().to_vec()).await;
+//!                 }
+//!             }
+//!         }
+//!     })
+//! }
+//! ```
+//!
+//! [STOMP 1.2]: https://stomp.github.io/stomp-specification-1.2.html
+//! [`WebSocketUpgrade::protocols`]: crate::WebSocketUpgrade::protocols
+
+use crate::{Message, WebSocket};
+use bytes::Bytes;
+use std::{collections::HashMap, fmt};
+
+/// The `Sec-WebSocket-Protocol` value for STOMP 1.2.
+pub const PROTOCOL: &str = "v12.stomp";
+
+/// A STOMP command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Command {
+    /// `CONNECT`
+    Connect,
+    /// `STOMP`
+    Stomp,
+    /// `CONNECTED`
+    Connected,
+    /// `SEND`
+    Send,
+    /// `SUBSCRIBE`
+    Subscribe,
+    /// `UNSUBSCRIBE`
+    Unsubscribe,
+    /// `ACK`
+    Ack,
+    /// `NACK`
+    Nack,
+    /// `BEGIN`
+    Begin,
+    /// `COMMIT`
+    Commit,
+    /// `ABORT`
+    Abort,
+    /// `DISCONNECT`
+    Disconnect,
+    /// `MESSAGE`
+    Message,
+    /// `RECEIPT`
+    Receipt,
+    /// `ERROR`
+    Error,
+}
+
+impl Command {
+    /// The command as it appears on the wire.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connect => "CONNECT",
+            Self::Stomp => "STOMP",
+            Self::Connected => "CONNECTED",
+            Self::Send => "SEND",
+            Self::Subscribe => "SUBSCRIBE",
+            Self::Unsubscribe => "UNSUBSCRIBE",
+            Self::Ack => "ACK",
+            Self::Nack => "NACK",
+            Self::Begin => "BEGIN",
+            Self::Commit => "COMMIT",
+            Self::Abort => "ABORT",
+            Self::Disconnect => "DISCONNECT",
+            Self::Message => "MESSAGE",
+            Self::Receipt => "RECEIPT",
+            Self::Error => "ERROR",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "CONNECT" => Self::Connect,
+            "STOMP" => Self::Stomp,
+            "CONNECTED" => Self::Connected,
+            "SEND" => Self::Send,
+            "SUBSCRIBE" => Self::Subscribe,
+            "UNSUBSCRIBE" => Self::Unsubscribe,
+            "ACK" => Self::Ack,
+            "NACK" => Self::Nack,
+            "BEGIN" => Self::Begin,
+            "COMMIT" => Self::Commit,
+            "ABORT" => Self::Abort,
+            "DISCONNECT" => Self::Disconnect,
+            "MESSAGE" => Self::Message,
+            "RECEIPT" => Self::Receipt,
+            "ERROR" => Self::Error,
+            _ => return None,
+        })
+    }
+
+    /// `CONNECT` and `CONNECTED` frames don't escape header values.
+    fn escapes_headers(&self) -> bool {
+        !matches!(self, Self::Connect | Self::Stomp | Self::Connected)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A STOMP frame.
+///
+/// ```
+/// use axum_tungstenite::stomp::{Command, Frame};
+///
+/// let frame = Frame::new(Command::Send)
+///     .with_header("destination", "/queue/a")
+///     .with_body("hello");
+///
+/// let parsed = Frame::parse(&frame.encode()).unwrap();
+/// assert_eq!(parsed.command(), Command::Send);
+/// assert_eq!(parsed.header("destination"), Some("/queue/a"));
+/// assert_eq!(parsed.body(), "hello".as_bytes());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    command: Command,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+}
+
+impl Frame {
+    /// Create a new frame without headers or body.
+    pub fn new(command: Command) -> Self {
+        Self {
+            command,
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// Add a header.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set the body.
+    pub fn with_body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Get the command.
+    pub fn command(&self) -> Command {
+        self.command
+    }
+
+    /// Get the value of a header.
+    ///
+    /// If the header is repeated the first value is returned, as required by the specification.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get all headers in the order they appear in the frame.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Get the body.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// Parse a frame.
+    ///
+    /// Trailing end-of-line characters after the terminating NUL are ignored. The body is read
+    /// up to `content-length` bytes if the header is present, otherwise up to the first NUL.
+    pub fn parse(input: &[u8]) -> Result<Self, ParseError> {
+        let mut rest = input;
+
+        let command = next_line(&mut rest).ok_or(ParseError::Incomplete)?;
+        let command = std::str::from_utf8(command)
+            .ok()
+            .and_then(Command::parse)
+            .ok_or(ParseError::UnknownCommand)?;
+
+        let mut headers = Vec::new();
+        loop {
+            let line = next_line(&mut rest).ok_or(ParseError::Incomplete)?;
+            if line.is_empty() {
+                break;
+            }
+            let line = std::str::from_utf8(line).map_err(|_| ParseError::InvalidHeader)?;
+            let (name, value) = line.split_once(':').ok_or(ParseError::InvalidHeader)?;
+            if command.escapes_headers() {
+                headers.push((unescape(name)?, unescape(value)?));
+            } else {
+                headers.push((name.to_owned(), value.to_owned()));
+            }
+        }
+
+        let content_length = headers
+            .iter()
+            .find(|(name, _)| name == "content-length")
+            .map(|(_, value)| value.parse::<usize>())
+            .transpose()
+            .map_err(|_| ParseError::InvalidHeader)?;
+
+        let body_len = match content_length {
+            Some(len) => len,
+            None => rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(ParseError::Incomplete)?,
+        };
+        if rest.len() <= body_len || rest[body_len] != 0 {
+            return Err(ParseError::Incomplete);
+        }
+        let body = Bytes::copy_from_slice(&rest[..body_len]);
+
+        if !rest[body_len + 1..]
+            .iter()
+            .all(|&b| b == b'\n' || b == b'\r')
+        {
+            return Err(ParseError::TrailingData);
+        }
+
+        Ok(Self {
+            command,
+            headers,
+            body,
+        })
+    }
+
+    /// Encode the frame.
+    ///
+    /// A `content-length` header is added if the frame has a body and doesn't already have one.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64 + self.body.len());
+        out.extend_from_slice(self.command.as_str().as_bytes());
+        out.push(b'\n');
+
+        for (name, value) in &self.headers {
+            if self.command.escapes_headers() {
+                escape_into(name, &mut out);
+                out.push(b':');
+                escape_into(value, &mut out);
+            } else {
+                out.extend_from_slice(name.as_bytes());
+                out.push(b':');
+                out.extend_from_slice(value.as_bytes());
+            }
+            out.push(b'\n');
+        }
+
+        if !self.body.is_empty() && self.header("content-length").is_none() {
+            out.extend_from_slice(format!("content-length:{}\n", self.body.len()).as_bytes());
+        }
+
+        out.push(b'\n');
+        out.extend_from_slice(&self.body);
+        out.push(0);
+        out
+    }
+}
+
+impl From<Frame> for Message {
+    fn from(frame: Frame) -> Self {
+        Message::Binary(frame.encode())
+    }
+}
+
+fn next_line<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let end = rest.iter().position(|&b| b == b'\n')?;
+    let mut line = &rest[..end];
+    if let [head @ .., b'\r'] = line {
+        line = head;
+    }
+    *rest = &rest[end + 1..];
+    Some(line)
+}
+
+fn unescape(s: &str) -> Result<String, ParseError> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some('c') => out.push(':'),
+            Some('\\') => out.push('\\'),
+            _ => return Err(ParseError::InvalidHeader),
+        }
+    }
+    Ok(out)
+}
+
+fn escape_into(s: &str, out: &mut Vec<u8>) {
+    for b in s.bytes() {
+        match b {
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b':' => out.extend_from_slice(b"\\c"),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            _ => out.push(b),
+        }
+    }
+}
+
+/// Error returned by [`Frame::parse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The frame ended before it was complete.
+    Incomplete,
+    /// The command isn't a STOMP 1.2 command.
+    UnknownCommand,
+    /// A header was malformed or used an invalid escape sequence.
+    InvalidHeader,
+    /// There was data after the frame's terminating NUL.
+    TrailingData,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Incomplete => write!(f, "Incomplete STOMP frame"),
+            Self::UnknownCommand => write!(f, "Unknown STOMP command"),
+            Self::InvalidHeader => write!(f, "Invalid STOMP header"),
+            Self::TrailingData => write!(f, "Unexpected data after STOMP frame"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// How messages sent to a subscription must be acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckMode {
+    /// Messages are considered acknowledged once sent.
+    Auto,
+    /// `ACK` acknowledges the message and all previous messages of the subscription.
+    Client,
+    /// `ACK` acknowledges only the given message.
+    ClientIndividual,
+}
+
+/// A subscription made by the client.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    id: String,
+    destination: String,
+    ack: AckMode,
+}
+
+impl Subscription {
+    /// The subscription ID chosen by the client.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The destination subscribed to.
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    /// The acknowledgement mode.
+    pub fn ack(&self) -> AckMode {
+        self.ack
+    }
+}
+
+/// A frame from the client, as surfaced by [`StompSession::next_event`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Event {
+    /// The client sent a message to a destination.
+    Send {
+        /// The `destination` header.
+        destination: String,
+        /// The full frame.
+        frame: Frame,
+    },
+    /// The client subscribed to a destination.
+    Subscribe(Subscription),
+    /// The client removed a subscription.
+    Unsubscribe(Subscription),
+    /// The client acknowledged a message.
+    Ack {
+        /// The `id` header, matching the `ack` header of the message.
+        id: String,
+        /// The full frame.
+        frame: Frame,
+    },
+    /// The client rejected a message.
+    Nack {
+        /// The `id` header, matching the `ack` header of the message.
+        id: String,
+        /// The full frame.
+        frame: Frame,
+    },
+    /// Any other client frame, such as `BEGIN`, `COMMIT`, or `ABORT`.
+    Other(Frame),
+}
+
+/// A server side STOMP session.
+///
+/// See the [module docs](self) for more details.
+#[derive(Debug)]
+pub struct StompSession {
+    socket: WebSocket,
+    connect: Frame,
+    subscriptions: HashMap<String, Subscription>,
+    next_message_id: u64,
+    disconnected: bool,
+}
+
+impl StompSession {
+    /// Wait for the client's `CONNECT` frame and reply with `CONNECTED`.
+    ///
+    /// Fails, after sending an `ERROR` frame, if the first frame isn't `CONNECT` or `STOMP` or the
+    /// client doesn't support version 1.2. Heart-beating is not supported and is declined in the
+    /// `CONNECTED` frame.
+    pub async fn accept(mut socket: WebSocket) -> Result<Self, SessionError> {
+        let connect = match recv_frame(&mut socket).await {
+            Some(Ok(frame)) => frame,
+            Some(Err(SessionError::Protocol(message))) => {
+                return Err(send_error(&mut socket, message).await)
+            }
+            Some(Err(err)) => return Err(err),
+            None => return Err(SessionError::Disconnected),
+        };
+
+        if !matches!(connect.command(), Command::Connect | Command::Stomp) {
+            return Err(send_error(&mut socket, "Expected CONNECT frame".to_owned()).await);
+        }
+
+        let supports_1_2 = connect
+            .header("accept-version")
+            .is_some_and(|versions| versions.split(',').any(|version| version.trim() == "1.2"));
+        if !supports_1_2 {
+            return Err(send_error(&mut socket, "Only STOMP 1.2 is supported".to_owned()).await);
+        }
+
+        let connected = Frame::new(Command::Connected)
+            .with_header("version", "1.2")
+            .with_header("heart-beat", "0,0");
+        socket
+            .send(connected.into())
+            .await
+            .map_err(SessionError::WebSocket)?;
+
+        Ok(Self {
+            socket,
+            connect,
+            subscriptions: HashMap::new(),
+            next_message_id: 0,
+            disconnected: false,
+        })
+    }
+
+    /// The `CONNECT` frame sent by the client.
+    ///
+    /// Useful for checking the `login`, `passcode`, and `host` headers.
+    pub fn connect_frame(&self) -> &Frame {
+        &self.connect
+    }
+
+    /// The client's active subscriptions.
+    pub fn subscriptions(&self) -> impl Iterator<Item = &Subscription> {
+        self.subscriptions.values()
+    }
+
+    /// Get a subscription by ID.
+    pub fn subscription(&self, id: &str) -> Option<&Subscription> {
+        self.subscriptions.get(id)
+    }
+
+    /// Receive the next event from the client.
+    ///
+    /// `RECEIPT` frames are sent automatically for frames with a `receipt` header. Returns
+    /// `None` once the client has disconnected, either by sending `DISCONNECT` or by closing the
+    /// socket. On protocol errors an `ERROR` frame is sent, the socket is closed, and the error
+    /// is returned.
+    pub async fn next_event(&mut self) -> Option<Result<Event, SessionError>> {
+        if self.disconnected {
+            return None;
+        }
+
+        let frame = match recv_frame(&mut self.socket).await {
+            Some(Ok(frame)) => frame,
+            Some(Err(SessionError::Protocol(message))) => {
+                return Some(Err(self.fail(message).await))
+            }
+            Some(Err(err)) => return Some(Err(err)),
+            None => {
+                self.disconnected = true;
+                return None;
+            }
+        };
+
+        let event = match self.handle(&frame) {
+            Ok(event) => event,
+            Err(message) => return Some(Err(self.fail(message).await)),
+        };
+
+        if let Some(receipt) = frame.header("receipt") {
+            let receipt = Frame::new(Command::Receipt).with_header("receipt-id", receipt);
+            if let Err(err) = self.socket.send(receipt.into()).await {
+                return Some(Err(SessionError::WebSocket(err)));
+            }
+        }
+
+        if frame.command() == Command::Disconnect {
+            self.disconnected = true;
+            let _ = self.socket.send(Message::Close(None)).await;
+            return None;
+        }
+
+        Some(Ok(event))
+    }
+
+    fn handle(&mut self, frame: &Frame) -> Result<Event, String> {
+        let required = |name: &str| {
+            frame
+                .header(name)
+                .map(ToOwned::to_owned)
+                .ok_or_else(|| format!("Missing `{}` header", name))
+        };
+
+        Ok(match frame.command() {
+            Command::Send => Event::Send {
+                destination: required("destination")?,
+                frame: frame.clone(),
+            },
+            Command::Subscribe => {
+                let ack = match frame.header("ack").unwrap_or("auto") {
+                    "auto" => AckMode::Auto,
+                    "client" => AckMode::Client,
+                    "client-individual" => AckMode::ClientIndividual,
+                    other => return Err(format!("Invalid ack mode `{}`", other)),
+                };
+                let subscription = Subscription {
+                    id: required("id")?,
+                    destination: required("destination")?,
+                    ack,
+                };
+                if self.subscriptions.contains_key(&subscription.id) {
+                    return Err(format!("Duplicate subscription `{}`", subscription.id));
+                }
+                self.subscriptions
+                    .insert(subscription.id.clone(), subscription.clone());
+                Event::Subscribe(subscription)
+            }
+            Command::Unsubscribe => {
+                let id = required("id")?;
+                let subscription = self
+                    .subscriptions
+                    .remove(&id)
+                    .ok_or_else(|| format!("Unknown subscription `{}`", id))?;
+                Event::Unsubscribe(subscription)
+            }
+            Command::Ack => Event::Ack {
+                id: required("id")?,
+                frame: frame.clone(),
+            },
+            Command::Nack => Event::Nack {
+                id: required("id")?,
+                frame: frame.clone(),
+            },
+            Command::Connect | Command::Stomp => return Err("Already connected".to_owned()),
+            Command::Connected | Command::Message | Command::Receipt | Command::Error => {
+                return Err(format!("Unexpected {} frame", frame.command()))
+            }
+            _ => Event::Other(frame.clone()),
+        })
+    }
+
+    /// Send a `MESSAGE` frame to a subscription.
+    ///
+    /// The `subscription`, `destination`, and `message-id` headers are set automatically, as is
+    /// the `ack` header for subscriptions that require acknowledgement. Returns the message ID.
+    pub async fn message(
+        &mut self,
+        subscription: &str,
+        body: impl Into<Bytes>,
+    ) -> Result<String, SessionError> {
+        self.message_with_headers(subscription, body, std::iter::empty::<(String, String)>())
+            .await
+    }
+
+    /// Send a `MESSAGE` frame to a subscription with additional headers.
+    ///
+    /// See [`message`](Self::message) for details.
+    pub async fn message_with_headers<I, K, V>(
+        &mut self,
+        subscription: &str,
+        body: impl Into<Bytes>,
+        headers: I,
+    ) -> Result<String, SessionError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let subscription = self.subscriptions.get(subscription).ok_or_else(|| {
+            SessionError::Protocol(format!("Unknown subscription `{}`", subscription))
+        })?;
+
+        let message_id = self.next_message_id.to_string();
+        self.next_message_id += 1;
+
+        let mut frame = Frame::new(Command::Message)
+            .with_header("subscription", subscription.id.clone())
+            .with_header("message-id", message_id.clone())
+            .with_header("destination", subscription.destination.clone());
+        if subscription.ack != AckMode::Auto {
+            frame = frame.with_header("ack", message_id.clone());
+        }
+        for (name, value) in headers {
+            frame = frame.with_header(name, value);
+        }
+
+        self.socket
+            .send(frame.with_body(body).into())
+            .await
+            .map_err(SessionError::WebSocket)?;
+
+        Ok(message_id)
+    }
+
+    /// Send an `ERROR` frame and close the socket.
+    pub async fn error(mut self, message: impl Into<String>) -> Result<(), SessionError> {
+        let frame = Frame::new(Command::Error).with_header("message", message);
+        self.socket
+            .send(frame.into())
+            .await
+            .map_err(SessionError::WebSocket)?;
+        self.socket.close().await.map_err(SessionError::WebSocket)
+    }
+
+    /// Get back the underlying socket.
+    pub fn into_inner(self) -> WebSocket {
+        self.socket
+    }
+
+    async fn fail(&mut self, message: String) -> SessionError {
+        self.disconnected = true;
+        send_error(&mut self.socket, message).await
+    }
+}
+
+async fn recv_frame(socket: &mut WebSocket) -> Option<Result<Frame, SessionError>> {
+    loop {
+        let data = match socket.recv().await? {
+            Ok(Message::Text(text)) => text.into_bytes(),
+            Ok(Message::Binary(data)) => data,
+            // keep receiving after a `Close` so the reply is sent
+            Ok(_) => continue,
+            Err(err) => return Some(Err(SessionError::WebSocket(err))),
+        };
+
+        // heart-beats are sent as bare end-of-line characters
+        if data.iter().all(|&b| b == b'\n' || b == b'\r') {
+            continue;
+        }
+
+        return Some(Frame::parse(&data).map_err(|err| SessionError::Protocol(err.to_string())));
+    }
+}
+
+async fn send_error(socket: &mut WebSocket, message: String) -> SessionError {
+    let frame = Frame::new(Command::Error).with_header("message", message.clone());
+    let _ = socket.send(frame.into()).await;
+    let _ = socket.send(Message::Close(None)).await;
+    SessionError::Protocol(message)
+}
+
+/// Error returned by [`StompSession`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SessionError {
+    /// The WebSocket failed.
+    WebSocket(crate::Error),
+    /// The client violated the protocol.
+    Protocol(String),
+    /// The client disconnected before the session was established.
+    Disconnected,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WebSocket(err) => write!(f, "WebSocket error: {}", err),
+            Self::Protocol(message) => write!(f, "STOMP protocol error: {}", message),
+            Self::Disconnected => write!(f, "Client disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::WebSocket(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::{io::DuplexStream, sync::mpsc};
+    use tokio_tungstenite::WebSocketStream;
+
+    type Client = WebSocketStream<DuplexStream>;
+
+    async fn send(client: &mut Client, frame: Frame) {
+        client.send(frame.into()).await.unwrap();
+    }
+
+    async fn recv(client: &mut Client) -> Frame {
+        match client.next().await.unwrap().unwrap() {
+            Message::Binary(data) => Frame::parse(&data).unwrap(),
+            msg => panic!("expected a frame, got {msg:?}"),
+        }
+    }
+
+    fn connect() -> Frame {
+        Frame::new(Command::Connect)
+            .with_header("accept-version", "1.0,1.2")
+            .with_header("host", "localhost")
+    }
+
+    #[test]
+    fn header_values_are_escaped_except_in_connect_frames() {
+        let frame = Frame::new(Command::Send).with_header("a:b", "c\\d\r\ne");
+        let encoded = frame.encode();
+        assert_eq!(encoded, b"SEND\na\\cb:c\\\\d\\r\\ne\n\n\0");
+        assert_eq!(Frame::parse(&encoded).unwrap(), frame);
+
+        let frame = Frame::new(Command::Connect).with_header("login", "a:b\\c");
+        let encoded = frame.encode();
+        assert_eq!(encoded, b"CONNECT\nlogin:a:b\\c\n\n\0");
+        assert_eq!(Frame::parse(&encoded).unwrap(), frame);
+    }
+
+    #[test]
+    fn bodies_are_read_up_to_the_content_length() {
+        let frame = Frame::new(Command::Send).with_body(&b"a\0b"[..]);
+        let encoded = frame.encode();
+        assert_eq!(encoded, b"SEND\ncontent-length:3\n\na\0b\0");
+
+        let parsed = Frame::parse(&encoded).unwrap();
+        assert_eq!(parsed.body(), &b"a\0b"[..]);
+        assert_eq!(parsed.header("content-length"), Some("3"));
+
+        let parsed = Frame::parse(b"SEND\r\nfoo:1\r\nfoo:2\r\n\r\nbody\0\r\n\n").unwrap();
+        assert_eq!(parsed.header("foo"), Some("1"));
+        assert_eq!(parsed.body(), "body".as_bytes());
+    }
+
+    #[test]
+    fn malformed_frames_are_rejected() {
+        for (input, err) in [
+            (&b"SEND"[..], ParseError::Incomplete),
+            (b"SEND\n\nbody", ParseError::Incomplete),
+            (b"SEND\ncontent-length:10\n\nbody\0", ParseError::Incomplete),
+            (b"SEND\ncontent-length:2\n\nbody\0", ParseError::Incomplete),
+            (b"FOO\n\n\0", ParseError::UnknownCommand),
+            (b"SEND\nfoo\n\n\0", ParseError::InvalidHeader),
+            (b"SEND\nfoo:\\t\n\n\0", ParseError::InvalidHeader),
+            (b"SEND\ncontent-length:x\n\n\0", ParseError::InvalidHeader),
+            (b"SEND\n\n\0SEND", ParseError::TrailingData),
+        ] {
+            assert_eq!(Frame::parse(input), Err(err), "{input:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_track_subscriptions_and_send_receipts() {
+        let (events_tx, mut events) = mpsc::unbounded_channel();
+        let mut client = test_helpers::serve(|socket| async move {
+            let mut session = StompSession::accept(socket).await.unwrap();
+            assert_eq!(session.connect_frame().header("host"), Some("localhost"));
+            while let Some(event) = session.next_event().await {
+                let event = event.unwrap();
+                if let Event::Send { frame, .. } = &event {
+                    session.message("sub", frame.body().clone()).await.unwrap();
+                }
+                let subscriptions = session.subscriptions().count();
+                events_tx.send((event, subscriptions)).unwrap();
+            }
+        })
+        .await;
+
+        send(&mut client, connect()).await;
+        let connected = recv(&mut client).await;
+        assert_eq!(connected.command(), Command::Connected);
+        assert_eq!(connected.header("version"), Some("1.2"));
+        assert_eq!(connected.header("heart-beat"), Some("0,0"));
+
+        let subscribe = Frame::new(Command::Subscribe)
+            .with_header("id", "sub")
+            .with_header("destination", "/queue/a")
+            .with_header("ack", "client")
+            .with_header("receipt", "r1");
+        send(&mut client, subscribe).await;
+        let receipt = recv(&mut client).await;
+        assert_eq!(receipt.command(), Command::Receipt);
+        assert_eq!(receipt.header("receipt-id"), Some("r1"));
+        let (event, subscriptions) = events.recv().await.unwrap();
+        assert!(matches!(
+            event,
+            Event::Subscribe(sub) if sub.destination() == "/queue/a" && sub.ack() == AckMode::Client
+        ));
+        assert_eq!(subscriptions, 1);
+
+        // heart-beats are skipped
+        client.send(Message::Text("\n".into())).await.unwrap();
+        let send_frame = Frame::new(Command::Send)
+            .with_header("destination", "/queue/a")
+            .with_body("hello");
+        send(&mut client, send_frame).await;
+        let message = recv(&mut client).await;
+        assert_eq!(message.command(), Command::Message);
+        assert_eq!(message.header("subscription"), Some("sub"));
+        assert_eq!(message.header("destination"), Some("/queue/a"));
+        assert_eq!(message.header("message-id"), Some("0"));
+        assert_eq!(message.header("ack"), Some("0"));
+        assert_eq!(message.body(), "hello".as_bytes());
+        let (event, _) = events.recv().await.unwrap();
+        assert!(matches!(event, Event::Send { destination, .. } if destination == "/queue/a"));
+
+        send(&mut client, Frame::new(Command::Ack).with_header("id", "0")).await;
+        let (event, _) = events.recv().await.unwrap();
+        assert!(matches!(event, Event::Ack { id, .. } if id == "0"));
+
+        send(
+            &mut client,
+            Frame::new(Command::Unsubscribe).with_header("id", "sub"),
+        )
+        .await;
+        let (event, subscriptions) = events.recv().await.unwrap();
+        assert!(matches!(event, Event::Unsubscribe(sub) if sub.id() == "sub"));
+        assert_eq!(subscriptions, 0);
+
+        send(
+            &mut client,
+            Frame::new(Command::Disconnect).with_header("receipt", "bye"),
+        )
+        .await;
+        assert_eq!(recv(&mut client).await.header("receipt-id"), Some("bye"));
+        assert_eq!(client.next().await.unwrap().unwrap(), Message::Close(None));
+        assert!(events.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn clients_without_stomp_1_2_are_rejected() {
+        let (done_tx, mut done) = mpsc::unbounded_channel();
+        let mut client = test_helpers::serve(|socket| async move {
+            done_tx.send(StompSession::accept(socket).await).unwrap();
+        })
+        .await;
+
+        send(
+            &mut client,
+            Frame::new(Command::Connect).with_header("accept-version", "1.1"),
+        )
+        .await;
+        let error = recv(&mut client).await;
+        assert_eq!(error.command(), Command::Error);
+        assert_eq!(error.header("message"), Some("Only STOMP 1.2 is supported"));
+        assert_eq!(client.next().await.unwrap().unwrap(), Message::Close(None));
+        assert!(matches!(
+            done.recv().await.unwrap(),
+            Err(SessionError::Protocol(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn protocol_errors_end_the_session() {
+        let (done_tx, mut done) = mpsc::unbounded_channel();
+        let mut client = test_helpers::serve(|socket| async move {
+            let mut session = StompSession::accept(socket).await.unwrap();
+            let result = session.next_event().await.unwrap();
+            done_tx
+                .send((result, session.next_event().await.is_none()))
+                .unwrap();
+        })
+        .await;
+
+        send(&mut client, connect()).await;
+        recv(&mut client).await;
+        send(
+            &mut client,
+            Frame::new(Command::Unsubscribe).with_header("id", "nope"),
+        )
+        .await;
+
+        let error = recv(&mut client).await;
+        assert_eq!(error.command(), Command::Error);
+        assert_eq!(error.header("message"), Some("Unknown subscription `nope`"));
+        assert_eq!(client.next().await.unwrap().unwrap(), Message::Close(None));
+        let (result, ended) = done.recv().await.unwrap();
+        assert!(matches!(result, Err(SessionError::Protocol(_))));
+        assert!(ended);
+    }
+
+    #[tokio::test]
+    async fn closing_the_socket_ends_the_session() {
+        let (done_tx, mut done) = mpsc::unbounded_channel();
+        let mut client = test_helpers::serve(|socket| async move {
+            let mut session = StompSession::accept(socket).await.unwrap();
+            done_tx.send(session.next_event().await.is_none()).unwrap();
+        })
+        .await;
+
+        send(&mut client, connect()).await;
+        recv(&mut client).await;
+        client.close(None).await.unwrap();
+
+        assert_eq!(client.next().await.unwrap().unwrap(), Message::Close(None));
+        assert!(done.recv().await.unwrap());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{WebSocket, WebSocketUpgrade};
+use axum::{body::Body, response::Response, routing::get, Router};
+use futures_util::future::poll_fn;
+use http::{header, HeaderMap, HeaderValue, Method, Request};
+use hyper::server::conn::Http;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+use tokio::io::DuplexStream;
+use tokio_tungstenite::{client_async, WebSocketStream};
+use tower_service::Service;
+
+/// Connect a client to `app` over an in-memory transport buffering at most `capacity` bytes
+/// in each direction.
+pub(crate) async fn connect(app: Router, capacity: usize) -> WebSocketStream<DuplexStream> {
+    connect_to(app, "ws://localhost/", capacity).await
+}
+
+/// Like [`connect`] but requests `uri`.
+pub(crate) async fn connect_to(
+    app: Router,
+    uri: &str,
+    capacity: usize,
+) -> WebSocketStream<DuplexStream> {
+    let (client, server) = tokio::io::duplex(capacity);
+    tokio::spawn(Http::new().serve_connection(server, app).with_upgrades());
+    let (socket, _) = client_async(uri, client).await.unwrap();
+    socket
+}
+
+/// Connect a client to a server that runs `callback` on the upgraded socket.
+pub(crate) async fn serve<F, Fut>(callback: F) -> WebSocketStream<DuplexStream>
+where
+    F: FnOnce(WebSocket) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let callback = Arc::new(Mutex::new(Some(callback)));
+    let app = Router::new().route(
+        "/",
+        get(move |ws: WebSocketUpgrade| async move {
+            let callback = callback.lock().unwrap().take().expect("connected twice");
+            ws.on_upgrade(callback)
+        }),
+    );
+    connect(app, 1024).await
+}
+
+/// Send `req` to `app` without a connection, so upgrades are rejected unless `req` carries an
+/// `OnUpgrade` extension.
+pub(crate) async fn call(mut app: Router, req: Request<Body>) -> Response {
+    poll_fn(|cx| app.poll_ready(cx)).await.unwrap();
+    app.call(req).await.unwrap()
+}
+
+/// The headers of a valid upgrade request.
+pub(crate) fn upgrade_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+    headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+    headers.insert(
+        header::SEC_WEBSOCKET_VERSION,
+        HeaderValue::from_static("13"),
+    );
+    headers.insert(
+        header::SEC_WEBSOCKET_KEY,
+        HeaderValue::from_static("dGhlIHNhbXBsZSBub25jZQ=="),
+    );
+    headers
+}
+
+/// A `WebSocketUpgrade` for a request offering `protocols`, one header line per entry.
+pub(crate) fn upgrade_offering(protocols: &[&'static str]) -> WebSocketUpgrade {
+    let mut headers = upgrade_headers();
+    for protocol in protocols {
+        headers.append(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(protocol),
+        );
+    }
+    let on_upgrade = hyper::upgrade::on(Request::new(()));
+    WebSocketUpgrade::new(&Method::GET, &headers, on_upgrade).unwrap()
+}
@@ -0,0 +1,518 @@
+//! Utilities for testing applications that use WebSockets.
+//!
+//! [`Faults`] injects failures into the sockets of an application, so reconnect and retry logic
+//! can be tested against realistic network problems. Insert it into the request extensions,
+//! typically with [`axum::Extension`], and every [`WebSocket`](crate::WebSocket) created by
+//! [`WebSocketUpgrade`](crate::WebSocketUpgrade) misbehaves accordingly.
+//!
+//! [`LoadGenerator`] serves a [`Router`] on a local port and runs many concurrent client
+//! connections against it, reporting throughput and latency.
+//!
+//! Requires the `testing` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use axum::{response::Response, routing::get, Extension, Router};
+//! use axum_tungstenite::{testing::Faults, WebSocketUpgrade};
+//! use std::time::Duration;
+//!
+//! fn app() -> Router {
+//!     Router::new().route("/ws", get(handler))
+//! }
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|socket| async { /* ... */ })
+//! }
+//!
+//! // in a test
+//! let faults = Faults::new()
+//!     .latency(Duration::from_millis(50))
+//!     .drop_outgoing(0.1)
+//!     .sever_after(100);
+//! let app = app().layer(Extension(faults));
+//! ```
+//!
+//! ```
+//! use axum::{response::Response, routing::get, Router};
+//! use axum_tungstenite::{
+//!     testing::{LoadGenerator, Script},
+//!     Message, WebSocketUpgrade,
+//! };
+//!
+//! async fn echo(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|mut socket| async move {
+//!         while let Some(Ok(msg)) = socket.recv().await {
+//!             if socket.send(msg).await.is_err() {
+//!                 break;
+//!             }
+//!         }
+//!     })
+//! }
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> std::io::Result<()> {
+//! let app = Router::new().route("/ws", get(echo));
+//! let report = LoadGenerator::new(app)
+//!     .path("/ws")
+//!     .connections(10)
+//!     .iterations(5)
+//!     .script(Script::new().send(Message::Text("hello".into())).recv())
+//!     .run()
+//!     .await?;
+//!
+//! assert_eq!(report.failed_connections(), 0);
+//! println!(
+//!     "{:.0} messages/s, p99 latency {:?}",
+//!     report.throughput(),
+//!     report.latency_percentile(99.0),
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Error, Message};
+use axum::Router;
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use rand::Rng;
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    sync::oneshot,
+    task::JoinSet,
+    time::{Instant, Sleep},
+};
+
+/// Failures to inject into sockets.
+///
+/// See the [module docs](self) for an example.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Faults {
+    latency: Option<Duration>,
+    flush_delay: Option<Duration>,
+    drop_rate: f64,
+    sever_after: Option<u64>,
+}
+
+impl Faults {
+    /// Create `Faults` that don't inject anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait `latency` before sending each message.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Wait `delay` before each flush of the socket.
+    ///
+    /// Messages are still written in order, but buffered messages reach the client later.
+    pub fn flush_delay(mut self, delay: Duration) -> Self {
+        self.flush_delay = Some(delay);
+        self
+    }
+
+    /// Silently drop the given fraction of outgoing `Text` and `Binary` messages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not between `0.0` and `1.0`.
+    pub fn drop_outgoing(mut self, rate: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&rate),
+            "drop rate must be between 0.0 and 1.0"
+        );
+        self.drop_rate = rate;
+        self
+    }
+
+    /// Sever the connection after `messages` messages have been sent or received.
+    ///
+    /// Afterwards the socket fails with [`io::ErrorKind::ConnectionReset`] and the connection
+    /// is closed without a closing handshake once the socket is dropped.
+    pub fn sever_after(mut self, messages: u64) -> Self {
+        self.sever_after = Some(messages);
+        self
+    }
+}
+
+/// The state of the faults injected into a socket.
+#[derive(Debug)]
+pub(crate) struct FaultState {
+    send_delay: Delay,
+    flush_delay: Delay,
+    drop_rate: f64,
+    sever_after: Option<u64>,
+    messages: u64,
+}
+
+impl FaultState {
+    pub(crate) fn new(faults: Faults) -> Self {
+        Self {
+            send_delay: Delay::new(faults.latency),
+            flush_delay: Delay::new(faults.flush_delay),
+            drop_rate: faults.drop_rate,
+            sever_after: faults.sever_after,
+            messages: 0,
+        }
+    }
+
+    pub(crate) fn poll_send_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.send_delay.poll(cx)
+    }
+
+    pub(crate) fn poll_flush_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.flush_delay.poll(cx)
+    }
+
+    pub(crate) fn flushed(&mut self) {
+        self.flush_delay.reset();
+    }
+
+    pub(crate) fn received(&mut self) {
+        self.messages += 1;
+    }
+
+    /// Returns `false` if the message should be dropped instead of sent.
+    pub(crate) fn sent(&mut self, droppable: bool) -> bool {
+        self.messages += 1;
+        self.send_delay.reset();
+        !(droppable && self.drop_rate > 0.0 && rand::thread_rng().gen_bool(self.drop_rate))
+    }
+
+    pub(crate) fn severed(&self) -> Option<io::Error> {
+        self.sever_after
+            .filter(|max| self.messages >= *max)
+            .map(|_| {
+                io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "connection severed by fault injection",
+                )
+            })
+    }
+}
+
+/// A delay that has to pass once before each operation.
+#[derive(Debug)]
+struct Delay {
+    duration: Option<Duration>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    elapsed: bool,
+}
+
+impl Delay {
+    fn new(duration: Option<Duration>) -> Self {
+        Self {
+            duration,
+            sleep: None,
+            elapsed: false,
+        }
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let duration = match self.duration {
+            Some(duration) if !self.elapsed => duration,
+            _ => return Poll::Ready(()),
+        };
+        let sleep = self
+            .sleep
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(duration)));
+        ready!(sleep.as_mut().poll(cx));
+        self.sleep = None;
+        self.elapsed = true;
+        Poll::Ready(())
+    }
+
+    fn reset(&mut self) {
+        self.elapsed = false;
+    }
+}
+
+/// Runs concurrent client connections against a [`Router`] and measures how it copes.
+///
+/// The router is served on a random local port for the duration of [`run`](Self::run). Each
+/// connection runs its [`Script`] the configured number of times and then closes the
+/// connection.
+///
+/// See the [module docs](self) for an example.
+#[derive(Debug)]
+pub struct LoadGenerator {
+    app: Router,
+    path: String,
+    connections: usize,
+    iterations: usize,
+    script: Script,
+}
+
+impl LoadGenerator {
+    /// Create a new `LoadGenerator` for `app`.
+    ///
+    /// Defaults to 10 connections to `/` that each send a single `Text` message and wait for a
+    /// reply.
+    pub fn new(app: Router) -> Self {
+        Self {
+            app,
+            path: "/".to_owned(),
+            connections: 10,
+            iterations: 1,
+            script: Script::new().send(Message::Text("ping".to_owned())).recv(),
+        }
+    }
+
+    /// Set the path to connect to.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Set the number of concurrent connections.
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    /// Set how many times each connection runs the script.
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Set the script each connection runs.
+    pub fn script(mut self, script: Script) -> Self {
+        self.script = script;
+        self
+    }
+
+    /// Serve the router, run all connections to completion, and report the results.
+    ///
+    /// Fails only if the router couldn't be served. Failures of individual connections are
+    /// counted in the [`LoadReport`].
+    pub async fn run(self) -> io::Result<LoadReport> {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = axum::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .map_err(io::Error::other)?
+            .serve(self.app.into_make_service());
+        let url = format!("ws://{}{}", server.local_addr(), self.path);
+        tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = shutdown_rx.await;
+        }));
+
+        let started = Instant::now();
+        let mut connections = JoinSet::new();
+        for _ in 0..self.connections {
+            let url = url.clone();
+            let script = self.script.clone();
+            let iterations = self.iterations;
+            connections.spawn(async move {
+                let mut stats = ConnectionStats::default();
+                let result = run_connection(&url, &script, iterations, &mut stats).await;
+                (result, stats)
+            });
+        }
+
+        let mut report = LoadReport::default();
+        while let Some(joined) = connections.join_next().await {
+            report.connections += 1;
+            match joined {
+                Ok((result, stats)) => {
+                    if let Err(ConnectionError::Connect) = result {
+                        report.failed_connections += 1;
+                    } else if result.is_err() {
+                        report.errors += 1;
+                    }
+                    report.messages_sent += stats.sent;
+                    report.messages_received += stats.received;
+                    report.latencies.extend(stats.latencies);
+                }
+                Err(_) => report.errors += 1,
+            }
+        }
+        report.elapsed = started.elapsed();
+        report.latencies.sort_unstable();
+
+        let _ = shutdown_tx.send(());
+        Ok(report)
+    }
+}
+
+/// The traffic a [`LoadGenerator`] connection sends.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Send(Message),
+    Recv,
+    Sleep(Duration),
+}
+
+impl Script {
+    /// Create an empty `Script`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send `msg`.
+    pub fn send(mut self, msg: Message) -> Self {
+        self.steps.push(Step::Send(msg));
+        self
+    }
+
+    /// Wait for a `Text` or `Binary` message from the server.
+    ///
+    /// The time since the last message was sent is recorded as a latency sample.
+    pub fn recv(mut self) -> Self {
+        self.steps.push(Step::Recv);
+        self
+    }
+
+    /// Wait for `duration`.
+    pub fn sleep(mut self, duration: Duration) -> Self {
+        self.steps.push(Step::Sleep(duration));
+        self
+    }
+}
+
+#[derive(Default)]
+struct ConnectionStats {
+    sent: u64,
+    received: u64,
+    latencies: Vec<Duration>,
+}
+
+enum ConnectionError {
+    Connect,
+    Failed,
+}
+
+impl From<Error> for ConnectionError {
+    fn from(_: Error) -> Self {
+        Self::Failed
+    }
+}
+
+async fn run_connection(
+    url: &str,
+    script: &Script,
+    iterations: usize,
+    stats: &mut ConnectionStats,
+) -> Result<(), ConnectionError> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(|_| ConnectionError::Connect)?;
+
+    let mut last_sent = Instant::now();
+    for _ in 0..iterations {
+        for step in &script.steps {
+            match step {
+                Step::Send(msg) => {
+                    socket.send(msg.clone()).await?;
+                    stats.sent += 1;
+                    last_sent = Instant::now();
+                }
+                Step::Recv => loop {
+                    match socket.next().await.ok_or(ConnectionError::Failed)?? {
+                        Message::Text(_) | Message::Binary(_) => {
+                            stats.received += 1;
+                            stats.latencies.push(last_sent.elapsed());
+                            break;
+                        }
+                        Message::Close(_) => return Err(ConnectionError::Failed),
+                        _ => {}
+                    }
+                },
+                Step::Sleep(duration) => tokio::time::sleep(*duration).await,
+            }
+        }
+    }
+
+    // the script has completed, so problems while closing don't count as errors
+    if socket.close(None).await.is_ok() {
+        while let Some(Ok(_)) = socket.next().await {}
+    }
+    Ok(())
+}
+
+/// The results of a [`LoadGenerator`] run.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    connections: usize,
+    failed_connections: usize,
+    errors: usize,
+    messages_sent: u64,
+    messages_received: u64,
+    elapsed: Duration,
+    /// Sorted from fastest to slowest.
+    latencies: Vec<Duration>,
+}
+
+impl LoadReport {
+    /// The number of connections attempted.
+    pub fn connections(&self) -> usize {
+        self.connections
+    }
+
+    /// The number of connections that couldn't be established.
+    pub fn failed_connections(&self) -> usize {
+        self.failed_connections
+    }
+
+    /// The number of established connections that failed before completing their script.
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// The total number of messages sent by the clients.
+    pub fn messages_sent(&self) -> u64 {
+        self.messages_sent
+    }
+
+    /// The total number of `Text` and `Binary` messages received by the clients.
+    pub fn messages_received(&self) -> u64 {
+        self.messages_received
+    }
+
+    /// How long the run took.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Messages sent and received per second.
+    pub fn throughput(&self) -> f64 {
+        (self.messages_sent + self.messages_received) as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The mean latency, or `None` if no latencies were recorded.
+    pub fn mean_latency(&self) -> Option<Duration> {
+        let samples = u32::try_from(self.latencies.len())
+            .ok()
+            .filter(|n| *n > 0)?;
+        Some(self.latencies.iter().sum::<Duration>() / samples)
+    }
+
+    /// The latency below which `percentile` percent of the samples fall, or `None` if no
+    /// latencies were recorded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `percentile` is not between `0.0` and `100.0`.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        assert!(
+            (0.0..=100.0).contains(&percentile),
+            "percentile must be between 0.0 and 100.0"
+        );
+        let last = self.latencies.len().checked_sub(1)?;
+        let idx = (last as f64 * percentile / 100.0).round() as usize;
+        self.latencies.get(idx).copied()
+    }
+}
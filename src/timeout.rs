@@ -0,0 +1,58 @@
+use std::fmt;
+
+/// Error returned when a connection is ended because a timeout elapsed.
+///
+/// It is returned from [`WebSocket::recv`](crate::WebSocket::recv), and from sending for
+/// [`Timeout::SlowConsumer`], wrapped in an [`Error::Io`](crate::Error::Io) with
+/// [`ErrorKind::TimedOut`](std::io::ErrorKind::TimedOut). It can be recovered by downcasting the
+/// result of [`std::io::Error::get_ref`] to tell the timeouts apart:
+///
+/// ```
+/// use axum_tungstenite::{Error, Timeout, WebSocket};
+///
+/// async fn run(mut socket: WebSocket) {
+///     while let Some(msg) = socket.recv().await {
+///         match msg {
+///             Ok(msg) => { /* ... */ }
+///             Err(Error::Io(err)) => {
+///                 match err.get_ref().and_then(|err| err.downcast_ref::<Timeout>()) {
+///                     Some(Timeout::Liveness) => { /* the client went silent */ }
+///                     Some(_) => { /* another timeout */ }
+///                     None => { /* some other I/O error */ }
+///                 }
+///             }
+///             Err(_) => { /* ... */ }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Timeout {
+    /// No frames were received within the
+    /// [liveness timeout](crate::WebSocketUpgrade::liveness_timeout).
+    Liveness,
+    /// The client didn't acknowledge the server's `Close` frame within the
+    /// [close timeout](crate::WebSocketUpgrade::close_timeout).
+    CloseHandshake,
+    /// The client stopped reading for longer than the
+    /// [slow consumer](crate::WebSocketUpgrade::slow_consumer) threshold.
+    SlowConsumer,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Liveness => write!(f, "No frames received within the liveness timeout"),
+            Self::CloseHandshake => {
+                write!(f, "Close frame not acknowledged within the close timeout")
+            }
+            Self::SlowConsumer => write!(
+                f,
+                "Client stopped reading for longer than the slow consumer threshold"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Timeout {}
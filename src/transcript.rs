@@ -0,0 +1,663 @@
+//! Record the messages of a connection and replay them against a server.
+//!
+//! [`WebSocket::record`](crate::WebSocket::record) passes every message sent or received on the
+//! socket, together with when it happened, to a [`TranscriptSink`], such as a channel or a
+//! [`TranscriptWriter`] writing to a file. A recorded [`Transcript`] can be parsed back and
+//! [`replay`]ed, sending the client's messages to a handler with their original timing. This
+//! helps reproducing problems that only happen with particular clients.
+//!
+//! # Transcript format
+//!
+//! Transcripts are written one message per line, as the microseconds since the connection was
+//! established, `in` or `out`, the message type, and the payload:
+//!
+//! ```text
+//! 1520 in text hello\nworld
+//! 1893 out binary 0a0b0c
+//! 60012 in ping
+//! 61004 in close 1000 bye
+//! ```
+//!
+//! `text` payloads and close reasons escape `\`, line feeds, and carriage returns with a
+//! backslash, and `binary`, `ping`, and `pong` payloads are written in hex.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::response::Response;
+//! use axum_tungstenite::{transcript::TranscriptWriter, WebSocketUpgrade};
+//! use std::fs::File;
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|mut socket| async move {
+//!         if let Ok(file) = File::create("/tmp/connection.transcript") {
+//!             socket.record(TranscriptWriter::new(file));
+//!         }
+//!         // handle the socket as usual
+//!     })
+//! }
+//! ```
+//!
+//! Replaying the transcript in a test:
+//!
+//! ```no_run
+//! use axum_tungstenite::transcript::{self, Transcript};
+//!
+//! # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! let recorded: Transcript = std::fs::read_to_string("/tmp/connection.transcript")?.parse()?;
+//! let replayed = transcript::replay(&recorded, "ws://127.0.0.1:3000/ws").await?;
+//! assert_eq!(
+//!     replayed.outbound().collect::<Vec<_>>(),
+//!     recorded.outbound().collect::<Vec<_>>(),
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{CloseCode, CloseFrame, Error, Message};
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use std::{
+    fmt::{self, Write as _},
+    io,
+    str::FromStr,
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+
+/// Whether a message was received or sent by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent by the client and received by the server.
+    Inbound,
+    /// Sent by the server.
+    Outbound,
+}
+
+/// A message in a [`Transcript`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    elapsed: Duration,
+    direction: Direction,
+    message: Message,
+}
+
+impl TranscriptEntry {
+    /// Create a new `TranscriptEntry`.
+    pub fn new(elapsed: Duration, direction: Direction, message: Message) -> Self {
+        Self {
+            elapsed,
+            direction,
+            message,
+        }
+    }
+
+    /// The time since the connection was established.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Whether the message was received or sent.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// The message.
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Consume `self` and get the message.
+    pub fn into_message(self) -> Message {
+        self.message
+    }
+}
+
+impl fmt::Display for TranscriptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::Inbound => "in",
+            Direction::Outbound => "out",
+        };
+        write!(f, "{} {}", self.elapsed.as_micros(), direction)?;
+
+        match &self.message {
+            Message::Text(text) => write!(f, " text{}", Payload(&escape(text))),
+            Message::Binary(data) => write!(f, " binary{}", Payload(&hex(data))),
+            Message::Ping(data) => write!(f, " ping{}", Payload(&hex(data))),
+            Message::Pong(data) => write!(f, " pong{}", Payload(&hex(data))),
+            Message::Close(None) => f.write_str(" close"),
+            Message::Close(Some(frame)) => write!(
+                f,
+                " close {}{}",
+                u16::from(frame.code),
+                Payload(&escape(&frame.reason))
+            ),
+            Message::Frame(_) => f.write_str(" frame"),
+        }
+    }
+}
+
+/// Writes a space before non-empty payloads.
+struct Payload<'a>(&'a str);
+
+impl fmt::Display for Payload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            write!(f, " {}", self.0)
+        }
+    }
+}
+
+impl FromStr for TranscriptEntry {
+    type Err = ParseTranscriptError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut parts = line.splitn(4, ' ');
+        let elapsed = parts
+            .next()
+            .and_then(|micros| micros.parse().ok())
+            .map(Duration::from_micros)
+            .ok_or_else(|| ParseTranscriptError::new("invalid timestamp"))?;
+        let direction = match parts.next() {
+            Some("in") => Direction::Inbound,
+            Some("out") => Direction::Outbound,
+            _ => return Err(ParseTranscriptError::new("invalid direction")),
+        };
+        let kind = parts
+            .next()
+            .ok_or_else(|| ParseTranscriptError::new("missing message type"))?;
+        let payload = parts.next().unwrap_or_default();
+
+        let message = match kind {
+            "text" => Message::Text(unescape(payload)?),
+            "binary" => Message::Binary(unhex(payload)?),
+            "ping" => Message::Ping(unhex(payload)?),
+            "pong" => Message::Pong(unhex(payload)?),
+            "close" if payload.is_empty() => Message::Close(None),
+            "close" => {
+                let (code, reason) = payload.split_once(' ').unwrap_or((payload, ""));
+                let code = code
+                    .parse::<u16>()
+                    .map_err(|_| ParseTranscriptError::new("invalid close code"))?;
+                Message::Close(Some(CloseFrame {
+                    code: CloseCode::from(code),
+                    reason: unescape(reason)?.into(),
+                }))
+            }
+            _ => return Err(ParseTranscriptError::new("unknown message type")),
+        };
+
+        Ok(Self::new(elapsed, direction, message))
+    }
+}
+
+/// The messages of a connection, in the order they were sent or received.
+///
+/// Parse a transcript with [`str::parse`] and write it with its [`Display`](fmt::Display)
+/// implementation. See the [module docs](self) for the format.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    /// Create an empty `Transcript`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry to the end of the transcript.
+    pub fn push(&mut self, entry: TranscriptEntry) {
+        self.entries.push(entry);
+    }
+
+    /// The entries of the transcript.
+    pub fn entries(&self) -> &[TranscriptEntry] {
+        &self.entries
+    }
+
+    /// The messages received by the server.
+    pub fn inbound(&self) -> impl Iterator<Item = &Message> {
+        self.messages(Direction::Inbound)
+    }
+
+    /// The messages sent by the server.
+    pub fn outbound(&self) -> impl Iterator<Item = &Message> {
+        self.messages(Direction::Outbound)
+    }
+
+    fn messages(&self, direction: Direction) -> impl Iterator<Item = &Message> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.direction == direction)
+            .map(TranscriptEntry::message)
+    }
+}
+
+impl FromIterator<TranscriptEntry> for Transcript {
+    fn from_iter<I: IntoIterator<Item = TranscriptEntry>>(iter: I) -> Self {
+        Self {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{entry}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Transcript {
+    type Err = ParseTranscriptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(idx, line)| {
+                line.parse()
+                    .map_err(|err: ParseTranscriptError| ParseTranscriptError {
+                        line: Some(idx + 1),
+                        ..err
+                    })
+            })
+            .collect()
+    }
+}
+
+/// The error returned when parsing a [`Transcript`] or [`TranscriptEntry`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTranscriptError {
+    line: Option<usize>,
+    reason: &'static str,
+}
+
+impl ParseTranscriptError {
+    fn new(reason: &'static str) -> Self {
+        Self { line: None, reason }
+    }
+
+    /// The line of the transcript that failed to parse, starting at 1.
+    ///
+    /// `None` when parsing a single [`TranscriptEntry`].
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+}
+
+impl fmt::Display for ParseTranscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.reason),
+            None => f.write_str(self.reason),
+        }
+    }
+}
+
+impl std::error::Error for ParseTranscriptError {}
+
+/// Receives the messages recorded by [`WebSocket::record`](crate::WebSocket::record).
+///
+/// The `Pong`s and `Close` frames that tungstenite sends automatically in reply to the client
+/// are recorded as well.
+pub trait TranscriptSink: Send + 'static {
+    /// Record a message.
+    ///
+    /// This is called while the socket is being polled, so it shouldn't block.
+    fn record(&mut self, entry: TranscriptEntry);
+}
+
+/// Entries are dropped once the receiver has been dropped.
+impl TranscriptSink for mpsc::UnboundedSender<TranscriptEntry> {
+    fn record(&mut self, entry: TranscriptEntry) {
+        let _ = self.send(entry);
+    }
+}
+
+/// A [`TranscriptSink`] that writes entries to an [`io::Write`] in the
+/// [transcript format](self#transcript-format).
+///
+/// Each entry is written with a separate call to [`io::Write::write_all`] and errors are
+/// ignored. Writes happen while the socket is being polled, so prefer fast writers, like files
+/// on a local disk.
+#[derive(Debug)]
+pub struct TranscriptWriter<W> {
+    writer: W,
+}
+
+impl<W> TranscriptWriter<W> {
+    /// Create a new `TranscriptWriter` writing to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Consume `self` and get the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W> TranscriptSink for TranscriptWriter<W>
+where
+    W: io::Write + Send + 'static,
+{
+    fn record(&mut self, entry: TranscriptEntry) {
+        let _ = self.writer.write_all(format!("{entry}\n").as_bytes());
+    }
+}
+
+pub(crate) struct Recorder {
+    sink: Box<dyn TranscriptSink>,
+    close_sent: bool,
+}
+
+impl Recorder {
+    pub(crate) fn new<S: TranscriptSink>(sink: S) -> Self {
+        Self {
+            sink: Box::new(sink),
+            close_sent: false,
+        }
+    }
+
+    pub(crate) fn record_received(&mut self, elapsed: Duration, msg: &Message) {
+        self.record(elapsed, Direction::Inbound, msg);
+
+        // tungstenite answers these itself, without going through the socket's `Sink`
+        match msg {
+            Message::Ping(data) => {
+                self.record_sent(elapsed, &Message::Pong(data.clone()));
+            }
+            Message::Close(frame) if !self.close_sent => {
+                self.record_sent(elapsed, &Message::Close(frame.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn record_sent(&mut self, elapsed: Duration, msg: &Message) {
+        self.close_sent |= matches!(msg, Message::Close(_));
+        self.record(elapsed, Direction::Outbound, msg);
+    }
+
+    fn record(&mut self, elapsed: Duration, direction: Direction, msg: &Message) {
+        // raw frames can't be replayed
+        if !matches!(msg, Message::Frame(_)) {
+            self.sink
+                .record(TranscriptEntry::new(elapsed, direction, msg.clone()));
+        }
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Recorder").finish()
+    }
+}
+
+/// Connect to `url` and send the inbound messages of `transcript` with their original timing.
+///
+/// After the last inbound message a `Close` frame is sent, unless the transcript already ends
+/// with one. Returns the messages sent by the server, as [outbound](Direction::Outbound) entries
+/// timed from when the connection was established.
+///
+/// See the [module docs](self) for an example.
+pub async fn replay(transcript: &Transcript, url: &str) -> Result<Transcript, Error> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(url).await?;
+    let started = Instant::now();
+
+    let mut inbound = transcript
+        .entries()
+        .iter()
+        .filter(|entry| entry.direction == Direction::Inbound);
+    let mut next = inbound.next();
+    let mut closing = false;
+    let mut replayed = Transcript::new();
+
+    loop {
+        let deadline = started + next.map_or(Duration::ZERO, TranscriptEntry::elapsed);
+
+        tokio::select! {
+            msg = socket.next() => match msg {
+                Some(Ok(msg)) => {
+                    closing |= matches!(msg, Message::Close(_));
+                    replayed.push(TranscriptEntry::new(started.elapsed(), Direction::Outbound, msg));
+                }
+                Some(Err(Error::ConnectionClosed)) | None => break,
+                Some(Err(err)) => return Err(err),
+            },
+            () = sleep_until(deadline), if next.is_some() && !closing => {
+                if let Some(entry) = next.take() {
+                    closing = matches!(entry.message, Message::Close(_));
+                    socket.send(entry.message.clone()).await?;
+                }
+                next = inbound.next();
+                if next.is_none() && !closing {
+                    closing = true;
+                    socket.send(Message::Close(None)).await?;
+                }
+            },
+        }
+    }
+
+    Ok(replayed)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &str) -> Result<String, ParseTranscriptError> {
+    let mut text = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => text.push('\\'),
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            _ => return Err(ParseTranscriptError::new("invalid escape sequence")),
+        }
+    }
+    Ok(text)
+}
+
+fn hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn unhex(hex: &str) -> Result<Vec<u8>, ParseTranscriptError> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2 && pair.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| ParseTranscriptError::new("invalid hex payload"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_helpers, WebSocketUpgrade};
+    use axum::{routing::get, Router};
+    use hyper::server::conn::Http;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn entries_round_trip_through_the_transcript_format() {
+        let transcript: Transcript = [
+            (Direction::Inbound, Message::Text("a \\ b\nc\r".into())),
+            (Direction::Outbound, Message::Binary(vec![0x0a, 0xff])),
+            (Direction::Inbound, Message::Ping(Vec::new())),
+            (Direction::Outbound, Message::Pong(vec![1])),
+            (Direction::Inbound, Message::Text(String::new())),
+            (
+                Direction::Inbound,
+                Message::Close(Some(CloseFrame {
+                    code: CloseCode::Normal,
+                    reason: "bye\nnow".into(),
+                })),
+            ),
+            (Direction::Outbound, Message::Close(None)),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (direction, message))| {
+            TranscriptEntry::new(Duration::from_micros(idx as u64 * 10), direction, message)
+        })
+        .collect();
+
+        let written = transcript.to_string();
+        assert_eq!(
+            written,
+            "0 in text a \\\\ b\\nc\\r\n\
+             10 out binary 0aff\n\
+             20 in ping\n\
+             30 out pong 01\n\
+             40 in text\n\
+             50 in close 1000 bye\\nnow\n\
+             60 out close\n"
+        );
+        assert_eq!(written.parse::<Transcript>().unwrap(), transcript);
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_line() {
+        let err = "1 in text ok\n\n3 in binary 0g\n"
+            .parse::<Transcript>()
+            .unwrap_err();
+        assert_eq!(err.line(), Some(3));
+        assert_eq!(err.to_string(), "line 3: invalid hex payload");
+
+        for (line, reason) in [
+            ("x in ping", "invalid timestamp"),
+            ("1 sideways ping", "invalid direction"),
+            ("1 in", "missing message type"),
+            ("1 in frame", "unknown message type"),
+            ("1 in text \\t", "invalid escape sequence"),
+            ("1 in binary abc", "invalid hex payload"),
+            ("1 out close bye", "invalid close code"),
+        ] {
+            let err = line.parse::<TranscriptEntry>().unwrap_err();
+            assert_eq!(err.line(), None);
+            assert_eq!(err.to_string(), reason, "{line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn recording_includes_automatic_replies() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut client = test_helpers::serve(|mut socket| async move {
+            socket.record(tx);
+            while let Some(Ok(msg)) = socket.recv().await {
+                if let Message::Text(text) = msg {
+                    socket
+                        .send(Message::Text(text.to_uppercase()))
+                        .await
+                        .unwrap();
+                }
+            }
+        })
+        .await;
+
+        client.send(Message::Text("hi".into())).await.unwrap();
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            Message::Text("HI".into())
+        );
+        client.send(Message::Ping(vec![7])).await.unwrap();
+        client.close(None).await.unwrap();
+        while client.next().await.is_some() {}
+
+        let mut recorded = Vec::new();
+        while let Some(entry) = rx.recv().await {
+            recorded.push((entry.direction(), entry.into_message()));
+        }
+        assert_eq!(
+            recorded,
+            [
+                (Direction::Inbound, Message::Text("hi".into())),
+                (Direction::Outbound, Message::Text("HI".into())),
+                (Direction::Inbound, Message::Ping(vec![7])),
+                (Direction::Outbound, Message::Pong(vec![7])),
+                (Direction::Inbound, Message::Close(None)),
+                (Direction::Outbound, Message::Close(None)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_sends_the_inbound_messages_and_collects_the_replies() {
+        let app = Router::new().route(
+            "/",
+            get(|ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|mut socket| async move {
+                    while let Some(Ok(msg)) = socket.recv().await {
+                        if let Message::Text(text) = msg {
+                            let len = text.len().to_string();
+                            socket.send(Message::Text(len)).await.unwrap();
+                        }
+                    }
+                })
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            Http::new()
+                .serve_connection(stream, app)
+                .with_upgrades()
+                .await
+                .unwrap();
+        });
+
+        let recorded: Transcript = "0 in text a\n\
+             100 out text stale\n\
+             200 in text abc\n"
+            .parse()
+            .unwrap();
+        let replayed = replay(&recorded, &format!("ws://{addr}/")).await.unwrap();
+
+        assert_eq!(
+            replayed.outbound().collect::<Vec<_>>(),
+            [
+                &Message::Text("1".into()),
+                &Message::Text("3".into()),
+                &Message::Close(None),
+            ]
+        );
+        assert!(replayed
+            .entries()
+            .iter()
+            .all(|entry| entry.direction() == Direction::Outbound));
+    }
+}
@@ -0,0 +1,446 @@
+//! Adapters for using a [`WebSocket`] as a generic transport.
+//!
+//! [`IoCompat`] exposes the socket as a [`Stream`] and [`Sink`] whose error type is
+//! [`io::Error`], so it can be used with transport code that isn't aware of tungstenite.
+//! [`ByteStream`] goes one step further and exposes the payloads of `Binary` messages as a byte
+//! stream implementing [`AsyncRead`] and [`AsyncWrite`], for tunneling protocols that expect an
+//! ordinary connection. [`tunnel`] connects such a byte stream to another connection, like a
+//! [`TcpStream`](tokio::net::TcpStream), for writing WebSocket to TCP gateways.
+//!
+//! # Examples
+//!
+//! ```
+//! use axum_tungstenite::{Message, WebSocket};
+//! use futures_util::{Sink, SinkExt, Stream, StreamExt};
+//! use std::io;
+//!
+//! // generic code that only knows about `io::Error`
+//! async fn echo<T>(mut transport: T) -> io::Result<()>
+//! where
+//!     T: Stream<Item = io::Result<Message>> + Sink<Message, Error = io::Error> + Unpin,
+//! {
+//!     while let Some(msg) = transport.next().await {
+//!         transport.send(msg?).await?;
+//!     }
+//!     Ok(())
+//! }
+//!
+//! async fn handle_socket(socket: WebSocket) {
+//!     let _ = echo(socket.into_io_compat()).await;
+//! }
+//! ```
+//!
+//! ```
+//! use axum_tungstenite::WebSocket;
+//! use tokio::io::{AsyncRead, AsyncWrite};
+//!
+//! // a protocol implementation that runs over any byte stream
+//! async fn serve_protocol<T>(io: T)
+//! where
+//!     T: AsyncRead + AsyncWrite + Unpin,
+//! {
+//!     // ...
+//! }
+//!
+//! async fn handle_socket(socket: WebSocket) {
+//!     serve_protocol(socket.into_byte_stream()).await;
+//! }
+//! ```
+//!
+//! ```
+//! use axum::response::Response;
+//! use axum_tungstenite::{transport::tunnel, WebSocketUpgrade};
+//! use tokio::net::TcpStream;
+//!
+//! async fn ssh_gateway(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|socket| async move {
+//!         if let Ok(stream) = TcpStream::connect("127.0.0.1:22").await {
+//!             let _ = tunnel(socket, stream).await;
+//!         }
+//!     })
+//! }
+//! ```
+
+use crate::{Error, Message, WebSocket};
+use futures_util::{
+    sink::{Sink, SinkExt},
+    stream::{Stream, StreamExt},
+};
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{copy, split, AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// A [`WebSocket`] whose [`Stream`] and [`Sink`] error type is [`io::Error`].
+///
+/// Created with [`WebSocket::into_io_compat`]. [`Error::Io`] is unwrapped, using the socket
+/// after it has closed fails with [`io::ErrorKind::BrokenPipe`], and other errors are wrapped
+/// with [`io::ErrorKind::Other`].
+///
+/// See the [module docs](self) for an example.
+#[derive(Debug)]
+pub struct IoCompat {
+    socket: WebSocket,
+}
+
+impl IoCompat {
+    pub(crate) fn new(socket: WebSocket) -> Self {
+        Self { socket }
+    }
+
+    /// Get a reference to the underlying socket.
+    pub fn get_ref(&self) -> &WebSocket {
+        &self.socket
+    }
+
+    /// Get a mutable reference to the underlying socket.
+    pub fn get_mut(&mut self) -> &mut WebSocket {
+        &mut self.socket
+    }
+
+    /// Consume `self` and get the underlying socket.
+    pub fn into_inner(self) -> WebSocket {
+        self.socket
+    }
+}
+
+impl Stream for IoCompat {
+    type Item = io::Result<Message>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.socket
+            .poll_next_unpin(cx)
+            .map(|item| item.map(|result| result.map_err(into_io_error)))
+    }
+}
+
+impl Sink<Message> for IoCompat {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_ready_unpin(cx).map_err(into_io_error)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> io::Result<()> {
+        self.socket.start_send_unpin(item).map_err(into_io_error)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_flush_unpin(cx).map_err(into_io_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_close_unpin(cx).map_err(into_io_error)
+    }
+}
+
+/// A [`WebSocket`] used as a byte stream.
+///
+/// Created with [`WebSocket::into_byte_stream`]. Each write is sent as a `Binary` message and
+/// reads return the payloads of received `Binary` messages. `Ping`s and `Pong`s are skipped,
+/// `Text` messages fail with [`io::ErrorKind::InvalidData`], and a `Close` frame or the end of
+/// the connection is read as the end of the stream. Shutting down the writing half sends a
+/// `Close` frame.
+///
+/// Message boundaries aren't preserved, so the protocol spoken over the stream has to do its own
+/// framing.
+///
+/// See the [module docs](self) for an example.
+#[derive(Debug)]
+pub struct ByteStream {
+    socket: WebSocket,
+    /// The payload currently being read and how much of it has been read.
+    read_buf: Vec<u8>,
+    read_pos: usize,
+}
+
+impl ByteStream {
+    pub(crate) fn new(socket: WebSocket) -> Self {
+        Self {
+            socket,
+            read_buf: Vec::new(),
+            read_pos: 0,
+        }
+    }
+
+    /// Get a reference to the underlying socket.
+    pub fn get_ref(&self) -> &WebSocket {
+        &self.socket
+    }
+
+    /// Get a mutable reference to the underlying socket.
+    pub fn get_mut(&mut self) -> &mut WebSocket {
+        &mut self.socket
+    }
+
+    /// Consume `self` and get the underlying socket.
+    ///
+    /// Bytes that were received but not yet read are lost.
+    pub fn into_inner(self) -> WebSocket {
+        self.socket
+    }
+}
+
+impl AsyncRead for ByteStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.read_pos == self.read_buf.len() {
+            match ready!(self.socket.poll_next_unpin(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    self.read_buf = data;
+                    self.read_pos = 0;
+                }
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "received a text message on a byte stream",
+                    )));
+                }
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {}
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Err(err)) => return Poll::Ready(Err(into_io_error(err))),
+            }
+        }
+
+        let this = &mut *self;
+        let remaining = &this.read_buf[this.read_pos..];
+        let len = remaining.len().min(buf.remaining());
+        buf.put_slice(&remaining[..len]);
+        this.read_pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ByteStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(self.socket.poll_ready_unpin(cx)).map_err(into_io_error)?;
+        self.socket
+            .start_send_unpin(Message::Binary(buf.to_vec()))
+            .map_err(into_io_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_flush_unpin(cx).map_err(into_io_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.socket.poll_close_unpin(cx).map_err(into_io_error)
+    }
+}
+
+/// Copy data in both directions between `socket` and `stream` until the connection ends.
+///
+/// The socket is used as a [`ByteStream`]. A `Close` frame from the client shuts down writing to
+/// `stream` and ends the tunnel, since nothing more can be sent to a client after it closed the
+/// connection. The end of `stream` sends a `Close` frame to the client and the tunnel keeps
+/// forwarding the client's data until it answers with its own `Close` frame.
+///
+/// See the [module docs](self) for an example.
+pub async fn tunnel<T>(socket: WebSocket, stream: T) -> io::Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut socket_read, mut socket_write) = split(socket.into_byte_stream());
+    let (mut stream_read, mut stream_write) = split(stream);
+
+    {
+        let from_client = async {
+            copy(&mut socket_read, &mut stream_write).await?;
+            stream_write.shutdown().await
+        };
+        let to_client = async {
+            copy(&mut stream_read, &mut socket_write).await?;
+            socket_write.shutdown().await
+        };
+        tokio::pin!(from_client, to_client);
+
+        let mut stream_done = false;
+        loop {
+            tokio::select! {
+                result = &mut from_client => break result?,
+                result = &mut to_client, if !stream_done => {
+                    result?;
+                    stream_done = true;
+                }
+            }
+        }
+    }
+
+    // write tungstenite's reply to the client's `Close` frame
+    let mut socket = socket_read.unsplit(socket_write);
+    let _ = socket.flush().await;
+    Ok(())
+}
+
+pub(crate) fn into_io_error(err: Error) -> io::Error {
+    match err {
+        Error::Io(err) => err,
+        Error::ConnectionClosed | Error::AlreadyClosed => {
+            io::Error::new(io::ErrorKind::BrokenPipe, err)
+        }
+        err => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+    use tokio::{io::AsyncReadExt, sync::oneshot};
+
+    #[tokio::test]
+    async fn io_compat_maps_errors_to_io_errors() {
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = test_helpers::serve(|socket| async move {
+            let mut transport = socket.into_io_compat();
+            let msg = transport.next().await.unwrap().unwrap();
+            transport.send(msg).await.unwrap();
+
+            assert!(matches!(
+                transport.next().await,
+                Some(Ok(Message::Close(_)))
+            ));
+            assert!(transport.next().await.is_none());
+            let err = transport
+                .send(Message::Text("late".to_owned()))
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            done_tx.send(()).unwrap();
+        })
+        .await;
+
+        client.send(Message::Text("echo".to_owned())).await.unwrap();
+        let msg = client.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Text("echo".to_owned()));
+        client.close(None).await.unwrap();
+        done_rx.await.unwrap();
+    }
+
+    #[test]
+    fn io_errors_are_unwrapped() {
+        let err = into_io_error(Error::Io(io::Error::new(io::ErrorKind::TimedOut, "slow")));
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let err = into_io_error(Error::AlreadyClosed);
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        let err = into_io_error(Error::AttackAttempt);
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[tokio::test]
+    async fn byte_stream_reads_binary_payloads_until_close() {
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = test_helpers::serve(|socket| async move {
+            let mut stream = socket.into_byte_stream();
+            let mut read = Vec::new();
+            stream.read_to_end(&mut read).await.unwrap();
+            assert_eq!(read, b"hello");
+            done_tx.send(()).unwrap();
+        })
+        .await;
+
+        client.send(Message::Binary(b"hel".to_vec())).await.unwrap();
+        client.send(Message::Ping(Vec::new())).await.unwrap();
+        client.send(Message::Binary(b"lo".to_vec())).await.unwrap();
+        client.close(None).await.unwrap();
+        done_rx.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn byte_stream_rejects_text() {
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut client = test_helpers::serve(|socket| async move {
+            let mut stream = socket.into_byte_stream();
+            let err = stream.read(&mut [0; 16]).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            done_tx.send(()).unwrap();
+        })
+        .await;
+
+        client
+            .send(Message::Text("hello".to_owned()))
+            .await
+            .unwrap();
+        done_rx.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn byte_stream_writes_binary_messages_and_closes_on_shutdown() {
+        let mut client = test_helpers::serve(|socket| async move {
+            let mut stream = socket.into_byte_stream();
+            stream.write_all(b"hello").await.unwrap();
+            stream.shutdown().await.unwrap();
+        })
+        .await;
+
+        let msg = client.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Binary(b"hello".to_vec()));
+        let msg = client.next().await.unwrap().unwrap();
+        assert!(msg.is_close());
+    }
+
+    /// Connect a client to a server tunneling the socket to the returned stream.
+    async fn tunneled() -> (
+        tokio_tungstenite::WebSocketStream<tokio::io::DuplexStream>,
+        tokio::io::DuplexStream,
+        oneshot::Receiver<io::Result<()>>,
+    ) {
+        let (backend, gateway) = tokio::io::duplex(1024);
+        let (done_tx, done_rx) = oneshot::channel();
+        let client = test_helpers::serve(|socket| async move {
+            done_tx.send(tunnel(socket, gateway).await).unwrap();
+        })
+        .await;
+        (client, backend, done_rx)
+    }
+
+    #[tokio::test]
+    async fn tunnel_forwards_both_ways_until_the_client_closes() {
+        let (mut client, mut backend, done_rx) = tunneled().await;
+
+        client
+            .send(Message::Binary(b"ping".to_vec()))
+            .await
+            .unwrap();
+        let mut buf = [0; 4];
+        backend.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        backend.write_all(b"pong").await.unwrap();
+        let msg = client.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Binary(b"pong".to_vec()));
+
+        client.close(None).await.unwrap();
+        // the client's close shuts down writing to the backend
+        assert_eq!(backend.read(&mut buf).await.unwrap(), 0);
+        done_rx.await.unwrap().unwrap();
+        assert!(client.next().await.unwrap().unwrap().is_close());
+    }
+
+    #[tokio::test]
+    async fn tunnel_closes_the_socket_when_the_backend_ends() {
+        let (mut client, mut backend, done_rx) = tunneled().await;
+
+        backend.shutdown().await.unwrap();
+        let msg = client.next().await.unwrap().unwrap();
+        assert!(msg.is_close());
+
+        // the tunnel keeps forwarding until the client answers the close frame, which
+        // tungstenite does when flushing
+        client.flush().await.unwrap();
+        done_rx.await.unwrap().unwrap();
+    }
+}
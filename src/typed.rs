@@ -0,0 +1,210 @@
+//! Sockets that send and receive typed messages.
+//!
+//! [`TypedWebSocket`] wraps a [`WebSocket`] and converts messages to and from Rust types with a
+//! [`Codec`]. [`JsonCodec`], the default, uses [`serde_json`].
+//!
+//! Requires the `json` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::response::Response;
+//! use axum_tungstenite::{
+//!     typed::{TypedError, TypedWebSocket},
+//!     WebSocketUpgrade,
+//! };
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Deserialize)]
+//! #[serde(tag = "type")]
+//! enum ClientMsg {
+//!     Join { room: String },
+//!     Say { text: String },
+//! }
+//!
+//! #[derive(Serialize)]
+//! #[serde(tag = "type")]
+//! enum ServerMsg {
+//!     Joined { room: String },
+//!     Error { reason: String },
+//! }
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|socket| async move {
+//!         let mut socket = TypedWebSocket::<ClientMsg, ServerMsg>::new(socket);
+//!         while let Some(msg) = socket.recv().await {
+//!             let reply = match msg {
+//!                 Ok(ClientMsg::Join { room }) => ServerMsg::Joined { room },
+//!                 Ok(ClientMsg::Say { .. }) => continue,
+//!                 Err(TypedError::Decode(err)) => ServerMsg::Error {
+//!                     reason: err.to_string(),
+//!                 },
+//!                 Err(_) => break,
+//!             };
+//!             if socket.send(reply).await.is_err() {
+//!                 break;
+//!             }
+//!         }
+//!     })
+//! }
+//! ```
+
+use crate::{Error, Message, WebSocket};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt, marker::PhantomData};
+
+/// Converts between messages and Rust types.
+pub trait Codec<In, Out> {
+    /// The error returned when decoding or encoding fails.
+    type Error;
+
+    /// Decode a `Text` or `Binary` message.
+    fn decode(&mut self, msg: Message) -> Result<In, Self::Error>;
+
+    /// Encode a value into a message.
+    fn encode(&mut self, value: Out) -> Result<Message, Self::Error>;
+}
+
+/// A [`Codec`] that encodes values as JSON `Text` messages.
+///
+/// Both `Text` and `Binary` messages are decoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl<In, Out> Codec<In, Out> for JsonCodec
+where
+    In: DeserializeOwned,
+    Out: Serialize,
+{
+    type Error = serde_json::Error;
+
+    fn decode(&mut self, msg: Message) -> Result<In, Self::Error> {
+        match msg {
+            Message::Text(text) => serde_json::from_str(&text),
+            msg => serde_json::from_slice(&msg.into_data()),
+        }
+    }
+
+    fn encode(&mut self, value: Out) -> Result<Message, Self::Error> {
+        serde_json::to_string(&value).map(Message::Text)
+    }
+}
+
+/// A [`WebSocket`] that receives `In`s and sends `Out`s.
+///
+/// See the [module docs](self) for an example.
+pub struct TypedWebSocket<In, Out, C = JsonCodec> {
+    socket: WebSocket,
+    codec: C,
+    _marker: PhantomData<fn(Out) -> In>,
+}
+
+impl<In, Out> TypedWebSocket<In, Out> {
+    /// Create a new `TypedWebSocket` using JSON.
+    pub fn new(socket: WebSocket) -> Self {
+        Self::with_codec(socket, JsonCodec)
+    }
+}
+
+impl<In, Out, C> TypedWebSocket<In, Out, C>
+where
+    C: Codec<In, Out>,
+{
+    /// Receive another message.
+    ///
+    /// `Ping`s and `Pong`s are skipped. Returns `None` once the client sends a `Close` frame or
+    /// the stream has closed. Messages that fail to decode are returned as
+    /// [`TypedError::Decode`] and the socket remains usable.
+    pub async fn recv(&mut self) -> Option<Result<In, TypedError<C::Error>>> {
+        loop {
+            match self.socket.recv().await? {
+                Ok(msg @ (Message::Text(_) | Message::Binary(_))) => {
+                    return Some(self.codec.decode(msg).map_err(TypedError::Decode));
+                }
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => {}
+                Err(err) => return Some(Err(TypedError::Socket(err))),
+            }
+        }
+    }
+
+    /// Send a message.
+    pub async fn send(&mut self, value: Out) -> Result<(), TypedError<C::Error>> {
+        let msg = self.codec.encode(value).map_err(TypedError::Encode)?;
+        self.socket.send(msg).await.map_err(TypedError::Socket)
+    }
+}
+
+impl<In, Out, C> TypedWebSocket<In, Out, C> {
+    /// Create a new `TypedWebSocket` using `codec`.
+    pub fn with_codec(socket: WebSocket, codec: C) -> Self {
+        Self {
+            socket,
+            codec,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get a reference to the underlying socket.
+    pub fn get_ref(&self) -> &WebSocket {
+        &self.socket
+    }
+
+    /// Get a mutable reference to the underlying socket.
+    pub fn get_mut(&mut self) -> &mut WebSocket {
+        &mut self.socket
+    }
+
+    /// Consume `self` and get the underlying socket.
+    pub fn into_inner(self) -> WebSocket {
+        self.socket
+    }
+}
+
+impl<In, Out, C> fmt::Debug for TypedWebSocket<In, Out, C>
+where
+    C: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedWebSocket")
+            .field("socket", &self.socket)
+            .field("codec", &self.codec)
+            .finish()
+    }
+}
+
+/// The error returned by [`TypedWebSocket`].
+#[derive(Debug)]
+pub enum TypedError<E> {
+    /// A received message couldn't be decoded.
+    Decode(E),
+    /// A value couldn't be encoded.
+    Encode(E),
+    /// The underlying socket failed.
+    Socket(Error),
+}
+
+impl<E> fmt::Display for TypedError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decode(err) => write!(f, "Failed to decode message: {}", err),
+            Self::Encode(err) => write!(f, "Failed to encode message: {}", err),
+            Self::Socket(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<E> std::error::Error for TypedError<E>
+where
+    E: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode(err) | Self::Encode(err) => Some(err),
+            Self::Socket(err) => Some(err),
+        }
+    }
+}
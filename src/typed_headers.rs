@@ -0,0 +1,158 @@
+//! Typed versions of the handshake headers for use with the [`headers`] crate.
+//!
+//! [`SecWebSocketProtocol`] and [`SecWebSocketExtensions`] implement [`Header`] so they work
+//! with axum's `TypedHeader` extractor and with [`WebSocketUpgrade::typed_header`]. The
+//! [`headers`] crate itself provides `SecWebsocketKey`, `SecWebsocketVersion`, `Upgrade`, and
+//! `Connection`.
+//!
+//! Requires the `headers` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::{http::StatusCode, response::{IntoResponse, Response}};
+//! use axum_tungstenite::{typed_headers::SecWebSocketProtocol, WebSocketUpgrade};
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     let offered = ws.typed_header::<SecWebSocketProtocol>();
+//!     if !offered.is_some_and(|protocols| protocols.contains("graphql-transport-ws")) {
+//!         return StatusCode::BAD_REQUEST.into_response();
+//!     }
+//!
+//!     ws.protocols(["graphql-transport-ws"])
+//!         .on_upgrade(|socket| async { /* ... */ })
+//! }
+//! ```
+//!
+//! [`WebSocketUpgrade::typed_header`]: crate::WebSocketUpgrade::typed_header
+
+use crate::handshake::{self, Extension};
+use headers::{Error, Header, HeaderName, HeaderValue};
+use http::header;
+
+/// The `Sec-WebSocket-Protocol` header.
+///
+/// Multiple headers are combined into a single list, in the order they were sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecWebSocketProtocol(Vec<String>);
+
+impl SecWebSocketProtocol {
+    /// Create a `SecWebSocketProtocol` header listing `protocols`.
+    pub fn new<I>(protocols: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self(protocols.into_iter().map(Into::into).collect())
+    }
+
+    /// The listed protocols.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    /// Returns `true` if `protocol` is listed.
+    pub fn contains(&self, protocol: &str) -> bool {
+        self.iter().any(|listed| listed == protocol)
+    }
+}
+
+impl Header for SecWebSocketProtocol {
+    fn name() -> &'static HeaderName {
+        &header::SEC_WEBSOCKET_PROTOCOL
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let mut protocols = Vec::new();
+        for value in values {
+            let value = value.to_str().map_err(|_| Error::invalid())?;
+            protocols.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|protocol| !protocol.is_empty())
+                    .map(str::to_owned),
+            );
+        }
+
+        if protocols.is_empty() {
+            Err(Error::invalid())
+        } else {
+            Ok(Self(protocols))
+        }
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        if let Ok(value) = HeaderValue::from_str(&self.0.join(", ")) {
+            values.extend(Some(value));
+        }
+    }
+}
+
+/// The `Sec-WebSocket-Extensions` header.
+///
+/// Multiple headers are combined into a single list, in the order they were sent. Extensions
+/// that fail to parse are skipped, like with [`handshake::parse_extensions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecWebSocketExtensions(Vec<Extension>);
+
+impl SecWebSocketExtensions {
+    /// Create a `SecWebSocketExtensions` header listing `extensions`.
+    pub fn new<I>(extensions: I) -> Self
+    where
+        I: IntoIterator<Item = Extension>,
+    {
+        Self(extensions.into_iter().collect())
+    }
+
+    /// The listed extensions.
+    pub fn iter(&self) -> impl Iterator<Item = &Extension> {
+        self.0.iter()
+    }
+
+    /// Get the extension called `name`.
+    pub fn get(&self, name: &str) -> Option<&Extension> {
+        self.iter().find(|extension| extension.name() == name)
+    }
+}
+
+impl Header for SecWebSocketExtensions {
+    fn name() -> &'static HeaderName {
+        &header::SEC_WEBSOCKET_EXTENSIONS
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let extensions = handshake::parse_extension_values(values);
+
+        if extensions.is_empty() {
+            Err(Error::invalid())
+        } else {
+            Ok(Self(extensions))
+        }
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = self
+            .0
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            values.extend(Some(value));
+        }
+    }
+}
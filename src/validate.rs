@@ -0,0 +1,135 @@
+//! Validation of inbound JSON messages.
+//!
+//! [`WebSocket::validate_json`](crate::WebSocket::validate_json) checks every `Text` and
+//! `Binary` message before it is returned by the socket. Messages that aren't valid JSON or are
+//! rejected by the validator never reach the handler. Instead the client either gets an error
+//! reply or the connection is closed with `1007 Invalid frame payload data`, depending on
+//! [`OnInvalid`].
+//!
+//! The validator is a closure, so any schema validation library can be plugged in.
+//!
+//! Requires the `json` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use axum::response::Response;
+//! use axum_tungstenite::{validate::OnInvalid, WebSocketUpgrade};
+//! use serde_json::Value;
+//!
+//! fn has_type(msg: &Value) -> Result<(), String> {
+//!     match msg.get("type") {
+//!         Some(Value::String(_)) => Ok(()),
+//!         _ => Err("missing `type` field".to_owned()),
+//!     }
+//! }
+//!
+//! async fn handler(ws: WebSocketUpgrade) -> Response {
+//!     ws.on_upgrade(|mut socket| async move {
+//!         socket.validate_json(has_type, OnInvalid::Reply);
+//!         while let Some(Ok(msg)) = socket.recv().await {
+//!             // only messages with a `type` field arrive here
+//!         }
+//!     })
+//! }
+//! ```
+
+use crate::Message;
+use serde_json::{json, Value};
+use std::{fmt, sync::Arc};
+
+/// What happens to messages that fail validation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OnInvalid {
+    /// Skip the message and send the client an error reply.
+    ///
+    /// The reply is a `Text` message like `{"error":"invalid message","reason":"..."}`. It is
+    /// only sent if the socket can accept it without waiting.
+    Reply,
+    /// Close the connection with `1007 Invalid frame payload data`.
+    ///
+    /// Like the error reply, the `Close` frame is only sent if the socket can accept it without
+    /// waiting. The socket returns an [`io::ErrorKind::InvalidData`](std::io::ErrorKind::InvalidData)
+    /// error and then ends.
+    Close,
+}
+
+#[derive(Clone)]
+pub(crate) struct Validator {
+    validate: Arc<dyn Fn(&Value) -> Result<(), String> + Send + Sync>,
+    pub(crate) on_invalid: OnInvalid,
+}
+
+impl Validator {
+    pub(crate) fn new<F>(validate: F, on_invalid: OnInvalid) -> Self
+    where
+        F: Fn(&Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        Self {
+            validate: Arc::new(validate),
+            on_invalid,
+        }
+    }
+
+    /// Returns why `msg` is invalid, if it is.
+    pub(crate) fn check(&self, msg: &Message) -> Result<(), String> {
+        let value = match msg {
+            Message::Text(text) => serde_json::from_str(text),
+            Message::Binary(data) => serde_json::from_slice(data),
+            _ => return Ok(()),
+        };
+        let value = value.map_err(|err| format!("Invalid JSON: {}", err))?;
+        (self.validate)(&value)
+    }
+
+    pub(crate) fn error_reply(reason: &str) -> Message {
+        let reply = json!({
+            "error": "invalid message",
+            "reason": reason,
+        });
+        Message::Text(reply.to_string())
+    }
+}
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Validator")
+            .field("on_invalid", &self.on_invalid)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_helpers, WebSocketUpgrade};
+    use axum::{routing::get, Router};
+    use futures_util::{SinkExt, StreamExt};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn replying_to_an_invalid_message_returns_the_next_one() {
+        let app = Router::new().route(
+            "/",
+            get(|ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|mut socket| async move {
+                    socket.validate_json(|_| Ok(()), OnInvalid::Reply);
+                    // let both messages arrive
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    let msg = socket.try_recv().unwrap().unwrap();
+                    socket.send(msg).await.unwrap();
+                })
+            }),
+        );
+        let mut client = test_helpers::connect(app, 1024).await;
+
+        client.send(Message::Text("{".to_owned())).await.unwrap();
+        client.send(Message::Text("{}".to_owned())).await.unwrap();
+
+        let reply = client.next().await.unwrap().unwrap().into_text().unwrap();
+        assert!(reply.contains("invalid message"), "{}", reply);
+        let echoed = client.next().await.unwrap().unwrap();
+        assert_eq!(echoed, Message::Text("{}".to_owned()));
+    }
+}